@@ -1,37 +1,42 @@
 use std::iter::Peekable;
 
-use crate::bx;
-use crate::utils::{consts::COMMANDS, token::*};
+use crate::utils::{consts::COMMANDS, error::Result, token::*};
+use crate::{bx, err};
 
-pub fn construct_tree(stream: &[Node]) -> Vec<Op> {
+pub fn construct_tree(stream: &[(Node, Span)]) -> Result<Vec<Op>> {
     let mut res = Vec::new();
     // Note: using a peekable iterator isn't really necessary yet, but it will be once I implement Node::Punctuation
     let mut stream = stream.iter().peekable();
 
     while let Some(tok) = stream.next() {
-        let op = current_tok(&mut stream, tok);
+        let op = current_tok(&mut stream, tok)?;
         if op != Op::Empty {
             res.push(op);
         }
     }
 
-    res
+    Ok(res)
 }
 
-pub fn current_tok<'a>(stream: &mut Peekable<impl Iterator<Item = &'a Node>>, cur: &Node) -> Op {
-    match *cur {
+pub fn current_tok<'a>(
+    stream: &mut Peekable<impl Iterator<Item = &'a (Node, Span)>>,
+    cur: &(Node, Span),
+) -> Result<Op> {
+    let (ref node, span) = *cur;
+
+    Ok(match *node {
         Node::Keyword(ref name) => {
             if let Some(&count) = COMMANDS.get(name) {
                 let mut v = Vec::with_capacity(count);
 
                 while v.len() < count {
                     if let Some(n) = stream.next() {
-                        let t = current_tok(stream, n);
+                        let t = current_tok(stream, n)?;
                         if t != Op::Empty {
                             v.push(t);
                         }
                     } else {
-                        panic!(
+                        return err!(at span;
                             "{} takes {} arguments but {} were provided",
                             name,
                             count,
@@ -40,9 +45,9 @@ pub fn current_tok<'a>(stream: &mut Peekable<impl Iterator<Item = &'a Node>>, cu
                     }
                 }
 
-                Op::Cmd(name.clone(), v)
+                Op::Cmd(name.clone(), v, span)
             } else {
-                panic!("Unrecognized command: {}", name);
+                return err!(at span; "Unrecognized command: {}", name);
             }
         }
 
@@ -51,30 +56,31 @@ pub fn current_tok<'a>(stream: &mut Peekable<impl Iterator<Item = &'a Node>>, cu
         // Memory: B[ah + 1], W[ah], DW[eax * 3 + 1]
         Node::Punctuation(ref chr) => {
             if *chr == '#' || *chr == '$' || *chr == '@' {
-                if let Some(Node::Punctuation('[')) = stream.next() {
-                    let tok = stream.next();
-                    let res = bx!(current_tok(
-                        stream,
-                        tok.expect("Invalid termination of a memory identifier: Missing body"),
-                    ));
-
-                    if let Some(Node::Punctuation(']')) = stream.next() {
+                if let Some((Node::Punctuation('['), _)) = stream.next() {
+                    let Some(tok) = stream.next() else {
+                        return err!(at span; "Invalid termination of a memory identifier: Missing body");
+                    };
+                    let res = bx!(current_tok(stream, tok)?);
+
+                    if let Some((Node::Punctuation(']'), _)) = stream.next() {
                         Op::Memory(*chr, res)
                     } else {
-                        panic!("Invalid termination of a memory identifier: Missing ']'")
+                        return err!(at span; "Invalid termination of a memory identifier: Missing ']'");
                     }
                 } else {
-                    panic!("Invalid beginning to a memory identifier: Missing '['")
+                    return err!(at span; "Invalid beginning to a memory identifier: Missing '['");
                 }
             } else if *chr == '[' || *chr == ']' {
-                panic!("Invalid free-standing punctuation '{}'.", chr);
+                return err!(at span; "Invalid free-standing punctuation '{}'.", chr);
             } else {
-                todo!("Math unimplemented");
+                return err!(at span; "Math unimplemented");
             }
         }
 
         Node::Numeric(val) => Op::Numeric(val),
 
+        Node::Float(val) => Op::Float(val),
+
         Node::String(ref str) => Op::String(str.clone()),
 
         Node::Branch(ref name) => {
@@ -85,11 +91,14 @@ pub fn current_tok<'a>(stream: &mut Peekable<impl Iterator<Item = &'a Node>>, cu
             } else {
                 let mut v = Vec::new();
                 while let Some(node) = stream.next() {
-                    if Node::Branch(String::from('.')) == *node {
+                    if Node::Branch(String::from('.')) == node.0 {
                         break;
                     }
 
-                    v.push(current_tok(stream, node));
+                    let op = current_tok(stream, node)?;
+                    if op != Op::Empty {
+                        v.push(op);
+                    }
                 }
 
                 Op::Branch(name, v)
@@ -100,7 +109,9 @@ pub fn current_tok<'a>(stream: &mut Peekable<impl Iterator<Item = &'a Node>>, cu
 
         Node::Char(ref chr) => Op::Char(*chr),
 
+        Node::Comment(_) => Op::Empty,
+
         #[cfg(feature = "inline-python")]
         Node::InlinePy(ref val) => Op::InlinePy(val.clone()),
-    }
+    })
 }
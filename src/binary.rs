@@ -0,0 +1,698 @@
+use std::convert::{TryFrom, TryInto};
+
+use crate::err;
+use crate::utils::{
+    consts::{COMMANDS, OPCODES},
+    error::Result,
+    token::{Op, Span},
+    traits::{register, FloatRegister},
+};
+
+// Every compiled program starts with these bytes, followed by the version
+pub const MAGIC: &[u8; 4] = b"ARSM";
+pub const VERSION: u16 = 1;
+
+// Set in the header's flags when the program carries a line info section
+const FLAG_LINES: u16 = 1;
+
+// Section tags. Each section is a tag, followed by its length as a `u32` and
+// its contents
+pub const SECTION_CODE: u8 = 1;
+pub const SECTION_DATA: u8 = 2;
+pub const SECTION_SYMBOLS: u8 = 3;
+pub const SECTION_LINES: u8 = 4;
+
+// Op tags inside of the code section
+pub const OP_CMD: u8 = 1;
+pub const OP_BRANCH: u8 = 2;
+pub const OP_LABEL: u8 = 3;
+pub const OP_MEMORY: u8 = 4;
+pub const OP_REGISTER: u8 = 5;
+pub const OP_NUMERIC: u8 = 6;
+pub const OP_STRING: u8 = 7;
+pub const OP_CHAR: u8 = 8;
+pub const OP_INLINE_PY: u8 = 9;
+pub const OP_FLOAT: u8 = 10;
+
+// How deeply ops may be nested inside of each other (branches, operands and
+// memory addresses), so corrupt code can't overflow the stack while decoding
+pub const MAX_DEPTH: usize = 256;
+
+// A named branch, referenced by index from branches and labels
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Symbol {
+    // Without the leading `.` or `:`
+    pub name: String,
+
+    // The index of the top-level branch with this name, if there is one
+    pub branch: Option<u32>,
+}
+
+// A program assembled into its binary form. All integers are little endian.
+//
+// The code section holds the top-level ops, each encoded as its tag followed by
+// its fields:
+//   cmd      - opcode (`u8`, index into `OPCODES`), then its operands
+//   branch   - symbol (`u32`), number of ops (`u32`), then the ops
+//   label    - symbol (`u32`)
+//   memory   - identifier (`u8`, one of `#$@`), then the address op
+//   register - name length (`u8`), then the name
+//   numeric  - value (`i32`)
+//   float    - value (`f64`)
+//   string   - offset (`u32`) and length (`u32`) of the text in the data section
+//   char     - code point (`u32`)
+// The line info section holds the line and column (`u32` each) of every
+// command, in the order they appear in the code section
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Program {
+    pub code: Vec<u8>,
+    pub data: Vec<u8>,
+    pub symbols: Vec<Symbol>,
+    pub lines: Option<Vec<Span>>,
+}
+
+// Whether `bytes` look like a compiled program rather than source
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn len_u32(len: usize) -> Result<u32> {
+    match u32::try_from(len) {
+        Ok(len) => Ok(len),
+        Err(_) => err!("Program is too large to compile"),
+    }
+}
+
+impl Program {
+    // Assembles `tree`, recording where each command came from if `lines` is set
+    pub fn from_tree(tree: &[Op], lines: bool) -> Result<Self> {
+        let mut res = Self {
+            lines: if lines { Some(Vec::new()) } else { None },
+            ..Self::default()
+        };
+
+        for (ind, op) in tree.iter().enumerate() {
+            if let Op::Branch(name, _) = op {
+                let sym = res.symbol(&name[1..])?;
+                let branch = &mut res.symbols[sym as usize].branch;
+                // Jumps go to the first branch with a name
+                if branch.is_none() {
+                    *branch = Some(len_u32(ind)?);
+                }
+            }
+        }
+
+        let mut code = Vec::new();
+        code.extend_from_slice(&len_u32(tree.len())?.to_le_bytes());
+        for op in tree {
+            res.encode(op, &mut code)?;
+        }
+        res.code = code;
+
+        Ok(res)
+    }
+
+    fn symbol(&mut self, name: &str) -> Result<u32> {
+        if let Some(ind) = self.symbols.iter().position(|sym| sym.name == name) {
+            return len_u32(ind);
+        }
+        if u16::try_from(name.len()).is_err() {
+            return err!(
+                "Branch name is too long to compile: {}...",
+                name.chars().take(16).collect::<String>()
+            );
+        }
+
+        self.symbols.push(Symbol {
+            name: name.to_owned(),
+            branch: None,
+        });
+        len_u32(self.symbols.len() - 1)
+    }
+
+    fn data(&mut self, text: &str, code: &mut Vec<u8>) -> Result<()> {
+        code.extend_from_slice(&len_u32(self.data.len())?.to_le_bytes());
+        code.extend_from_slice(&len_u32(text.len())?.to_le_bytes());
+        self.data.extend_from_slice(text.as_bytes());
+        Ok(())
+    }
+
+    fn encode(&mut self, op: &Op, code: &mut Vec<u8>) -> Result<()> {
+        match op {
+            Op::Cmd(name, args, span) => {
+                let Some(opcode) = OPCODES.iter().position(|cmd| cmd == name) else {
+                    return err!(at *span; "Unrecognized command: {}", name);
+                };
+
+                code.push(OP_CMD);
+                code.push(u8::try_from(opcode).unwrap());
+                if let Some(lines) = &mut self.lines {
+                    lines.push(*span);
+                }
+                for arg in args {
+                    self.encode(arg, code)?;
+                }
+            }
+
+            Op::Branch(name, body) => {
+                let sym = self.symbol(&name[1..])?;
+                code.push(OP_BRANCH);
+                code.extend_from_slice(&sym.to_le_bytes());
+                code.extend_from_slice(&len_u32(body.len())?.to_le_bytes());
+                for op in body {
+                    self.encode(op, code)?;
+                }
+            }
+
+            Op::Label(name) => {
+                let sym = self.symbol(&name[1..])?;
+                code.push(OP_LABEL);
+                code.extend_from_slice(&sym.to_le_bytes());
+            }
+
+            Op::Memory(ident, op) => {
+                code.push(OP_MEMORY);
+                code.push(*ident as u8);
+                self.encode(op, code)?;
+            }
+
+            Op::Register(name) => {
+                code.push(OP_REGISTER);
+                code.push(u8::try_from(name.len()).unwrap_or(u8::MAX));
+                code.extend_from_slice(name.as_bytes());
+            }
+
+            Op::Numeric(val) => {
+                code.push(OP_NUMERIC);
+                code.extend_from_slice(&val.to_le_bytes());
+            }
+
+            Op::Float(val) => {
+                code.push(OP_FLOAT);
+                code.extend_from_slice(&val.to_le_bytes());
+            }
+
+            Op::String(text) => {
+                code.push(OP_STRING);
+                self.data(text, code)?;
+            }
+
+            Op::Char(chr) => {
+                code.push(OP_CHAR);
+                code.extend_from_slice(&u32::from(*chr).to_le_bytes());
+            }
+
+            #[cfg(feature = "inline-python")]
+            Op::InlinePy(text) => {
+                code.push(OP_INLINE_PY);
+                self.data(text, code)?;
+            }
+
+            _ => return err!("Cannot compile {:?}", op),
+        }
+
+        Ok(())
+    }
+
+    // Sizes were already checked to fit while assembling
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn section(res: &mut Vec<u8>, tag: u8, body: &[u8]) {
+            res.push(tag);
+            res.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            res.extend_from_slice(body);
+        }
+
+        let mut res = MAGIC.to_vec();
+        res.extend_from_slice(&VERSION.to_le_bytes());
+        let flags = if self.lines.is_some() { FLAG_LINES } else { 0 };
+        res.extend_from_slice(&flags.to_le_bytes());
+
+        section(&mut res, SECTION_CODE, &self.code);
+        section(&mut res, SECTION_DATA, &self.data);
+
+        let mut symbols = (self.symbols.len() as u32).to_le_bytes().to_vec();
+        for sym in &self.symbols {
+            symbols.extend_from_slice(&(sym.name.len() as u16).to_le_bytes());
+            symbols.extend_from_slice(sym.name.as_bytes());
+            symbols.extend_from_slice(&sym.branch.unwrap_or(u32::MAX).to_le_bytes());
+        }
+        section(&mut res, SECTION_SYMBOLS, &symbols);
+
+        if let Some(lines) = &self.lines {
+            let mut body = Vec::with_capacity(lines.len() * 8);
+            for span in lines {
+                body.extend_from_slice(&(span.line as u32).to_le_bytes());
+                body.extend_from_slice(&(span.col as u32).to_le_bytes());
+            }
+            section(&mut res, SECTION_LINES, &body);
+        }
+
+        res
+    }
+
+    // Reads a compiled program, checking its header and that every section is
+    // present and well formed
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !is_binary(bytes) {
+            return err!("Not a compiled arsm program: bad magic number");
+        }
+
+        let mut header = Reader::new(&bytes[MAGIC.len()..], "header");
+        let version = header.u16()?;
+        if version != VERSION {
+            return err!(
+                "Unsupported binary version {}, this build of arsm only runs version {}",
+                version,
+                VERSION
+            );
+        }
+        let flags = header.u16()?;
+
+        let mut res = Self::default();
+        let (mut code, mut data, mut symbols) = (None, None, None);
+        let mut rest = Reader::new(header.rest(), "section table");
+        while !rest.is_empty() {
+            let tag = rest.u8()?;
+            let len = rest.u32()? as usize;
+            let body = rest.bytes(len)?;
+
+            let slot = match tag {
+                SECTION_CODE => &mut code,
+                SECTION_DATA => &mut data,
+                SECTION_SYMBOLS => &mut symbols,
+                SECTION_LINES => {
+                    let mut reader = Reader::new(body, "line info");
+                    let mut lines = Vec::with_capacity(len / 8);
+                    while !reader.is_empty() {
+                        lines.push(Span {
+                            line: reader.u32()? as usize,
+                            col: reader.u32()? as usize,
+                        });
+                    }
+                    res.lines = Some(lines);
+                    continue;
+                }
+                _ => return err!("Corrupt binary: unknown section {}", tag),
+            };
+
+            if slot.replace(body).is_some() {
+                return err!("Corrupt binary: duplicate section {}", tag);
+            }
+        }
+
+        let (Some(code), Some(data), Some(symbols)) = (code, data, symbols) else {
+            return err!("Corrupt binary: missing a section");
+        };
+        if (flags & FLAG_LINES != 0) != res.lines.is_some() {
+            return err!("Corrupt binary: line info doesn't match the header");
+        }
+
+        let mut reader = Reader::new(symbols, "symbol table");
+        for _ in 0..reader.u32()? {
+            let len = reader.u16()? as usize;
+            let Ok(name) = String::from_utf8(reader.bytes(len)?.to_vec()) else {
+                return err!("Corrupt binary: symbol is not valid UTF-8");
+            };
+            let branch = Some(reader.u32()?).filter(|&ind| ind != u32::MAX);
+            res.symbols.push(Symbol { name, branch });
+        }
+        if !reader.is_empty() {
+            return err!("Corrupt binary: trailing bytes in the symbol table");
+        }
+
+        res.code = code.to_vec();
+        res.data = data.to_vec();
+        Ok(res)
+    }
+
+    // Rebuilds the tree the program was assembled from. Commands keep their
+    // location if the program has line info, and get the default span if not
+    pub fn to_tree(&self) -> Result<Vec<Op>> {
+        let mut decoder = Decoder {
+            program: self,
+            code: Reader::new(&self.code, "code"),
+            cmds: 0,
+            depth: 0,
+        };
+
+        let count = decoder.code.u32()?;
+        let mut res = Vec::new();
+        for _ in 0..count {
+            res.push(decoder.op()?);
+        }
+
+        if !decoder.code.is_empty() {
+            return err!("Corrupt binary: trailing bytes in the code section");
+        }
+        if self
+            .lines
+            .as_ref()
+            .is_some_and(|lines| lines.len() != decoder.cmds)
+        {
+            return err!("Corrupt binary: line info doesn't match the code");
+        }
+
+        Ok(res)
+    }
+
+    pub fn symbol_name(&self, ind: u32) -> Result<&str> {
+        match self.symbols.get(ind as usize) {
+            Some(sym) => Ok(&sym.name),
+            None => err!("Corrupt binary: symbol {} is out of range", ind),
+        }
+    }
+
+    pub fn data_str(&self, offset: u32, len: u32) -> Result<&str> {
+        let range = offset as usize..offset as usize + len as usize;
+        match self.data.get(range).map(std::str::from_utf8) {
+            Some(Ok(text)) => Ok(text),
+            _ => err!("Corrupt binary: invalid string in the data section"),
+        }
+    }
+}
+
+// Reads little endian values from a section, failing once it runs out
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pub pos: usize,
+    section: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8], section: &'static str) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            section,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        match self.bytes.get(self.pos..self.pos + len) {
+            Some(res) => {
+                self.pos += len;
+                Ok(res)
+            }
+            None => err!("Corrupt binary: unexpected end of the {}", self.section),
+        }
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+struct Decoder<'a> {
+    program: &'a Program,
+    code: Reader<'a>,
+
+    // How many commands have been decoded, to find their line info
+    cmds: usize,
+
+    // How many ops the one being decoded is nested in
+    depth: usize,
+}
+
+impl Decoder<'_> {
+    fn op(&mut self) -> Result<Op> {
+        if self.depth == MAX_DEPTH {
+            return err!("Corrupt binary: ops are nested too deeply");
+        }
+
+        self.depth += 1;
+        let res = self.decode();
+        self.depth -= 1;
+        res
+    }
+
+    fn decode(&mut self) -> Result<Op> {
+        Ok(match self.code.u8()? {
+            OP_CMD => {
+                let opcode = self.code.u8()?;
+                let Some(&name) = OPCODES.get(opcode as usize) else {
+                    return err!("Corrupt binary: unknown opcode {}", opcode);
+                };
+
+                let span = match &self.program.lines {
+                    Some(lines) => lines.get(self.cmds).copied().unwrap_or_default(),
+                    None => Span::default(),
+                };
+                self.cmds += 1;
+
+                let mut args = Vec::with_capacity(COMMANDS[name]);
+                for _ in 0..COMMANDS[name] {
+                    args.push(self.op()?);
+                }
+                Op::Cmd(name.to_owned(), args, span)
+            }
+
+            OP_BRANCH => {
+                let name = self.program.symbol_name(self.code.u32()?)?;
+                let len = self.code.u32()?;
+                let mut body = Vec::new();
+                for _ in 0..len {
+                    body.push(self.op()?);
+                }
+                Op::Branch(format!(".{name}"), body)
+            }
+
+            OP_LABEL => Op::Label(format!(":{}", self.program.symbol_name(self.code.u32()?)?)),
+
+            OP_MEMORY => {
+                let ident = self.code.u8()? as char;
+                if !['#', '$', '@'].contains(&ident) {
+                    return err!("Corrupt binary: invalid memory identifier {:?}", ident);
+                }
+                Op::Memory(ident, Box::new(self.op()?))
+            }
+
+            OP_REGISTER => {
+                let len = self.code.u8()? as usize;
+                match std::str::from_utf8(self.code.bytes(len)?) {
+                    Ok(name)
+                        if register(name).is_some() || FloatRegister::parse(name).is_some() =>
+                    {
+                        Op::Register(name.to_owned())
+                    }
+                    _ => return err!("Corrupt binary: invalid register name"),
+                }
+            }
+
+            OP_NUMERIC => Op::Numeric(self.code.i32()?),
+
+            OP_FLOAT => Op::Float(self.code.f64()?),
+
+            OP_STRING => {
+                let (offset, len) = (self.code.u32()?, self.code.u32()?);
+                Op::String(self.program.data_str(offset, len)?.to_owned())
+            }
+
+            OP_CHAR => match char::from_u32(self.code.u32()?) {
+                Some(chr) => Op::Char(chr),
+                None => return err!("Corrupt binary: invalid character"),
+            },
+
+            #[cfg(feature = "inline-python")]
+            OP_INLINE_PY => {
+                let (offset, len) = (self.code.u32()?, self.code.u32()?);
+                Op::InlinePy(self.program.data_str(offset, len)?.to_owned())
+            }
+
+            #[cfg(not(feature = "inline-python"))]
+            OP_INLINE_PY => {
+                return err!("Cannot run inline python code when the feature is disabled")
+            }
+
+            tag => return err!("Corrupt binary: unknown op {}", tag),
+        })
+    }
+}
+
+#[cfg(test)]
+mod binary_tests {
+    use super::*;
+    use crate::{ast, lexer};
+
+    const PROGRAM: &str = "str \"Hi\" 10\n.loop\n    chr #[eh]\n    inc eh\n    ceq db 0 10 eh\n    cjm :skip\n    jmp :loop\n..skip\n    out 'A\n    fmov f3 0.1\n    hlt 3";
+
+    fn tree(program: &str) -> Vec<Op> {
+        ast::construct_tree(&lexer::lex_spanned(program).unwrap()).unwrap()
+    }
+
+    fn build(program: &str, lines: bool) -> Vec<u8> {
+        Program::from_tree(&tree(program), lines)
+            .unwrap()
+            .to_bytes()
+    }
+
+    fn load(bytes: &[u8]) -> Result<Vec<Op>> {
+        Program::from_bytes(bytes)?.to_tree()
+    }
+
+    #[test]
+    fn test_opcodes() {
+        assert_eq!(OPCODES.len(), COMMANDS.len());
+        assert!(OPCODES.iter().all(|cmd| COMMANDS.contains_key(*cmd)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(load(&build(PROGRAM, true)).unwrap(), tree(PROGRAM));
+    }
+
+    // Forgets where every command came from
+    fn strip(op: Op) -> Op {
+        match op {
+            Op::Cmd(name, args, _) => {
+                Op::Cmd(name, args.into_iter().map(strip).collect(), Span::default())
+            }
+            Op::Branch(name, body) => Op::Branch(name, body.into_iter().map(strip).collect()),
+            op => op,
+        }
+    }
+
+    #[test]
+    fn test_without_lines() {
+        let bytes = build(PROGRAM, false);
+
+        assert!(bytes.len() < build(PROGRAM, true).len());
+        assert_eq!(
+            load(&bytes).unwrap(),
+            tree(PROGRAM).into_iter().map(strip).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        let program = Program::from_bytes(&build(PROGRAM, false)).unwrap();
+        assert_eq!(
+            program.symbols,
+            vec![
+                Symbol {
+                    name: "loop".to_string(),
+                    branch: Some(1)
+                },
+                Symbol {
+                    name: "skip".to_string(),
+                    branch: Some(2)
+                }
+            ]
+        );
+        assert_eq!(program.data, b"Hi");
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        assert_eq!(
+            load(b"ELF\x7f").unwrap_err().msg,
+            "Not a compiled arsm program: bad magic number"
+        );
+    }
+
+    #[test]
+    fn test_bad_version() {
+        let mut bytes = build(PROGRAM, false);
+        bytes[4] = 9;
+
+        assert_eq!(
+            load(&bytes).unwrap_err().msg,
+            "Unsupported binary version 9, this build of arsm only runs version 1"
+        );
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let bytes = build(PROGRAM, true);
+        for len in MAGIC.len()..bytes.len() {
+            let err = load(&bytes[..len]).unwrap_err();
+            assert!(err.msg.starts_with("Corrupt binary"), "{}", err.msg);
+        }
+
+        // Point the first command at an opcode that doesn't exist
+        let mut bytes = build("out 1", false);
+        let code = MAGIC.len() + 4 + 5 + 4;
+        assert_eq!(bytes[code], OP_CMD);
+        bytes[code + 1] = 255;
+        assert_eq!(
+            load(&bytes).unwrap_err().msg,
+            "Corrupt binary: unknown opcode 255"
+        );
+    }
+
+    #[test]
+    fn test_registers() {
+        let program = Program::from_tree(&tree("out ax"), false).unwrap();
+        let name = program.code.len() - 3;
+        assert_eq!(program.code[name..], [2, b'a', b'x']);
+
+        // A name that isn't a register, then an empty one
+        let mut bad = program.clone();
+        bad.code[name + 1] = b'z';
+        let mut empty = program;
+        empty.code.truncate(name);
+        empty.code.push(0);
+
+        for program in [bad, empty] {
+            assert_eq!(
+                load(&program.to_bytes()).unwrap_err().msg,
+                "Corrupt binary: invalid register name"
+            );
+        }
+    }
+
+    #[test]
+    fn test_nesting() {
+        // `out #[#[#[...]]]`, nested far deeper than the stack could decode
+        let mut code = 1_u32.to_le_bytes().to_vec();
+        code.extend_from_slice(&[
+            OP_CMD,
+            u8::try_from(OPCODES.iter().position(|&name| name == "out").unwrap()).unwrap(),
+        ]);
+        for _ in 0..100_000 {
+            code.extend_from_slice(&[OP_MEMORY, b'#']);
+        }
+        code.push(OP_NUMERIC);
+        code.extend_from_slice(&0_i32.to_le_bytes());
+
+        let program = Program {
+            code,
+            ..Program::default()
+        };
+        assert_eq!(
+            load(&program.to_bytes()).unwrap_err().msg,
+            "Corrupt binary: ops are nested too deeply"
+        );
+
+        // Programs nested less deeply still load
+        let mut program = String::from("out ");
+        program.push_str(&"#[".repeat(MAX_DEPTH - 2));
+        program.push('0');
+        program.push_str(&"]".repeat(MAX_DEPTH - 2));
+        assert!(load(&build(&program, false)).is_ok());
+    }
+}
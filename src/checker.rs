@@ -0,0 +1,512 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::utils::{
+    consts::{FLOAT_COMMANDS, POINTERS},
+    token::{float_text, Op, Span},
+    traits::FloatRegister,
+};
+use crate::{ast, lexer};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+// A problem found without running the program
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub msg: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    fn error(span: Span, msg: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            msg: msg.into(),
+            span,
+        }
+    }
+
+    fn warning(span: Span, msg: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            msg: msg.into(),
+            span,
+        }
+    }
+}
+
+// The width in bits of the value an op reads or writes, if it is known
+// statically
+pub(crate) fn width(op: &Op) -> Option<u32> {
+    match op {
+        Op::Register(name) if FloatRegister::parse(name).is_some() => None,
+        Op::Register(name) if name.len() == 3 => Some(32),
+        Op::Register(name) if name.ends_with('x') || POINTERS.contains(&name.as_str()) => Some(16),
+        Op::Register(_) | Op::Memory('#', _) => Some(8),
+        Op::Memory('$', _) => Some(16),
+        Op::Memory('@', _) => Some(32),
+        _ => None,
+    }
+}
+
+// Whether `val` can be stored in something `bits` wide, mirroring the casts the
+// parser performs (8-bit values are unsigned, wider ones are signed)
+fn fits(val: i32, bits: u32) -> bool {
+    match bits {
+        8 => u8::try_from(val).is_ok(),
+        16 => i16::try_from(val).is_ok(),
+        _ => true,
+    }
+}
+
+// Why `op` can't be read as an integer, if it is a float or a float is used as
+// an address inside of it. Float operands may be floats themselves, but their
+// addresses can't be
+fn float_use(op: &Op, is_float: bool) -> Option<String> {
+    match op {
+        Op::Float(val) if !is_float => Some(format!(
+            "Expected an integer, but {} is a float",
+            float_text(*val)
+        )),
+        Op::Register(name) if !is_float && FloatRegister::parse(name).is_some() => Some(format!(
+            "Expected an integer, but {name} is a float register"
+        )),
+        Op::Memory(_, addr) => float_use(addr, false),
+        _ => None,
+    }
+}
+
+// The location of the first command in `op`, if there is one
+fn first_span(op: &Op) -> Option<Span> {
+    match op {
+        Op::Cmd(_, _, span) => Some(*span),
+        Op::Branch(_, body) => body.iter().find_map(first_span),
+        _ => None,
+    }
+}
+
+fn is_cmd(op: &Op, names: &[&str]) -> bool {
+    matches!(op, Op::Cmd(name, ..) if names.contains(&&**name))
+}
+
+// Calls `f` on every label in `op`, including those nested in operands
+fn labels<'a>(op: &'a Op, f: &mut impl FnMut(&'a str)) {
+    match op {
+        Op::Label(name) => f(&name[1..]),
+        Op::Cmd(_, args, _) => args.iter().for_each(|arg| labels(arg, f)),
+        Op::Branch(_, body) => body.iter().for_each(|op| labels(op, f)),
+        Op::Memory(_, op) => labels(op, f),
+        _ => {}
+    }
+}
+
+struct Checker<'a> {
+    tree: &'a [Op],
+    branches: HashSet<&'a str>,
+    targets: HashSet<&'a str>,
+    res: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn cmd(&mut self, op: &Op, top_level: bool) {
+        let Op::Cmd(name, args, span) = op else {
+            return;
+        };
+        let span = *span;
+
+        let mut undefined = Vec::new();
+        labels(op, &mut |label| {
+            if !self.branches.contains(label) {
+                undefined.push(label.to_owned());
+            }
+        });
+        for label in undefined {
+            self.res.push(Diagnostic::error(
+                span,
+                format!("No matching branch for label :{label}"),
+            ));
+        }
+
+        let floats = FLOAT_COMMANDS
+            .iter()
+            .find(|(cmd, _)| cmd == name)
+            .map(|(_, floats)| *floats);
+        for (i, arg) in args.iter().enumerate() {
+            let is_float = floats.is_some_and(|floats| floats[i]);
+            if let Some(msg) = float_use(arg, is_float) {
+                self.res.push(Diagnostic::error(span, msg));
+            }
+        }
+        let writes_float = matches!(&**name, "fmov" | "fadd" | "fsub" | "fmul" | "fdiv" | "fld");
+        if writes_float
+            && !matches!(&args[0], Op::Register(reg) if FloatRegister::parse(reg).is_some())
+        {
+            self.res.push(Diagnostic::error(
+                span,
+                "Expected a float register to write to",
+            ));
+        }
+
+        match &**name {
+            "psh" | "cps" => {
+                if let Op::Numeric(size) = args[0] {
+                    if ![1, 2, 4].contains(&size) {
+                        self.res.push(Diagnostic::error(
+                            span,
+                            format!(
+                                "Cannot push a {size} byte number to the stack, only 1, 2 or 4"
+                            ),
+                        ));
+                    }
+                }
+            }
+
+            "sts" | "stf" => {
+                if let Op::Numeric(size) = args[0] {
+                    if ![1, 2, 4].contains(&size) {
+                        self.res.push(Diagnostic::error(
+                            span,
+                            format!(
+                                "Cannot store a {size} byte number on the stack, only 1, 2 or 4"
+                            ),
+                        ));
+                    }
+                }
+            }
+
+            "mov" | "cmo" => {
+                if let Some(dest) = width(&args[0]) {
+                    match &args[1] {
+                        Op::Numeric(val) if !fits(*val, dest) => {
+                            self.res.push(Diagnostic::error(
+                                span,
+                                format!("{val} does not fit into {dest} bits"),
+                            ));
+                        }
+                        Op::Char(chr) if !fits(*chr as i32, dest) => {
+                            self.res.push(Diagnostic::error(
+                                span,
+                                format!("'{chr} does not fit into {dest} bits"),
+                            ));
+                        }
+                        src => {
+                            if let Some(src) = width(src).filter(|&src| src > dest) {
+                                self.res.push(Diagnostic::warning(
+                                    span,
+                                    format!("Writing a {src}-bit value into {dest} bits may truncate it"),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+
+            "ret" | "cre" if top_level => {
+                self.res.push(Diagnostic::error(
+                    span,
+                    "`ret` outside of a branch can never return",
+                ));
+            }
+
+            _ => {}
+        }
+
+        // Nested commands, like the `db` in `ceq db 0 10 eh`
+        for arg in args {
+            self.cmd(arg, false);
+        }
+    }
+
+    // Checks a sequence of ops, either the top-level or the body of a branch
+    fn body(&mut self, ops: &[Op], top_level: bool) {
+        let mut halted = false;
+
+        for op in ops {
+            // Top-level branches can still be jumped to after a `hlt`
+            if top_level && matches!(op, Op::Branch(..)) {
+                halted = false;
+            }
+
+            if halted {
+                if let Some(span) = first_span(op) {
+                    self.res
+                        .push(Diagnostic::warning(span, "Unreachable code after `hlt`"));
+                }
+                // Only the first unreachable op is reported
+                halted = false;
+            }
+
+            match op {
+                Op::Cmd(..) => {
+                    self.cmd(op, top_level);
+                    halted = is_cmd(op, &["hlt"]);
+                }
+                Op::Branch(_, body) => self.body(body, false),
+                _ => {}
+            }
+        }
+    }
+
+    // Whether something can `jmp` into the top-level branch at `ind`, either
+    // directly or by falling through from the branches before it
+    fn has_caller(&self, mut ind: usize) -> bool {
+        loop {
+            let Op::Branch(name, _) = &self.tree[ind] else {
+                return false;
+            };
+
+            if self.targets.contains(&name[1..]) {
+                return true;
+            }
+
+            match ind.checked_sub(1).map(|i| &self.tree[i]) {
+                Some(Op::Branch(_, body))
+                    if !body
+                        .last()
+                        .is_some_and(|op| is_cmd(op, &["jmp", "ret", "hlt"])) =>
+                {
+                    ind -= 1;
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    fn returns(&mut self) {
+        for (ind, op) in self.tree.iter().enumerate() {
+            let Op::Branch(name, body) = op else {
+                continue;
+            };
+
+            let mut rets = Vec::new();
+            find_rets(body, &mut rets);
+            if !rets.is_empty() && !self.has_caller(ind) {
+                for span in rets {
+                    self.res.push(Diagnostic::warning(
+                        span,
+                        format!(
+                            "`ret` has no possible caller, nothing jumps to :{}",
+                            &name[1..]
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn find_rets(ops: &[Op], res: &mut Vec<Span>) {
+    for op in ops {
+        match op {
+            Op::Cmd(name, _, span) if name == "ret" || name == "cre" => res.push(*span),
+            Op::Branch(_, body) => find_rets(body, res),
+            _ => {}
+        }
+    }
+}
+
+// Looks for mistakes in `tree` that would otherwise only be found at runtime
+pub fn check_tree(tree: &[Op]) -> Vec<Diagnostic> {
+    let branches = tree
+        .iter()
+        .filter_map(|op| match op {
+            Op::Branch(name, _) => Some(&name[1..]),
+            _ => None,
+        })
+        .collect();
+
+    let mut targets = HashSet::new();
+    for op in tree {
+        labels(op, &mut |label| {
+            targets.insert(label);
+        });
+    }
+
+    let mut checker = Checker {
+        tree,
+        branches,
+        targets,
+        res: Vec::new(),
+    };
+    checker.body(tree, true);
+    checker.returns();
+
+    let mut res = checker.res;
+    res.sort_by_key(|d| (d.span.line, d.span.col));
+    res
+}
+
+// Lexes and builds `program`, then checks it. Errors lexing or building the
+// tree are reported as diagnostics too
+pub fn check(program: &str) -> Vec<Diagnostic> {
+    match lexer::lex_spanned(program).and_then(|nodes| ast::construct_tree(&nodes)) {
+        Ok(tree) => check_tree(&tree),
+        Err(e) => vec![Diagnostic::error(e.span.unwrap_or_default(), e.msg)],
+    }
+}
+
+#[cfg(test)]
+mod checker_tests {
+    use super::*;
+
+    fn messages(program: &str) -> Vec<(Severity, usize, String)> {
+        check(program)
+            .into_iter()
+            .map(|d| (d.severity, d.span.line, d.msg))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean() {
+        assert!(check("str \"Hi\" 10\n.loop\n    chr #[eh]\n    inc eh\n    ceq db 0 10 eh\n    cjm :skip\n    jmp :loop\n..skip").is_empty());
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(
+            messages(".a\n    jmp :b\n    ceq 1 1\n    cjm :a"),
+            vec![(
+                Severity::Error,
+                2,
+                "No matching branch for label :b".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_psh() {
+        assert_eq!(
+            messages("stk 4\npsh 3 ax\npsh 4 ax\nsts 8 0 ax\nstf 2 0 ax"),
+            vec![
+                (
+                    Severity::Error,
+                    2,
+                    "Cannot push a 3 byte number to the stack, only 1, 2 or 4".to_string()
+                ),
+                (
+                    Severity::Error,
+                    4,
+                    "Cannot store a 8 byte number on the stack, only 1, 2 or 4".to_string()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_widths() {
+        assert_eq!(
+            messages("mov al 300\nmov ah abx\nmov ax bh\nmov #[0] $[1]"),
+            vec![
+                (
+                    Severity::Error,
+                    1,
+                    "300 does not fit into 8 bits".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    2,
+                    "Writing a 32-bit value into 8 bits may truncate it".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    4,
+                    "Writing a 16-bit value into 8 bits may truncate it".to_string()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(
+            messages(
+                "fmov f0 1.5\nfadd f0 ax\nmov ax f0\nfmov ax 2\nout #[f1]\nftoi f2 2.5\nfout 0.5 2"
+            ),
+            vec![
+                (
+                    Severity::Error,
+                    3,
+                    "Expected an integer, but f0 is a float register".to_string()
+                ),
+                (
+                    Severity::Error,
+                    4,
+                    "Expected a float register to write to".to_string()
+                ),
+                (
+                    Severity::Error,
+                    5,
+                    "Expected an integer, but f1 is a float register".to_string()
+                ),
+                (
+                    Severity::Error,
+                    6,
+                    "Expected an integer, but f2 is a float register".to_string()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreachable() {
+        assert_eq!(
+            messages("out 1\nhlt 0\nout 2\nout 3\n.a\n    hlt 1\n    out 4\n."),
+            vec![
+                (
+                    Severity::Warning,
+                    3,
+                    "Unreachable code after `hlt`".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    7,
+                    "Unreachable code after `hlt`".to_string()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_returns() {
+        assert_eq!(
+            messages(
+                "ret\n.f\n    cz ax\n    cre\n..g\n    ret\n..h\n    hlt 0\n..i\n    ret\n.\njmp :f"
+            ),
+            vec![
+                (
+                    Severity::Error,
+                    1,
+                    "`ret` outside of a branch can never return".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    10,
+                    "`ret` has no possible caller, nothing jumps to :i".to_string()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tree_errors() {
+        assert_eq!(
+            messages("out 1\nfoo 2"),
+            vec![(Severity::Error, 2, "Unrecognized command: foo".to_string())]
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use std::convert::TryFrom;
+use std::io::{BufRead, Write};
+
+use crate::parser;
+use crate::utils::{
+    error::{Error, Result},
+    token::{float_text, Op},
+    traits::FloatRegister,
+    watch::{Target, Watch},
+};
+use crate::{bx, Environment};
+
+const HELP: &str = "step [N]     Run the next N commands (1 by default)
+back [N]     Undo the last N commands (1 by default)
+continue     Run until the program stops
+last <T>     Go back to right before T was last written to
+who <T>      Show the command that last wrote to T
+print <T>    Show the value of T
+where        Show the command that runs next
+watch <T>    Pause after T is written to
+rwatch <T>   Pause after T is read
+awatch <T>   Pause after T is read or written to
+unwatch <T>  Stop watching T
+quit         Stop debugging
+T is a register (like ax, ah, abx, fp or f0), `flag`, `stack`, or memory (like #[3], $[3], @[3] or
+#[3..10] for cells 3 to 9)";
+
+// How the program stopped
+enum Stop {
+    Exited(i32),
+    Failed(Error),
+}
+
+// Runs a program one command at a time, reading what to do from `commands`
+// and reporting to `out`. A history of every command that ran is kept, so it
+// can step backwards as well as forwards. `source` is used to show commands
+// as they were written, if the program is source code
+pub struct Debugger<'a> {
+    ast: &'a [Op],
+    source: Option<&'a str>,
+    env: &'a mut Environment,
+    stop: Option<Stop>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(ast: &'a [Op], source: Option<&'a str>, env: &'a mut Environment) -> Self {
+        env.record_history();
+        env.break_on_watch = true;
+        Self {
+            ast,
+            source,
+            env,
+            stop: None,
+        }
+    }
+
+    // Debugs until told to quit or `commands` run out, returning the exit code
+    // the program stopped with so far, or 1 if it failed
+    pub fn run(&mut self, commands: impl BufRead, out: &mut dyn Write) -> Result<i32> {
+        self.show_where(out)?;
+        write!(out, "> ")?;
+        out.flush()?;
+        for line in commands.lines() {
+            let line = line?;
+            let mut words = line.split_whitespace();
+            let cmd = words.next().unwrap_or_default();
+            let arg = words.next();
+            if matches!(cmd, "quit" | "q") {
+                break;
+            }
+            self.command(cmd, arg, out)?;
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+        writeln!(out)?;
+
+        Ok(match &self.stop {
+            Some(Stop::Exited(code)) => *code,
+            Some(Stop::Failed(_)) => 1,
+            None => 0,
+        })
+    }
+
+    fn command(&mut self, cmd: &str, arg: Option<&str>, out: &mut dyn Write) -> Result<()> {
+        match cmd {
+            "" => {}
+            "step" | "s" | "back" | "b" => {
+                let Some(n) = arg.map_or(Some(1), |n| n.parse::<u64>().ok()) else {
+                    writeln!(out, "Invalid number: {}", arg.unwrap_or_default())?;
+                    return Ok(());
+                };
+                if cmd.starts_with('s') {
+                    self.forward(Some(n), out)?;
+                } else {
+                    for _ in 0..n {
+                        if !self.back() {
+                            break;
+                        }
+                    }
+                }
+                self.show_where(out)?;
+            }
+            "continue" | "c" => {
+                self.forward(None, out)?;
+                self.show_where(out)?;
+            }
+            "where" | "w" => self.show_where(out)?,
+            "last" | "who" | "print" | "p" | "watch" | "rwatch" | "awatch" | "unwatch" => {
+                let Some(target) = arg.and_then(Target::parse) else {
+                    writeln!(
+                        out,
+                        "Expected a register, `flag`, `stack` or memory, like #[3]"
+                    )?;
+                    return Ok(());
+                };
+                match cmd {
+                    "last" => self.last(&target, out)?,
+                    "who" => self.who(&target, out)?,
+                    "print" | "p" => self.print(&target, out)?,
+                    "unwatch" => {
+                        let name = arg.unwrap_or_default().to_lowercase();
+                        self.env.mem.watches.retain(|watch| watch.name != name);
+                    }
+                    _ => {
+                        let (reads, writes) = (cmd != "watch", cmd != "rwatch");
+                        let watch = Watch::parse(arg.unwrap_or_default(), reads, writes);
+                        self.env.mem.watches.extend(watch);
+                    }
+                }
+            }
+            "help" | "h" => writeln!(out, "{HELP}")?,
+            _ => writeln!(out, "Unknown command: {cmd}. Try `help`")?,
+        }
+        Ok(())
+    }
+
+    // Runs `steps` commands, or until the program stops
+    fn forward(&mut self, steps: Option<u64>, out: &mut dyn Write) -> Result<()> {
+        if self.stop.is_some() {
+            writeln!(out, "The program already stopped")?;
+            return Ok(());
+        }
+
+        self.env.step_limit = steps.map(|steps| self.env.steps.saturating_add(steps));
+        let res = parser::resume(self.ast, self.env);
+        self.env.step_limit = None;
+        let hits = self.env.mem.take_hits();
+        if let Some(frame) = self.env.history.as_ref().and_then(|history| history.last()) {
+            let (step, pos) = (frame.step, frame.pos);
+            for hit in hits {
+                write!(out, "{} was {} by ", hit.name, hit.access)?;
+                self.show_command(step, pos, out)?;
+            }
+        }
+        match res {
+            Ok(code) if self.env.exit.is_some() || self.env.pos.0 >= self.ast.len() => {
+                self.stop = Some(Stop::Exited(code));
+            }
+            Ok(_) => {}
+            Err(e) => self.stop = Some(Stop::Failed(e)),
+        }
+        Ok(())
+    }
+
+    fn back(&mut self) -> bool {
+        let stepped = self.env.step_back();
+        if stepped {
+            self.stop = None;
+        }
+        stepped
+    }
+
+    // Steps back to right before the last command that wrote to `target`
+    fn last(&mut self, target: &Target, out: &mut dyn Write) -> Result<()> {
+        let Some(frame) = self.last_write(target) else {
+            writeln!(out, "It wasn't written to")?;
+            return Ok(());
+        };
+        while self.env.history.as_ref().map_or(0, Vec::len) > frame {
+            self.back();
+        }
+        self.show_where(out)
+    }
+
+    fn who(&mut self, target: &Target, out: &mut dyn Write) -> Result<()> {
+        let Some(frame) = self.last_write(target) else {
+            writeln!(out, "It wasn't written to")?;
+            return Ok(());
+        };
+        let frame = &self.env.history.as_ref().unwrap()[frame];
+        let (step, pos) = (frame.step, frame.pos);
+        write!(out, "Written by ")?;
+        self.show_command(step, pos, out)
+    }
+
+    fn print(&mut self, target: &Target, out: &mut dyn Write) -> Result<()> {
+        let locations = target.locations(&self.env.mem);
+        // Reading memory isn't checked, unlike writing it
+        if locations.iter().any(|loc| loc.end > self.env.mem.size) {
+            return Ok(writeln!(out, "Out of bounds")?);
+        }
+
+        let op = match target {
+            Target::Register(name) => {
+                if let Some(reg) = FloatRegister::parse(name) {
+                    let val = self.env.mem.f_read(reg);
+                    self.env.mem.take_hits();
+                    return Ok(writeln!(out, "{}", float_text(val))?);
+                }
+                Op::Register(name.clone())
+            }
+            Target::Memory(ident, pos) => {
+                let pos = i32::try_from(*pos).unwrap_or(i32::MAX);
+                Op::Memory(*ident, bx!(Op::Numeric(pos)))
+            }
+            // Shown as their bytes, which for the stack are only those on it
+            Target::Flag | Target::Stack | Target::Cells(_) => {
+                let mut range = locations[0].clone();
+                if *target == Target::Stack {
+                    range.end = range.start + self.env.mem.s_len;
+                }
+                let bytes: Vec<String> = self.env.mem.as_slice()[range]
+                    .iter()
+                    .map(u8::to_string)
+                    .collect();
+                return Ok(writeln!(out, "{}", bytes.join(" "))?);
+            }
+        };
+
+        match parser::to_numeric::<i32>(self.env, self.ast, &op) {
+            Ok(val) => writeln!(out, "{val}")?,
+            Err(e) => writeln!(out, "{}", e.msg)?,
+        }
+        // Looking isn't something the program did
+        self.env.mem.take_hits();
+        Ok(())
+    }
+
+    // The index in the history of the last command that wrote to `target`
+    fn last_write(&self, target: &Target) -> Option<usize> {
+        let locs = target.locations(&self.env.mem);
+        let change = self
+            .env
+            .mem
+            .log
+            .as_ref()?
+            .iter()
+            .rposition(|change| locs.iter().any(|loc| loc.contains(&change.loc)))?;
+        let history = self.env.history.as_ref()?;
+        Some(history.partition_point(|frame| frame.changes <= change) - 1)
+    }
+
+    fn show_where(&self, out: &mut dyn Write) -> Result<()> {
+        match &self.stop {
+            Some(Stop::Exited(code)) => {
+                writeln!(out, "The program stopped with exit code {code}")?;
+            }
+            Some(Stop::Failed(e)) => match e.span {
+                Some(span) => writeln!(out, "The program failed at {span}: {}", e.msg)?,
+                None => writeln!(out, "The program failed: {}", e.msg)?,
+            },
+            None => self.show_command(self.env.steps, self.env.pos, out)?,
+        }
+        Ok(())
+    }
+
+    // Shows the command at `pos`, which was (or will be) command number `step` + 1
+    fn show_command(&self, step: u64, pos: (usize, usize), out: &mut dyn Write) -> Result<()> {
+        let op = match self.ast.get(pos.0) {
+            Some(Op::Branch(_, body)) => body.get(pos.1),
+            op => op,
+        };
+        let Some(Op::Cmd(name, _, span)) = op else {
+            writeln!(out, "#{} at the end of a branch", step + 1)?;
+            return Ok(());
+        };
+
+        let line = self
+            .source
+            .and_then(|source| source.lines().nth(span.line.wrapping_sub(1)))
+            .map_or(name.as_str(), str::trim);
+        writeln!(out, "#{} {span}: {line}", step + 1)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod debugger_tests {
+    use super::*;
+    use crate::utils::{input::Input, output::Capture};
+
+    const PROGRAM: &str = "mov ax 1
+mov #[2] in
+.loop
+    inc ax
+    cne ax 4
+    cjm :loop
+    mov bx #[2]
+out ax";
+
+    // Debugs `PROGRAM` with the debugger commands `commands`, returning what
+    // the debugger and the program printed
+    fn debug(commands: &str) -> (String, String) {
+        let tree = crate::compile(PROGRAM).unwrap();
+        let out = Capture::default();
+        let mut env = Environment::new();
+        env.stdin = Input::from(b"ab".to_vec());
+        env.out = Box::new(out.clone());
+
+        let mut res = Vec::new();
+        Debugger::new(&tree, Some(PROGRAM), &mut env)
+            .run(commands.as_bytes(), &mut res)
+            .unwrap();
+        (String::from_utf8(res).unwrap(), out.text())
+    }
+
+    #[test]
+    fn test_step() {
+        let (res, out) = debug("s 2\np #[2]\nc\nb\np ax\nq\n");
+        assert_eq!(
+            res,
+            "#1 1:1: mov ax 1
+> #3 4:5: inc ax
+> 97
+> The program stopped with exit code 0
+> #13 8:1: out ax
+> 4
+> \n"
+        );
+        assert_eq!(out, "4");
+    }
+
+    #[test]
+    fn test_back() {
+        // Stepping back gives input back to the program
+        let (res, _) = debug("s 2\nb 2\ns 2\np #[2]\nb 100\nw\n");
+        assert!(res.ends_with("> 97\n> #1 1:1: mov ax 1\n> #1 1:1: mov ax 1\n> \n"));
+    }
+
+    #[test]
+    fn test_last_write() {
+        let (res, _) = debug("c\nwho ax\nwho #[2]\nlast al\np ax\nwho cx\nlast #[5]\n");
+        assert_eq!(
+            res,
+            "#1 1:1: mov ax 1
+> The program stopped with exit code 0
+> Written by #9 4:5: inc ax
+> Written by #2 2:1: mov #[2] in
+> #9 4:5: inc ax
+> 3
+> It wasn't written to
+> It wasn't written to
+> \n"
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let (res, _) = debug("watch bx\nrwatch #[2..4]\nc\nc\nunwatch bx\nb\nc\n");
+        assert_eq!(
+            res,
+            "#1 1:1: mov ax 1\n> > > #[2..4] was read by #12 7:5: mov bx #[2]\n\
+             bx was written to by #12 7:5: mov bx #[2]\n#13 8:1: out ax\n\
+             > The program stopped with exit code 0\n> > #13 8:1: out ax\n\
+             > The program stopped with exit code 0\n> \n"
+        );
+    }
+
+    #[test]
+    fn test_floats() {
+        let (res, _) = debug("p f0\nwatch f1\n");
+        assert!(res.contains("> 0.0\n> "));
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let (res, _) = debug(
+            "p @[1002]
+p #[1003]
+",
+        );
+        assert!(res.contains(
+            "> Out of bounds
+> 0
+"
+        ));
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt::Write;
+
+use crate::binary::*;
+use crate::err;
+use crate::utils::{
+    consts::{COMMANDS, OPCODES},
+    error::Result,
+    token::{float_text, Span},
+};
+
+const INDENT: &str = "    ";
+
+// A line of the listing: the source text, and where it was encoded
+struct Line {
+    text: String,
+    addr: Option<usize>,
+    span: Option<Span>,
+    hex: Vec<String>,
+}
+
+// An operand's source text, and its encoding as hex
+type Operand = (String, String);
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut res, byte| {
+        let _ = write!(res, "{byte:02x}");
+        res
+    })
+}
+
+struct Disassembler<'a> {
+    program: &'a Program,
+    code: Reader<'a>,
+    lines: Vec<Line>,
+
+    // How many commands have been read, to find their line info
+    cmds: usize,
+}
+
+impl Disassembler<'_> {
+    // The bytes read since `start`, as hex
+    fn since(&self, start: usize) -> String {
+        hex(&self.program.code[start..self.code.pos])
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        let start = self.code.pos;
+        let text = match self.code.u8()? {
+            OP_CMD => {
+                let (name, args, _) = self.cmd()?;
+                let mut res = name.to_owned();
+                for (arg, _) in args {
+                    res.push(' ');
+                    res.push_str(&arg);
+                }
+                res
+            }
+
+            OP_LABEL => format!(":{}", self.program.symbol_name(self.code.u32()?)?),
+
+            OP_MEMORY => {
+                let ident = self.code.u8()? as char;
+                let (addr, _) = self.operand()?;
+                format!("{ident}[{addr}]")
+            }
+
+            OP_REGISTER => {
+                let len = self.code.u8()? as usize;
+                String::from_utf8_lossy(self.code.bytes(len)?).into_owned()
+            }
+
+            OP_NUMERIC => self.code.i32()?.to_string(),
+
+            OP_FLOAT => float_text(self.code.f64()?),
+
+            OP_STRING => {
+                let (offset, len) = (self.code.u32()?, self.code.u32()?);
+                format!("\"{}\"", self.program.data_str(offset, len)?)
+            }
+
+            OP_CHAR => match char::from_u32(self.code.u32()?) {
+                Some(chr) => format!("'{chr}"),
+                None => return err!("Corrupt binary: invalid character"),
+            },
+
+            OP_INLINE_PY => {
+                let (offset, len) = (self.code.u32()?, self.code.u32()?);
+                format!("{{{}}}", self.program.data_str(offset, len)?)
+            }
+
+            tag => return err!("Corrupt binary: unexpected op {} in an operand", tag),
+        };
+
+        Ok((text, self.since(start)))
+    }
+
+    // Reads the rest of a command, after its tag
+    fn cmd(&mut self) -> Result<(&'static str, Vec<Operand>, Option<Span>)> {
+        let opcode = self.code.u8()?;
+        let Some(&name) = OPCODES.get(opcode as usize) else {
+            return err!("Corrupt binary: unknown opcode {}", opcode);
+        };
+
+        let span = self
+            .program
+            .lines
+            .as_ref()
+            .and_then(|lines| lines.get(self.cmds).copied());
+        self.cmds += 1;
+
+        let mut args = Vec::new();
+        for _ in 0..COMMANDS[name] {
+            args.push(self.operand()?);
+        }
+        Ok((name, args, span))
+    }
+
+    fn op(&mut self, depth: usize) -> Result<()> {
+        let start = self.code.pos;
+        let indent = INDENT.repeat(depth);
+
+        match self.code.u8()? {
+            OP_CMD => {
+                let (name, args, span) = self.cmd()?;
+                let mut text = format!("{indent}{name}");
+                let mut encoding = vec![hex(&self.program.code[start..start + 2])];
+                for (arg, arg_hex) in args {
+                    text.push(' ');
+                    text.push_str(&arg);
+                    encoding.push(arg_hex);
+                }
+
+                self.lines.push(Line {
+                    text,
+                    addr: Some(start),
+                    span,
+                    hex: encoding,
+                });
+            }
+
+            OP_BRANCH => {
+                let name = self.program.symbol_name(self.code.u32()?)?;
+                let len = self.code.u32()?;
+                self.lines.push(Line {
+                    text: format!("{indent}.{name}"),
+                    addr: Some(start),
+                    span: None,
+                    hex: vec![self.since(start)],
+                });
+
+                for _ in 0..len {
+                    self.op(depth + 1)?;
+                }
+                self.lines.push(Line {
+                    text: format!("{indent}."),
+                    addr: None,
+                    span: None,
+                    hex: Vec::new(),
+                });
+            }
+
+            OP_INLINE_PY => {
+                self.code.pos = start;
+                let (text, encoding) = self.operand()?;
+                self.lines.push(Line {
+                    text: format!("{indent}{text}"),
+                    addr: Some(start),
+                    span: None,
+                    hex: vec![encoding],
+                });
+            }
+
+            tag => return err!("Corrupt binary: unexpected op {} at the top level", tag),
+        }
+
+        Ok(())
+    }
+}
+
+// Prints `program` as source, with the address (its offset into the code
+// section), source location and encoding of every op in a trailing comment.
+// The result assembles back into the same program
+pub fn disassemble(program: &Program) -> Result<String> {
+    // Make sure the whole program is well formed before printing any of it
+    program.to_tree()?;
+
+    let mut disasm = Disassembler {
+        program,
+        code: Reader::new(&program.code, "code"),
+        lines: Vec::new(),
+        cmds: 0,
+    };
+    for _ in 0..disasm.code.u32()? {
+        disasm.op(0)?;
+    }
+
+    let mut res = format!(
+        "; arsm binary version {}, {} symbols, {} bytes of code, {} bytes of data{}\n",
+        VERSION,
+        program.symbols.len(),
+        program.code.len(),
+        program.data.len(),
+        if program.lines.is_some() {
+            ", with line info"
+        } else {
+            ""
+        }
+    );
+
+    let width = disasm
+        .lines
+        .iter()
+        .map(|line| line.text.chars().count())
+        .max()
+        .unwrap_or(0);
+    for line in disasm.lines {
+        res.push_str(&line.text);
+        if let Some(addr) = line.addr {
+            let pad = width - line.text.chars().count();
+            let _ = write!(res, "{:pad$} ; {addr:04x}", "");
+            if let Some(span) = line.span {
+                let _ = write!(res, " ({span})");
+            }
+            let _ = write!(res, ": {}", line.hex.join(" "));
+        }
+        res.push('\n');
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod disasm_tests {
+    use super::*;
+
+    const PROGRAM: &str = "str \"Hi\" 10\n.loop\n    chr #[eh]\n    inc eh\n    ceq db 0 10 eh\n    cjm :skip\n    jmp :loop\n..skip\n    out 'A\n    fmov f3 0.1\n    hlt 3";
+
+    fn build(program: &str, lines: bool) -> Program {
+        Program::from_tree(&crate::compile(program).unwrap(), lines).unwrap()
+    }
+
+    #[test]
+    fn test_listing() {
+        let listing = disassemble(&build("out 1\n.f\n    mov ax :f", true)).unwrap();
+        assert_eq!(
+            listing,
+            "; arsm binary version 1, 1 symbols, 31 bytes of code, 0 bytes of data, with line info\n\
+             out 1         ; 0004 (1:1): 0106 0601000000\n\
+             .f            ; 000b: 020000000001000000\n    \
+                 mov ax :f ; 0014 (3:5): 0100 05026178 0300000000\n\
+             .\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for lines in [false, true] {
+            let program = build(PROGRAM, lines);
+            let listing = disassemble(&program).unwrap();
+
+            assert_eq!(
+                build(&listing, false).to_bytes(),
+                build(PROGRAM, false).to_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut program = build(PROGRAM, false);
+        program.code.truncate(program.code.len() - 1);
+
+        assert!(disassemble(&program).is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::ops::Range;
+
+use serde_json::{json, Map, Value};
+
+use crate::utils::{
+    consts::{
+        FLOAT_REGISTER_COUNT, FRAME_POINTER, OFFSET, REGISTER_NAMES as REGISTERS, REGISTRY_OFFSET,
+    },
+    mem::Memory,
+    token::float_text,
+    traits::{FloatRegister, Num},
+};
+
+// Dumps of memory, as printed by `-d`. Each part of memory is shown for what
+// it is: the registers by name in every width, the flags decoded, the stack
+// as far as it is filled, and the cells as a hexdump. Bytes are read straight
+// from memory, so dumping doesn't set off any watches
+const HEX_WIDTH: usize = 16;
+
+// Every way a register can be read, by name, as the program would read it
+fn registers(mem: &Memory) -> Vec<(String, i64)> {
+    let bytes = mem.as_slice();
+    let reg = |r: usize| [bytes[r * 2], bytes[r * 2 + 1]];
+
+    let fp = [bytes[FRAME_POINTER], bytes[FRAME_POINTER + 1]];
+    let mut res = vec![
+        ("sp".to_owned(), i64::try_from(mem.sp()).unwrap_or(i64::MAX)),
+        ("fp".to_owned(), i64::from(i16::from_ne_bytes(fp))),
+    ];
+    for (r, name) in REGISTERS.chars().enumerate() {
+        let [low, high] = reg(r);
+        res.push((
+            format!("{name}x"),
+            i64::from(i16::from_ne_bytes([low, high])),
+        ));
+        res.push((format!("{name}h"), i64::from(high)));
+        res.push((format!("{name}l"), i64::from(low)));
+    }
+    for (r1, name1) in REGISTERS.chars().enumerate() {
+        for (r2, name2) in REGISTERS.chars().enumerate().filter(|&(r2, _)| r2 != r1) {
+            let ([a, b], [c, d]) = (reg(r1), reg(r2));
+            res.push((
+                format!("{name1}{name2}x"),
+                i64::from(i32::from_ne_bytes([a, b, c, d])),
+            ));
+        }
+    }
+    res
+}
+
+// The float registers, by name
+fn floats(mem: &Memory) -> Vec<(String, f64)> {
+    (0..FLOAT_REGISTER_COUNT)
+        .map(|i| {
+            let start = FloatRegister(i).offset();
+            let val = f64::from_bytes(&mem.as_slice()[start..start + 8]);
+            (format!("f{i}"), val)
+        })
+        .collect()
+}
+
+// The cells in `cells`, cut down to those that exist
+fn cell_bytes<'a>(mem: &'a Memory, cells: &Range<usize>) -> (usize, &'a [u8]) {
+    let all = &mem.as_slice()[OFFSET + mem.s_size..];
+    let start = cells.start.min(all.len());
+    (start, &all[start..cells.end.clamp(start, all.len())])
+}
+
+// A dump to be read, showing the cells in `cells`
+pub fn text(mem: &Memory, cells: &Range<usize>) -> String {
+    let mut res = String::from("Registers:\n");
+    let registers = registers(mem);
+    // The pointers come first, then the 16 and 8 bit views of each register,
+    // and then the pairs
+    let (pointers, registers) = registers.split_at(2);
+    let (single, pairs) = registers.split_at(REGISTERS.len() * 3);
+    for line in std::iter::once(pointers)
+        .chain(single.chunks(3))
+        .chain(pairs.chunks(4))
+    {
+        let entries: Vec<String> = line
+            .iter()
+            .map(|(name, val)| format!("{name:>3} = {val:<11}"))
+            .collect();
+        let _ = writeln!(res, "  {}", entries.join(" ").trim_end());
+    }
+    res.push_str("Float registers:\n");
+    for line in floats(mem).chunks(4) {
+        let entries: Vec<String> = line
+            .iter()
+            .map(|(name, val)| format!("{name:>3} = {:<11}", float_text(*val)))
+            .collect();
+        let _ = writeln!(res, "  {}", entries.join(" ").trim_end());
+    }
+
+    let flag = mem.as_slice()[REGISTRY_OFFSET];
+    let _ = writeln!(
+        res,
+        "Flags:\n  conditional = {}",
+        if flag == 0 { "reset" } else { "set" }
+    );
+
+    let stack = &mem.as_slice()[OFFSET..OFFSET + mem.s_len];
+    let _ = writeln!(res, "Stack ({} of {} bytes):", mem.s_len, mem.s_size);
+    for line in stack.chunks(HEX_WIDTH) {
+        let _ = writeln!(res, "  {}", hex_line(line));
+    }
+
+    let _ = writeln!(res, "Heap ({} cells):", mem.heap.len);
+    for (start, block) in &mem.heap.blocks {
+        let _ = writeln!(
+            res,
+            "  {start}..{} {}",
+            start + block.len,
+            block.state.name()
+        );
+    }
+
+    let (start, bytes) = cell_bytes(mem, cells);
+    let _ = writeln!(res, "Cells {start}..{}:", start + bytes.len());
+    let mut last: Option<&[u8]> = None;
+    let mut skipped = false;
+    for (i, line) in bytes.chunks(HEX_WIDTH).enumerate() {
+        // Runs of the same line, usually zeroes, are shown once
+        if last == Some(line) && line.len() == HEX_WIDTH {
+            if !skipped {
+                res.push_str("  *\n");
+                skipped = true;
+            }
+            continue;
+        }
+        last = Some(line);
+        skipped = false;
+
+        let ascii: String = line
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    char::from(byte)
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(
+            res,
+            "  {:06}  {:<width$}  |{ascii}|",
+            start + i * HEX_WIDTH,
+            hex_line(line),
+            width = HEX_WIDTH * 3 - 1
+        );
+    }
+    res
+}
+
+// The same dump as JSON, for tools to read
+pub fn json(mem: &Memory, cells: &Range<usize>) -> Value {
+    let registers: Map<String, Value> = registers(mem)
+        .into_iter()
+        .map(|(name, val)| (name, json!(val)))
+        .collect();
+    let floats: Map<String, Value> = floats(mem)
+        .into_iter()
+        .map(|(name, val)| (name, json!(val)))
+        .collect();
+    let (start, bytes) = cell_bytes(mem, cells);
+    let blocks: Vec<Value> = mem
+        .heap
+        .blocks
+        .iter()
+        .map(|(start, block)| json!([start, block.len, block.state.name()]))
+        .collect();
+
+    json!({
+        "registers": registers,
+        "floats": floats,
+        "flags": { "conditional": mem.as_slice()[REGISTRY_OFFSET] != 0 },
+        "stack": {
+            "len": mem.s_len,
+            "size": mem.s_size,
+            "bytes": &mem.as_slice()[OFFSET..OFFSET + mem.s_len],
+        },
+        "heap": { "len": mem.heap.len, "blocks": blocks },
+        "cells": { "start": start, "bytes": bytes },
+    })
+}
+
+fn hex_line(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    hex.join(" ")
+}
+
+#[cfg(test)]
+mod dump_tests {
+    use super::*;
+
+    fn memory() -> Memory {
+        let mut mem = Memory::init(OFFSET + 4 + 40, 4);
+        mem.r_write(&'a', &258_i16);
+        mem.r_write(&'b', &-1_i16);
+        mem.flag_write_cmp();
+        mem.s_push(&7_u8).unwrap();
+        for (i, &byte) in b"hi!".iter().enumerate() {
+            mem.m_write(i, &byte).unwrap();
+        }
+        mem.alloc(2);
+        mem.f_write(FloatRegister(5), 2.5);
+        mem
+    }
+
+    #[test]
+    fn test_registers() {
+        let registers = registers(&memory());
+        let get = |name: &str| registers.iter().find(|(n, _)| n == name).unwrap().1;
+        assert_eq!(registers.len(), 2 + 8 * 3 + 8 * 7);
+        assert_eq!(get("sp"), 1);
+        assert_eq!(get("ax"), 258);
+        assert_eq!(get("ah"), 1);
+        assert_eq!(get("al"), 2);
+        assert_eq!(get("bx"), -1);
+        assert_eq!(get("abx"), -65278);
+        assert_eq!(get("bax"), 0x0102_ffff);
+    }
+
+    #[test]
+    fn test_text() {
+        let dump = text(&memory(), &(0..usize::MAX));
+        assert!(dump.contains("Registers:\n   sp = 1            fp = 0\n"));
+        assert!(dump.contains("\n   ax = 258          ah = 1            al = 2\n"));
+        assert!(dump.contains(
+            "Float registers:\n   f0 = 0.0          f1 = 0.0          f2 = 0.0          f3 = 0.0\n   \
+             f4 = 0.0          f5 = 2.5          f6 = 0.0          f7 = 0.0\nFlags:\n  conditional = set\n"
+        ));
+        assert!(dump.contains("Stack (1 of 4 bytes):\n  07\nHeap (2 cells):\n  38..40 used\n"));
+        assert!(dump.contains(
+            "Cells 0..40:\n  000000  68 69 21 00 00 00 00 00 00 00 00 00 00 00 00 00  |hi!.............|\n  \
+             000016  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  |................|\n  \
+             000032  00 00 00 00 00 00 00 00                          |........|\n"
+        ));
+
+        let dump = text(&memory(), &(1..3));
+        assert!(dump.contains("Cells 1..3:\n  000001  69 21    "));
+        assert!(dump.ends_with("  |i!|\n"));
+    }
+
+    #[test]
+    fn test_repeats() {
+        let dump = text(&Memory::init(OFFSET + 64, 0), &(0..64));
+        assert!(dump.ends_with(
+            "Cells 0..64:\n  000000  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  |................|\n  *\n"
+        ));
+    }
+
+    #[test]
+    fn test_json() {
+        let dump = json(&memory(), &(1..100));
+        assert_eq!(dump["registers"]["bh"], 255);
+        assert_eq!(dump["floats"]["f5"], 2.5);
+        assert_eq!(dump["flags"]["conditional"], true);
+        assert_eq!(dump["stack"]["bytes"], json!([7]));
+        assert_eq!(dump["heap"]["blocks"], json!([[38, 2, "used"]]));
+        assert_eq!(dump["cells"]["start"], 1);
+        assert_eq!(dump["cells"]["bytes"].as_array().unwrap().len(), 39);
+    }
+}
@@ -0,0 +1,1222 @@
+use std::fmt::Write;
+
+use crate::checker;
+#[cfg(feature = "inline-python")]
+use crate::err;
+use crate::utils::{
+    consts::{CONDITIONALS, FLOAT_REGISTERS, FRAME_POINTER, MEM_SIZE, OFFSET, REGISTRY_OFFSET},
+    error::Result,
+    token::{Op, Span},
+    traits::{float_register, not_integer, register, Reg},
+};
+
+// Everything the generated program needs besides the ops themselves. It
+// mirrors `utils/mem.rs` byte for byte: registers, then the flags byte, then
+// the stack, and the cell memory after that
+const PRELUDE: &str = r#"#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static uint8_t mem[MEM_SIZE];
+static size_t s_size = 0, s_len = 0;
+
+// Where execution is, as (top-level op, op inside of its branch), and where
+// every `jmp` came from
+static size_t pos0 = 0, pos1 = 0;
+static size_t *jumps = NULL, jumps_len = 0, jumps_cap = 0;
+
+static int halted = 0, exit_code = 0;
+static int main_argc = 0;
+static char **main_argv = NULL;
+static int64_t cmd_val = 0;
+static const char *at = "";
+
+static void fail(const char *msg) {
+    fflush(stdout);
+    fprintf(stderr, "Error: %s: %s\n", at, msg);
+    exit(1);
+}
+
+static void fail_values(const char *msg, int64_t left, int64_t right) {
+    fflush(stdout);
+    fprintf(stderr, "Error: %s: Assertion failed: %s (left: %lld, right: %lld)\n", at, msg,
+            (long long)left, (long long)right);
+    exit(1);
+}
+
+static int64_t as_u8(int64_t v) {
+    if (v < 0 || v > UINT8_MAX) fail("Could not convert value to type <u8>");
+    return v;
+}
+
+static int64_t as_i16(int64_t v) {
+    if (v < INT16_MIN || v > INT16_MAX) fail("Could not convert value to type <i16>");
+    return v;
+}
+
+static int64_t as_i32(int64_t v) {
+    if (v < INT32_MIN || v > INT32_MAX) fail("Could not convert value to type <i32>");
+    return v;
+}
+
+static int64_t as_usize(int64_t v) {
+    if (v < 0) fail("Could not convert value to type <usize>");
+    return v;
+}
+
+static int32_t wrap(int64_t v) {
+    return (int32_t)(uint32_t)(uint64_t)v;
+}
+
+static int32_t checked(int64_t v, const char *msg) {
+    if (v < INT32_MIN || v > INT32_MAX) fail(msg);
+    return (int32_t)v;
+}
+
+static int64_t shift(int64_t r, const char *msg) {
+    if (r < 0 || r > 31) fail(msg);
+    return r;
+}
+
+static int64_t reg8(size_t a) {
+    return mem[a];
+}
+
+static int64_t reg16(size_t a) {
+    return (int16_t)(uint16_t)(mem[a] | mem[a + 1] << 8);
+}
+
+static int64_t reg32(size_t a, size_t b) {
+    return (int32_t)((uint32_t)mem[a] | (uint32_t)mem[a + 1] << 8 | (uint32_t)mem[b] << 16 |
+                     (uint32_t)mem[b + 1] << 24);
+}
+
+static void set_reg8(size_t a, int64_t v) {
+    mem[a] = (uint8_t)v;
+}
+
+static void set_reg16(size_t a, int64_t v) {
+    mem[a] = (uint8_t)v;
+    mem[a + 1] = (uint8_t)(v >> 8);
+}
+
+static void set_reg32(size_t a, size_t b, int64_t v) {
+    mem[a] = (uint8_t)v;
+    mem[a + 1] = (uint8_t)(v >> 8);
+    mem[b] = (uint8_t)(v >> 16);
+    mem[b + 1] = (uint8_t)(v >> 24);
+}
+
+static double freg(size_t i) {
+    double v;
+    memcpy(&v, mem + FLOAT_REGISTERS + 8 * i, 8);
+    return v;
+}
+
+static void set_freg(size_t i, double v) {
+    memcpy(mem + FLOAT_REGISTERS + 8 * i, &v, 8);
+}
+
+// Rounds towards zero, like casting does, but fails instead of overflowing
+static int64_t f_to_i32(double v) {
+    if (!(v > -2147483649.0 && v < 2147483648.0)) fail("Float does not fit into <i32>");
+    return (int64_t)v;
+}
+
+// Prints `v` with `digits` digits after the point, the way Rust formats it
+static void put_float(double v, int64_t digits) {
+    if (isnan(v))
+        fputs("NaN", stdout);
+    else if (isinf(v))
+        fputs(v < 0 ? "-inf" : "inf", stdout);
+    else
+        printf("%.*f", (int)digits, v);
+}
+
+static size_t cell(int64_t pos, size_t len) {
+    size_t start = OFFSET + s_size;
+    if ((uint64_t)pos >= MEM_SIZE || start + (size_t)pos + len > MEM_SIZE)
+        fail("Memory access out of bounds");
+    return start + (size_t)pos;
+}
+
+static int64_t m_read(int64_t pos, size_t len) {
+    size_t a = cell(pos, len);
+    switch (len) {
+        case 1: return mem[a];
+        case 2: return reg16(a);
+        default: return reg32(a, a + 2);
+    }
+}
+
+static void m_write(int64_t pos, size_t len, int64_t v) {
+    size_t a = cell(pos, len);
+    for (size_t i = 0; i < len; i++) mem[a + i] = (uint8_t)(v >> (8 * i));
+}
+
+static double m_read_f64(int64_t pos) {
+    double v;
+    memcpy(&v, mem + cell(pos, 8), 8);
+    return v;
+}
+
+static void m_write_f64(int64_t pos, double v) {
+    memcpy(mem + cell(pos, 8), &v, 8);
+}
+
+static void s_push(size_t len, int64_t v) {
+    if (s_len + len > s_size) fail("The stack is full");
+    for (size_t i = 0; i < len; i++) mem[OFFSET + s_len++] = (uint8_t)(v >> (8 * i));
+}
+
+static void set_sp(int64_t v) {
+    if (v < 0 || (uint64_t)v > s_size) fail("The stack pointer has to stay inside of the stack");
+    if ((size_t)v < s_len) memset(mem + OFFSET + v, 0, s_len - (size_t)v);
+    s_len = (size_t)v;
+}
+
+static int64_t s_pop(size_t len) {
+    if (s_len < len) fail("Attempted to pop from empty stack");
+    s_len -= len;
+    int64_t v = len == 1 ? mem[OFFSET + s_len]
+              : len == 2 ? reg16(OFFSET + s_len)
+                         : reg32(OFFSET + s_len, OFFSET + s_len + 2);
+    memset(mem + OFFSET + s_len, 0, len);
+    return v;
+}
+
+// Where the `len` bytes `pos` bytes above the bottom of the stack are, failing
+// unless all of them are on the stack
+static size_t s_at(int64_t pos, size_t len) {
+    if (pos < 0 || (uint64_t)pos + len > s_len) fail("Stack access out of bounds");
+    return OFFSET + (size_t)pos;
+}
+
+static int64_t s_read(int64_t pos, size_t len) {
+    size_t a = s_at(pos, len);
+    switch (len) {
+        case 1: return mem[a];
+        case 2: return reg16(a);
+        default: return reg32(a, a + 2);
+    }
+}
+
+static void s_write(int64_t pos, size_t len, int64_t v) {
+    size_t a = s_at(pos, len);
+    for (size_t i = 0; i < len; i++) mem[a + i] = (uint8_t)(v >> (8 * i));
+}
+
+static void enter(int64_t len) {
+    if (s_len + 2 + (uint64_t)len > s_size) fail("The stack is full");
+    s_push(2, reg16(FRAME_POINTER));
+    set_reg16(FRAME_POINTER, (int64_t)s_len);
+    set_sp((int64_t)s_len + len);
+}
+
+static void leave(void) {
+    int64_t fp = reg16(FRAME_POINTER);
+    if (fp < 2 || (uint64_t)fp > s_len) fail("There is no frame to leave");
+    set_sp(fp);
+    set_reg16(FRAME_POINTER, s_pop(2));
+}
+
+// The heap, like `utils/heap.rs` keeps it: blocks by where they start in the
+// cell memory, which is how long they are (0 if none starts there) and whether
+// they are free. They cover the last `heap_len` cells
+static size_t heap_len = 0;
+static size_t block_len[MEM_SIZE];
+static uint8_t block_free[MEM_SIZE];
+
+static int64_t heap_alloc(int64_t len) {
+    size_t top = MEM_SIZE - OFFSET - s_size, n = len < 1 ? 1 : (size_t)len;
+    size_t p = top - heap_len;
+    for (; p < top; p += block_len[p]) {
+        if (block_free[p] && block_len[p] >= n) break;
+    }
+    if (p < top) {
+        if (block_len[p] > n) {
+            block_len[p + n] = block_len[p] - n;
+            block_free[p + n] = 1;
+        }
+    } else if (heap_len + n > top) {
+        return -1;
+    } else {
+        heap_len += n;
+        p = top - heap_len;
+    }
+    block_len[p] = n;
+    block_free[p] = 0;
+    memset(mem + OFFSET + s_size + p, 0, n);
+    return (int64_t)p;
+}
+
+static void heap_free(int64_t pos) {
+    size_t top = MEM_SIZE - OFFSET - s_size, prev = top;
+    size_t p = top - heap_len;
+    for (; p < top && p < (uint64_t)pos; p += block_len[p]) prev = p;
+    if (p >= top || p != (uint64_t)pos || block_free[p]) {
+        char msg[80];
+        snprintf(msg, sizeof msg, "Address %lld is not the start of an allocated block",
+                 (long long)pos);
+        fail(msg);
+    }
+
+    size_t len = block_len[p], next = p + len;
+    if (next < top && block_free[next]) {
+        len += block_len[next];
+        block_len[next] = 0;
+    }
+    if (prev < top && block_free[prev]) {
+        block_len[p] = 0;
+        p = prev;
+        len += block_len[prev];
+    }
+
+    // Free space at the bottom of the heap goes back to the cell memory
+    if (p == top - heap_len) {
+        block_len[p] = 0;
+        heap_len -= len;
+    } else {
+        block_len[p] = len;
+        block_free[p] = 1;
+    }
+}
+
+static void resize_stack(int64_t size) {
+    if ((size_t)size != s_size && heap_len)
+        fail("The stack can't be resized while memory is allocated");
+    if ((uint64_t)size > MEM_SIZE - OFFSET) fail("The stack does not fit into memory");
+    if ((size_t)size > s_size) memset(mem + OFFSET + s_size, 0, (size_t)size - s_size);
+    s_size = (size_t)size;
+    if (s_len > s_size) s_len = s_size;
+}
+
+static void put_chr(FILE *f, int64_t v) {
+    if (v < 0x80) {
+        fputc((int)v, f);
+    } else {
+        fputc((int)(0xc0 | v >> 6), f);
+        fputc((int)(0x80 | (v & 0x3f)), f);
+    }
+}
+
+// The next byte of input without reading it, or EOF once there is none left.
+// Input is only read once the program asks for it, like the interpreter does
+static int peek_in(void) {
+    int c = getchar();
+    if (c != EOF) ungetc(c, stdin);
+    return c;
+}
+
+static int64_t read_in(void) {
+    int c = getchar();
+    return c == EOF ? 0 : c;
+}
+
+static int is_space(int64_t c) {
+    return c == ' ' || c == '\t' || c == '\n' || c == '\f' || c == '\r';
+}
+
+static int64_t read_int(void) {
+    while (is_space(peek_in())) getchar();
+    if (peek_in() == EOF) {
+        mem[REGISTRY_OFFSET] = 0;
+        return 0;
+    }
+
+    int neg = 0, digits = 0;
+    int64_t v = 0;
+    if (peek_in() == '-' || peek_in() == '+') neg = getchar() == '-';
+    while (peek_in() >= '0' && peek_in() <= '9') {
+        digits = 1;
+        int c = getchar();
+        if (v <= 2147483648) v = v * 10 + (c - '0');
+    }
+    if (!digits) fail("Expected an integer in the input");
+    if (v > 2147483647LL + neg) fail("Integer in the input does not fit into <i32>");
+
+    mem[REGISTRY_OFFSET] = 1;
+    return neg ? -v : v;
+}
+
+static int64_t read_line(int64_t start, int64_t max) {
+    int64_t len = 0;
+    mem[REGISTRY_OFFSET] = peek_in() != EOF;
+    for (; len < max && peek_in() != EOF && peek_in() != '\n'; len++) m_write(start + len, 1, getchar());
+    if (peek_in() == '\n') getchar();
+    m_write(start + len, 1, 0);
+    return len;
+}
+
+// Prints the lowest `bits` bits of `v` in `base`, with enough zeros in front
+// to make it at least `width` digits long
+static void put_base(int64_t v, int bits, int base, int64_t width) {
+    uint64_t u = (uint64_t)v & ((1ull << bits) - 1);
+    char buf[64];
+    int64_t n = 0;
+    do {
+        buf[n++] = "0123456789abcdef"[u % base];
+        u /= base;
+    } while (u);
+    for (int64_t i = n; i < width; i++) putchar('0');
+    while (n) putchar(buf[--n]);
+}
+
+// Writes at most `max` bytes of `s` into memory at `start`, followed by a
+// null-byte, returning how many were written
+static int64_t write_str(const char *s, int64_t start, int64_t max) {
+    int64_t len = 0;
+    for (; len < max && s[len]; len++) m_write(start + len, 1, (uint8_t)s[len]);
+    m_write(start + len, 1, 0);
+    return len;
+}
+
+static void put_padded(int64_t v, int64_t width) {
+    char buf[24];
+    int64_t n = snprintf(buf, sizeof buf, "%lld", (long long)v);
+    for (int64_t i = n; i < width; i++) putchar(' ');
+    fputs(buf, stdout);
+}
+
+static void set_ind(size_t *ind, int64_t n) {
+    if (jumps_len == jumps_cap) {
+        jumps_cap = jumps_cap ? jumps_cap * 2 : 8;
+        jumps = realloc(jumps, jumps_cap * 2 * sizeof(size_t));
+        if (!jumps) fail("Out of memory");
+    }
+    jumps[2 * jumps_len] = pos0;
+    jumps[2 * jumps_len + 1] = pos1;
+    jumps_len++;
+
+    *ind = (size_t)n;
+    pos0 = *ind;
+    pos1 = 0;
+}
+
+static int ret(size_t *ind) {
+    if (!jumps_len) fail("Cannot return");
+    jumps_len--;
+    size_t left = jumps[2 * jumps_len], right = jumps[2 * jumps_len + 1];
+    if (left >= OPS) fail("Returned past the end of the program");
+
+    if (IS_BRANCH[left]) {
+        pos0 = left;
+        pos1 = right + 1;
+    } else {
+        left++;
+        pos0 = left;
+        pos1 = right;
+    }
+    *ind = left;
+    return 1;
+}
+"#;
+
+// The C types values get converted to before they are used, matching the
+// types the parser converts to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    U8,
+    I16,
+    I32,
+    Usize,
+}
+
+impl Ty {
+    fn cast(self) -> &'static str {
+        match self {
+            Self::U8 => "as_u8",
+            Self::I16 => "as_i16",
+            Self::I32 => "as_i32",
+            Self::Usize => "as_usize",
+        }
+    }
+
+    // How many bytes popping this type takes off the stack
+    fn pop_len(self) -> usize {
+        match self {
+            Self::U8 => 1,
+            Self::I16 => 2,
+            Self::I32 | Self::Usize => 4,
+        }
+    }
+}
+
+// Where the value written by `write` comes from
+enum Src<'a> {
+    Op(&'a Op),
+
+    // A C expression holding an `i32` the command computed
+    Expr(String),
+
+    // Popped off the stack
+    Pop,
+}
+
+// `val` as a C double literal
+fn c_float(val: f64) -> String {
+    if val.is_nan() {
+        "NAN".to_owned()
+    } else if val.is_infinite() {
+        if val < 0.0 { "-INFINITY" } else { "INFINITY" }.to_owned()
+    } else {
+        format!("{val:?}")
+    }
+}
+
+// Escapes `text` as a C string literal
+fn c_str(text: &str) -> String {
+    let mut res = String::from('"');
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                res.push('\\');
+                res.push(byte as char);
+            }
+            b' '..=b'~' => res.push(byte as char),
+            _ => {
+                let _ = write!(res, "\\{byte:03o}");
+            }
+        }
+    }
+    res.push('"');
+    res
+}
+
+struct Gen<'a> {
+    tree: &'a [Op],
+
+    // Every generated function, in the order they have to be declared
+    funcs: Vec<String>,
+    temps: usize,
+}
+
+impl Gen<'_> {
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("t{}", self.temps)
+    }
+
+    fn label(&self, name: &str) -> Option<usize> {
+        self.tree
+            .iter()
+            .position(|op| matches!(op, Op::Branch(n, _) if n[1..] == name[1..]))
+    }
+
+    // Emits the statements that read `op` as a `ty`, returning the variable
+    // holding it
+    fn read(&mut self, body: &mut String, op: &Op, ty: Ty) -> Result<String> {
+        if let Some(msg) = not_integer(op) {
+            return Ok(fail(body, &msg));
+        }
+
+        let raw = match op {
+            Op::Numeric(val) => val.to_string(),
+
+            Op::Char(chr) => (*chr as u8).to_string(),
+
+            Op::Memory(ident, addr) => {
+                let pos = self.read(body, addr, Ty::Usize)?;
+                let len = match ident {
+                    '#' => 1,
+                    '$' => 2,
+                    _ => 4,
+                };
+                format!("m_read({pos}, {len})")
+            }
+
+            Op::Register(name) => match register(name) {
+                Some(Reg::Low(a) | Reg::High(a)) => format!("reg8({a})"),
+                Some(Reg::Wide(a)) => format!("reg16({a})"),
+                Some(Reg::Pair(a, b)) => format!("reg32({a}, {b})"),
+                Some(Reg::Stack) => "(int64_t)s_len".to_owned(),
+                None => return Ok(fail(body, &format!("Invalid register: {name}"))),
+            },
+
+            Op::Label(name) => match self.label(name) {
+                Some(ind) => ind.to_string(),
+                None => return Ok(fail(body, &format!("No matching branch for label {name}"))),
+            },
+
+            Op::Cmd(name, args, span) => {
+                let func = self.cmd(name, args, *span)?;
+                let saved = self.temp();
+                let _ = writeln!(
+                    body,
+                    "    {{ const char *{saved} = at; size_t d = 0; {func}(&d); at = {saved}; }}"
+                );
+                "cmd_val".to_owned()
+            }
+
+            Op::BinOp(..) => return Ok(fail(body, "Math unimplemented")),
+
+            #[cfg(feature = "inline-python")]
+            Op::InlinePy(_) => return err!("Inline python can't be compiled to C"),
+
+            _ => return Ok(fail(body, &format!("Invalid numeric literal: {op:?}"))),
+        };
+
+        let var = self.temp();
+        let _ = writeln!(body, "    int64_t {var} = {}({raw});", ty.cast());
+        Ok(var)
+    }
+
+    // Emits the statements that read `op` as a float, returning the variable
+    // holding it. Integers are converted
+    fn read_float(&mut self, body: &mut String, op: &Op) -> Result<String> {
+        let raw = match op {
+            Op::Float(val) => c_float(*val),
+            _ => match float_register(op) {
+                Some(reg) => format!("freg({})", reg.0),
+                None => format!("(double){}", self.read(body, op, Ty::I32)?),
+            },
+        };
+
+        let var = self.temp();
+        let _ = writeln!(body, "    double {var} = {raw};");
+        Ok(var)
+    }
+
+    // Emits the statements that find where on the stack the `len` bytes
+    // `lds`/`sts` (from the top) or `ldf`/`stf` (from the frame pointer)
+    // access with `offset` start, returning the variable holding it
+    fn stack_pos(
+        &mut self,
+        body: &mut String,
+        name: &str,
+        offset: &Op,
+        len: usize,
+    ) -> Result<String> {
+        let var = self.temp();
+        if name.ends_with('s') {
+            let offset = self.read(body, offset, Ty::Usize)?;
+            let _ = writeln!(
+                body,
+                "    int64_t {var} = (int64_t)s_len - {offset} - {len};"
+            );
+        } else {
+            let offset = self.read(body, offset, Ty::I32)?;
+            let _ = writeln!(body, "    int64_t {var} = reg16(FRAME_POINTER) + {offset};");
+        }
+        Ok(var)
+    }
+
+    // Reads where the value to write comes from
+    fn value(&mut self, body: &mut String, src: &Src<'_>, ty: Ty) -> Result<String> {
+        match src {
+            Src::Op(op) => self.read(body, op, ty),
+            Src::Expr(expr) => {
+                let var = self.temp();
+                let _ = writeln!(body, "    int64_t {var} = {}({expr});", ty.cast());
+                Ok(var)
+            }
+            Src::Pop => {
+                let var = self.temp();
+                let _ = writeln!(
+                    body,
+                    "    int64_t {var} = {}(s_pop({}));",
+                    ty.cast(),
+                    ty.pop_len()
+                );
+                Ok(var)
+            }
+        }
+    }
+
+    // Emits the statements that write `src` to `dest`
+    fn write(&mut self, body: &mut String, dest: &Op, src: &Src<'_>) -> Result<()> {
+        if let Some(msg) = not_integer(dest) {
+            fail(body, &msg);
+            return Ok(());
+        }
+
+        match dest {
+            Op::Register(name) => {
+                let Some(reg) = register(name) else {
+                    fail(body, &format!("Invalid register: {name}"));
+                    return Ok(());
+                };
+
+                let ty = match reg {
+                    Reg::Low(_) | Reg::High(_) => Ty::U8,
+                    Reg::Wide(_) | Reg::Stack => Ty::I16,
+                    Reg::Pair(..) => Ty::I32,
+                };
+                let val = self.value(body, src, ty)?;
+                let _ = match reg {
+                    Reg::Low(a) | Reg::High(a) => writeln!(body, "    set_reg8({a}, {val});"),
+                    Reg::Wide(a) => writeln!(body, "    set_reg16({a}, {val});"),
+                    Reg::Pair(a, b) => writeln!(body, "    set_reg32({a}, {b}, {val});"),
+                    Reg::Stack => writeln!(body, "    set_sp({val});"),
+                };
+            }
+
+            Op::Memory(ident, addr) => {
+                let pos = self.read(body, addr, Ty::Usize)?;
+                let (ty, len) = match ident {
+                    '#' => (Ty::U8, 1),
+                    '$' => (Ty::I16, 2),
+                    _ => (Ty::I32, 4),
+                };
+                let val = self.value(body, src, ty)?;
+                let _ = writeln!(body, "    m_write({pos}, {len}, {val});");
+            }
+
+            _ => {
+                fail(body, &format!("Invalid parameter: {dest:?}"));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Generates a function running the command, returning its name. The
+    // function returns whether it jumped, and leaves its value in `cmd_val`
+    fn cmd(&mut self, name: &str, args: &[Op], span: Span) -> Result<String> {
+        let mut body = String::new();
+        if CONDITIONALS.iter().any(|(cond, _)| *cond == name) {
+            body.push_str("    if (!mem[REGISTRY_OFFSET]) return 0;\n");
+        }
+
+        match name {
+            "mov" | "cmo" => self.write(&mut body, &args[0], &Src::Op(&args[1]))?,
+
+            "inc" | "cin" | "dec" | "cde" => {
+                let val = self.read(&mut body, &args[0], Ty::I32)?;
+                let (op, msg) = if name == "inc" || name == "cin" {
+                    ('+', "attempt to add with overflow")
+                } else {
+                    ('-', "attempt to subtract with overflow")
+                };
+                self.write(
+                    &mut body,
+                    &args[0],
+                    &Src::Expr(format!("checked({val} {op} 1, \"{msg}\")")),
+                )?;
+            }
+
+            "out" | "cou" => {
+                let val = self.read(&mut body, &args[0], Ty::I32)?;
+                let _ = writeln!(body, "    printf(\"%lld\", (long long){val});");
+            }
+
+            "chr" | "cch" => {
+                let val = self.read(&mut body, &args[0], Ty::U8)?;
+                let _ = writeln!(body, "    put_chr(stdout, {val});");
+            }
+
+            "err" => {
+                let val = self.read(&mut body, &args[0], Ty::I32)?;
+                let _ = writeln!(body, "    fprintf(stderr, \"%lld\", (long long){val});");
+            }
+
+            "errc" => {
+                let val = self.read(&mut body, &args[0], Ty::U8)?;
+                let _ = writeln!(body, "    put_chr(stderr, {val});");
+            }
+
+            "jmp" | "cjm" => {
+                let val = self.read(&mut body, &args[0], Ty::Usize)?;
+                let _ = writeln!(body, "    set_ind(ind, {val});\n    return 1;");
+            }
+
+            "mul" | "cmu" | "div" | "cdi" | "add" | "cad" | "sub" | "csu" | "lsh" | "cls"
+            | "rsh" | "crs" | "or" | "cor" | "xor" | "cxo" | "and" | "can" => {
+                let l = self.read(&mut body, &args[0], Ty::I32)?;
+                let r = self.read(&mut body, &args[1], Ty::I32)?;
+                let expr = match name {
+                    "mul" | "cmu" => {
+                        format!("checked({l} * {r}, \"attempt to multiply with overflow\")")
+                    }
+                    "div" | "cdi" => {
+                        let _ = writeln!(
+                            body,
+                            "    if ({r} == 0) fail(\"attempt to divide by zero\");\n    if ({l} == INT32_MIN && {r} == -1) fail(\"attempt to divide with overflow\");"
+                        );
+                        format!("{l} / {r}")
+                    }
+                    "add" | "cad" => format!("checked({l} + {r}, \"attempt to add with overflow\")"),
+                    "sub" | "csu" => {
+                        format!("checked({l} - {r}, \"attempt to subtract with overflow\")")
+                    }
+                    // Bits shifted out are lost, only the amount is checked
+                    "lsh" | "cls" => format!(
+                        "wrap((int64_t)((uint32_t){l} << shift({r}, \"attempt to shift left with overflow\")))"
+                    ),
+                    "rsh" | "crs" => format!(
+                        "(int32_t){l} >> shift({r}, \"attempt to shift right with overflow\")"
+                    ),
+                    "or" | "cor" => format!("{l} | {r}"),
+                    "xor" | "cxo" => format!("{l} ^ {r}"),
+                    _ => format!("{l} & {r}"),
+                };
+                self.write(&mut body, &args[0], &Src::Expr(expr))?;
+            }
+
+            "ceq" | "cne" | "cl" | "cle" | "cg" | "cge" => {
+                let l = self.read(&mut body, &args[0], Ty::I32)?;
+                let r = self.read(&mut body, &args[1], Ty::I32)?;
+                let op = match name {
+                    "ceq" => "==",
+                    "cne" => "!=",
+                    "cl" => "<",
+                    "cle" => "<=",
+                    "cg" => ">",
+                    _ => ">=",
+                };
+                let _ = writeln!(body, "    mem[REGISTRY_OFFSET] = {l} {op} {r};");
+            }
+
+            "cz" => {
+                let val = self.read(&mut body, &args[0], Ty::I32)?;
+                let _ = writeln!(body, "    mem[REGISTRY_OFFSET] = {val} == 0;");
+            }
+
+            "str" => match &args[0] {
+                Op::String(text) => {
+                    for (i, chr) in text.chars().enumerate() {
+                        let _ = writeln!(body, "    m_write({i}, 1, {});", chr as u8);
+                    }
+                    let term = self.read(&mut body, &args[1], Ty::U8)?;
+                    let _ = writeln!(body, "    m_write({}, 1, {term});", text.len());
+                }
+                _ => {
+                    fail(
+                        &mut body,
+                        "Argument #0 for command 'str' must be of type Op::String",
+                    );
+                }
+            },
+
+            "stk" => {
+                let val = self.read(&mut body, &args[0], Ty::Usize)?;
+                let _ = writeln!(body, "    resize_stack({val});");
+            }
+
+            "psh" | "cps" => {
+                let size = self.read(&mut body, &args[0], Ty::I32)?;
+                let _ = writeln!(body, "    switch ({size}) {{");
+                for (len, ty) in [(1, Ty::U8), (2, Ty::I16), (4, Ty::I32)] {
+                    let _ = writeln!(body, "    case {len}: {{");
+                    let val = self.read(&mut body, &args[1], ty)?;
+                    let _ = writeln!(body, "    s_push({len}, {val});\n    break;\n    }}");
+                }
+                body.push_str("    default:\n");
+                fail(&mut body, "Cannot push a number of that size to the stack");
+                body.push_str("    }\n");
+            }
+
+            "pop" | "cpo" => self.write(&mut body, &args[0], &Src::Pop)?,
+
+            "enter" => {
+                let len = self.read(&mut body, &args[0], Ty::Usize)?;
+                let _ = writeln!(body, "    enter({len});");
+            }
+
+            "leave" => body.push_str("    leave();\n"),
+
+            "lds" | "ldf" => {
+                let Some(bits) = checker::width(&args[0]) else {
+                    fail(&mut body, &format!("Invalid parameter: {:?}", args[0]));
+                    return Ok(self.finish(name, span, &body));
+                };
+                let len = bits as usize / 8;
+                let pos = self.stack_pos(&mut body, name, &args[1], len)?;
+                let val = self.temp();
+                let _ = writeln!(body, "    int64_t {val} = s_read({pos}, {len});");
+                self.write(&mut body, &args[0], &Src::Expr(val))?;
+            }
+
+            "sts" | "stf" => {
+                let size = self.read(&mut body, &args[0], Ty::Usize)?;
+                let _ = writeln!(body, "    switch ({size}) {{");
+                for (len, ty) in [(1, Ty::U8), (2, Ty::I16), (4, Ty::I32)] {
+                    let _ = writeln!(body, "    case {len}: {{");
+                    let pos = self.stack_pos(&mut body, name, &args[1], len)?;
+                    let val = self.read(&mut body, &args[2], ty)?;
+                    let _ = writeln!(
+                        body,
+                        "    s_write({pos}, {len}, {val});\n    break;\n    }}"
+                    );
+                }
+                body.push_str("    default:\n");
+                fail(&mut body, "Cannot store a number of that size on the stack");
+                body.push_str("    }\n");
+            }
+
+            "not" | "cno" => {
+                let val = self.read(&mut body, &args[0], Ty::I32)?;
+                self.write(&mut body, &args[0], &Src::Expr(format!("~{val}")))?;
+            }
+
+            "swp" | "csw" => {
+                self.write(&mut body, &args[1], &Src::Op(&args[0]))?;
+                self.write(&mut body, &args[0], &Src::Op(&args[1]))?;
+            }
+
+            "db" => {
+                let start = self.read(&mut body, &args[0], Ty::Usize)?;
+                let term = self.read(&mut body, &args[1], Ty::U8)?;
+                let _ = writeln!(
+                    body,
+                    "    int64_t len = 0;\n    for (int64_t i = {start}; m_read(i, 1) != {term}; i++) len++;\n    cmd_val = len;"
+                );
+            }
+
+            "in" => body.push_str("    cmd_val = read_in();\n"),
+
+            "eof" => body.push_str("    cmd_val = peek_in() == EOF;\n"),
+
+            "rdn" => self.write(&mut body, &args[0], &Src::Expr("read_int()".to_owned()))?,
+
+            "rdl" => {
+                let start = self.read(&mut body, &args[0], Ty::Usize)?;
+                let max = self.read(&mut body, &args[1], Ty::Usize)?;
+                let _ = writeln!(body, "    cmd_val = read_line({start}, {max});");
+            }
+
+            "hex" | "bin" => {
+                let val = self.read(&mut body, &args[0], Ty::I32)?;
+                let width = self.read(&mut body, &args[1], Ty::Usize)?;
+                let bits = checker::width(&args[0]).unwrap_or(32);
+                let base = if name == "hex" { 16 } else { 2 };
+                let _ = writeln!(body, "    put_base({val}, {bits}, {base}, {width});");
+            }
+
+            "pad" => {
+                let val = self.read(&mut body, &args[0], Ty::I32)?;
+                let width = self.read(&mut body, &args[1], Ty::Usize)?;
+                let _ = writeln!(body, "    put_padded({val}, {width});");
+            }
+
+            "prs" => {
+                let start = self.read(&mut body, &args[0], Ty::Usize)?;
+                let term = self.read(&mut body, &args[1], Ty::U8)?;
+                let _ = writeln!(
+                    body,
+                    "    for (int64_t i = {start}; m_read(i, 1) != {term}; i++) put_chr(stdout, m_read(i, 1));"
+                );
+            }
+
+            "fmov" | "fadd" | "fsub" | "fmul" | "fdiv" | "fld" => {
+                let Some(reg) = float_register(&args[0]) else {
+                    fail(&mut body, "Expected a float register to write to");
+                    return Ok(self.finish(name, span, &body));
+                };
+                let val = if name == "fld" {
+                    let pos = self.read(&mut body, &args[1], Ty::Usize)?;
+                    format!("m_read_f64({pos})")
+                } else {
+                    self.read_float(&mut body, &args[1])?
+                };
+                let expr = match name {
+                    "fadd" => format!("freg({}) + {val}", reg.0),
+                    "fsub" => format!("freg({}) - {val}", reg.0),
+                    "fmul" => format!("freg({}) * {val}", reg.0),
+                    "fdiv" => format!("freg({}) / {val}", reg.0),
+                    _ => val,
+                };
+                let _ = writeln!(body, "    set_freg({}, {expr});", reg.0);
+            }
+
+            "ftoi" => {
+                let val = self.read_float(&mut body, &args[1])?;
+                self.write(&mut body, &args[0], &Src::Expr(format!("f_to_i32({val})")))?;
+            }
+
+            "feq" | "fne" | "flt" | "fle" | "fgt" | "fge" => {
+                let l = self.read_float(&mut body, &args[0])?;
+                let r = self.read_float(&mut body, &args[1])?;
+                let op = match name {
+                    "feq" => "==",
+                    "fne" => "!=",
+                    "flt" => "<",
+                    "fle" => "<=",
+                    "fgt" => ">",
+                    _ => ">=",
+                };
+                let _ = writeln!(body, "    mem[REGISTRY_OFFSET] = {l} {op} {r};");
+            }
+
+            "fst" => {
+                let pos = self.read(&mut body, &args[0], Ty::Usize)?;
+                let val = self.read_float(&mut body, &args[1])?;
+                let _ = writeln!(body, "    m_write_f64({pos}, {val});");
+            }
+
+            "fout" => {
+                let val = self.read_float(&mut body, &args[0])?;
+                let digits = self.read(&mut body, &args[1], Ty::Usize)?;
+                let _ = writeln!(body, "    put_float({val}, {digits});");
+            }
+
+            "ret" | "cre" => body.push_str("    return ret(ind);\n"),
+
+            "hlt" | "chl" => {
+                let val = self.read(&mut body, &args[0], Ty::I32)?;
+                let _ = writeln!(
+                    body,
+                    "    exit_code = (int){val};\n    halted = 1;\n    return 1;"
+                );
+            }
+
+            "argc" => body.push_str("    cmd_val = main_argc;\n"),
+
+            "argv" => {
+                let i = self.read(&mut body, &args[0], Ty::Usize)?;
+                let start = self.read(&mut body, &args[1], Ty::Usize)?;
+                let max = self.read(&mut body, &args[2], Ty::Usize)?;
+                let _ = writeln!(
+                    body,
+                    "    if ({i} < main_argc) {{\n        mem[REGISTRY_OFFSET] = 1;\n        cmd_val = write_str(main_argv[{i}], {start}, {max});\n    }} else {{\n        mem[REGISTRY_OFFSET] = 0;\n        cmd_val = -1;\n    }}"
+                );
+            }
+
+            "alloc" => {
+                let len = self.read(&mut body, &args[0], Ty::Usize)?;
+                let _ = writeln!(
+                    body,
+                    "    cmd_val = heap_alloc({len});\n    mem[REGISTRY_OFFSET] = cmd_val >= 0;"
+                );
+            }
+
+            "free" => {
+                let pos = self.read(&mut body, &args[0], Ty::Usize)?;
+                let _ = writeln!(body, "    heap_free({pos});");
+            }
+
+            // Translated programs can't be allowed into any directory or to
+            // look up any environment variable, so these always fail
+            "env" | "fop" | "frd" | "fwr" | "fsk" | "fcl" => {
+                for arg in args {
+                    self.read(&mut body, arg, Ty::I32)?;
+                }
+                body.push_str("    mem[REGISTRY_OFFSET] = 0;\n    cmd_val = -1;\n");
+            }
+
+            "assert" => {
+                body.push_str("    if (!mem[REGISTRY_OFFSET]) {\n");
+                match &args[0] {
+                    Op::String(msg) => {
+                        fail(&mut body, &format!("Assertion failed: {msg}"));
+                    }
+                    _ => {
+                        fail(&mut body, "Assertion message must be a string");
+                    }
+                }
+                body.push_str("    }\n");
+            }
+
+            "assert_eq" | "assert_ne" => {
+                let l = self.read(&mut body, &args[0], Ty::I32)?;
+                let r = self.read(&mut body, &args[1], Ty::I32)?;
+                let op = if name == "assert_eq" { "==" } else { "!=" };
+                let _ = writeln!(body, "    if (!({l} {op} {r})) {{");
+                match &args[2] {
+                    Op::String(msg) => {
+                        let _ = writeln!(body, "    fail_values({}, {l}, {r});", c_str(msg));
+                    }
+                    _ => {
+                        fail(&mut body, "Assertion message must be a string");
+                    }
+                }
+                body.push_str("    }\n");
+            }
+
+            _ => {
+                fail(&mut body, &format!("Command: {name} unrecognized"));
+            }
+        }
+
+        Ok(self.finish(name, span, &body))
+    }
+
+    // Generates the function for the command `name` with the statements in
+    // `body`, returning its name
+    fn finish(&mut self, name: &str, span: Span, body: &str) -> String {
+        let func = format!("cmd{}", self.funcs.len());
+        self.funcs.push(format!(
+            "// {name} at {span}\nstatic int {func}(size_t *ind) {{\n    (void)ind;\n    at = {};\n    cmd_val = 0;\n{body}    return 0;\n}}\n",
+            c_str(&span.to_string())
+        ));
+        func
+    }
+
+    // Generates a function running the op at `ind` inside of `ast`, returning
+    // its name. `top_level` is set for ops directly inside of the program
+    fn op(&mut self, op: &Op, top_level: bool) -> Result<String> {
+        match op {
+            Op::Cmd(name, args, span) => {
+                let cmd = self.cmd(name, args, *span)?;
+                if !top_level {
+                    return Ok(cmd);
+                }
+
+                // Commands outside of any branch forget where jumps came from
+                let func = format!("top{}", self.funcs.len());
+                self.funcs.push(format!(
+                    "static int {func}(size_t *ind) {{\n    jumps_len = 0;\n    return {cmd}(ind);\n}}\n"
+                ));
+                Ok(func)
+            }
+
+            Op::Branch(name, ops) => {
+                let mut cases = String::new();
+                for (i, op) in ops.iter().enumerate() {
+                    let func = self.op(op, false)?;
+                    let _ = writeln!(cases, "        case {i}: jumped = {func}(&j); break;");
+                }
+
+                let func = format!("branch{}", self.funcs.len());
+                self.funcs.push(format!(
+                    "// {name}\nstatic int {func}(size_t *ind) {{\n    for (size_t i = pos1; i < {}; i++) {{\n        size_t j = i;\n        int jumped = 0;\n        switch (i) {{\n{cases}        }}\n        if (jumped) {{\n            *ind = j;\n            return 1;\n        }}\n        pos1++;\n    }}\n    return 0;\n}}\n",
+                    ops.len()
+                ));
+                Ok(func)
+            }
+
+            #[cfg(feature = "inline-python")]
+            Op::InlinePy(_) => err!("Inline python can't be compiled to C"),
+
+            _ => {
+                let mut body = String::new();
+                fail(&mut body, &format!("Invalid top-level op: {op:?}"));
+                let func = format!("invalid{}", self.funcs.len());
+                self.funcs.push(format!(
+                    "static int {func}(size_t *ind) {{\n    (void)ind;\n{body}    return 0;\n}}\n"
+                ));
+                Ok(func)
+            }
+        }
+    }
+}
+
+// Emits a call to `fail`, returning a placeholder value for the code that
+// follows it (which never runs)
+fn fail(body: &mut String, msg: &str) -> String {
+    let _ = writeln!(body, "    fail({});", c_str(msg));
+    "0".to_owned()
+}
+
+// Translates `tree` into a self-contained C program, which behaves exactly like
+// running the tree would: it reads the program's input from stdin, prints the
+// same output and exits with the same code
+pub fn emit(tree: &[Op]) -> Result<String> {
+    let mut gen = Gen {
+        tree,
+        funcs: Vec::new(),
+        temps: 0,
+    };
+
+    let mut cases = String::new();
+    for (i, op) in tree.iter().enumerate() {
+        let func = gen.op(op, true)?;
+        let _ = writeln!(cases, "        case {i}: jumped = {func}(&ind); break;");
+    }
+
+    let is_branch: Vec<&str> = tree
+        .iter()
+        .map(|op| {
+            if matches!(op, Op::Branch(..)) {
+                "1"
+            } else {
+                "0"
+            }
+        })
+        .chain(std::iter::once("0"))
+        .collect();
+
+    let mut res = format!(
+        "// Generated by `arsm emit-c`\n#define MEM_SIZE {MEM_SIZE}\n#define FRAME_POINTER {FRAME_POINTER}\n#define FLOAT_REGISTERS {FLOAT_REGISTERS}\n#define REGISTRY_OFFSET {REGISTRY_OFFSET}\n#define OFFSET {OFFSET}\n#define OPS {}\n\nstatic const char IS_BRANCH[] = {{{}}};\n\n",
+        tree.len(),
+        is_branch.join(", ")
+    );
+    res.push_str(PRELUDE);
+    for func in &gen.funcs {
+        res.push('\n');
+        res.push_str(func);
+    }
+
+    let _ = write!(
+        res,
+        r"
+int main(int argc, char **argv) {{
+    main_argc = argc;
+    main_argv = argv;
+    size_t ind = 0;
+    while (ind < OPS) {{
+        int jumped = 0;
+        switch (ind) {{
+{cases}        }}
+        if (!jumped) {{
+            ind++;
+            pos0++;
+        }}
+        if (halted) break;
+    }}
+
+    fflush(stdout);
+    return exit_code;
+}}
+"
+    );
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod emit_c_tests {
+    use super::*;
+
+    fn emit_program(program: &str) -> String {
+        emit(&crate::compile(program).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_layout() {
+        let res = emit_program("out 1");
+        assert!(res.contains(&format!("#define OFFSET {OFFSET}")));
+        assert!(res.contains(&format!("#define REGISTRY_OFFSET {REGISTRY_OFFSET}")));
+        assert!(res.contains("static const char IS_BRANCH[] = {0, 0};"));
+    }
+
+    #[test]
+    fn test_registers() {
+        let res = emit_program("mov abx 70000\nmov ch 3\nmov dl 4\nmov ex 5");
+        assert!(res.contains("set_reg32(0, 2, "));
+        assert!(res.contains("set_reg8(5, "));
+        assert!(res.contains("set_reg8(6, "));
+        assert!(res.contains("set_reg16(8, "));
+    }
+
+    #[test]
+    fn test_errors_at_runtime() {
+        // Mistakes only fail once (and if) they are reached, like in the parser
+        let res = emit_program("out 1\njmp :missing");
+        assert!(res.contains("fail(\"No matching branch for label :missing\");"));
+    }
+
+    #[test]
+    fn test_overflow() {
+        // Math fails with the same messages as the interpreter instead of wrapping
+        let res = emit_program("inc abx\nmul ax 3\nlsh ax bx");
+        assert!(res.contains("checked(t1 + 1, \"attempt to add with overflow\")"));
+        assert!(res.contains("\"attempt to multiply with overflow\")"));
+        assert!(res.contains("\"attempt to shift left with overflow\")"));
+    }
+
+    #[test]
+    fn test_floats() {
+        let res = emit_program("fmov f3 0.1\nfadd f3 ax\nmov bx f3");
+        assert!(res.contains("double t1 = 0.1;"));
+        assert!(res.contains("set_freg(3, freg(3) + t"));
+        assert!(res.contains("fail(\"Expected an integer, but f3 is a float register\");"));
+    }
+
+    #[test]
+    fn test_c_str() {
+        assert_eq!(c_str("a\"b\\\n"), "\"a\\\"b\\\\\\012\"");
+    }
+}
@@ -0,0 +1,1413 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::checker;
+#[cfg(feature = "inline-python")]
+use crate::err;
+use crate::utils::{
+    consts::{CONDITIONALS, FRAME_POINTER, MEM_SIZE, OFFSET, REGISTRY_OFFSET},
+    error::Result,
+    token::{Op, Span},
+    traits::{float_register, not_integer, register, Reg},
+};
+
+// The module's data (error messages and the branch table) is placed right
+// after memory, which is as big as an `Environment` starts out with, and the
+// stack of `jmp` return points after that
+const PAGE_SIZE: usize = 65536;
+
+// The types values get converted to before they are used, matching the types
+// the parser converts to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    U8,
+    I16,
+    I32,
+    Usize,
+}
+
+impl Ty {
+    fn cast(self) -> &'static str {
+        match self {
+            Self::U8 => "$as_u8",
+            Self::I16 => "$as_i16",
+            Self::I32 => "$as_i32",
+            Self::Usize => "$as_usize",
+        }
+    }
+
+    // How many bytes values of this type take up in memory
+    fn len(self) -> usize {
+        match self {
+            Self::U8 => 1,
+            Self::I16 => 2,
+            Self::I32 | Self::Usize => 4,
+        }
+    }
+}
+
+// Where the value written by `write` comes from
+enum Src<'a> {
+    Op(&'a Op),
+
+    // A WAT expression holding an `i64` the command computed
+    Expr(String),
+
+    // Popped off the stack
+    Pop,
+}
+
+// `val` as a WAT float literal
+fn wat_float(val: f64) -> String {
+    if val.is_nan() {
+        "nan".to_owned()
+    } else if val.is_infinite() {
+        if val < 0.0 { "-inf" } else { "inf" }.to_owned()
+    } else {
+        format!("{val:?}")
+    }
+}
+
+// Escapes `bytes` as a WAT string literal
+fn wat_str(bytes: &[u8]) -> String {
+    let mut res = String::from('"');
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                res.push('\\');
+                res.push(byte as char);
+            }
+            b' '..=b'~' => res.push(byte as char),
+            _ => {
+                let _ = write!(res, "\\{byte:02x}");
+            }
+        }
+    }
+    res.push('"');
+    res
+}
+
+// A function being generated: its locals, and the instructions in its body
+#[derive(Default)]
+struct Func {
+    locals: Vec<(String, &'static str)>,
+    body: String,
+}
+
+impl Func {
+    fn local(&mut self, ty: &'static str) -> String {
+        let name = format!("$t{}", self.locals.len());
+        self.locals.push((name.clone(), ty));
+        name
+    }
+
+    fn line(&mut self, line: &str) {
+        let _ = writeln!(self.body, "    {line}");
+    }
+
+    fn locals(&self) -> String {
+        self.locals
+            .iter()
+            .fold(String::new(), |mut res, (name, ty)| {
+                let _ = write!(res, " (local {name} {ty})");
+                res
+            })
+    }
+}
+
+struct Gen<'a> {
+    tree: &'a [Op],
+
+    // Every generated function, in the order they were generated
+    funcs: Vec<String>,
+
+    // The module's data segment, and where each string was placed in it
+    data: Vec<u8>,
+    strings: HashMap<String, usize>,
+}
+
+impl Gen<'_> {
+    // Places `text` in the data segment, returning its address and length as
+    // the two `i32` arguments most imports take
+    fn string(&mut self, text: &str) -> String {
+        let pos = if let Some(&pos) = self.strings.get(text) {
+            pos
+        } else {
+            let pos = MEM_SIZE + self.data.len();
+            self.data.extend_from_slice(text.as_bytes());
+            self.strings.insert(text.to_owned(), pos);
+            pos
+        };
+        format!("(i32.const {pos}) (i32.const {})", text.len())
+    }
+
+    fn fail_call(&mut self, msg: &str) -> String {
+        format!("(call $fail {})", self.string(msg))
+    }
+
+    // Emits a call to `$fail`, returning a placeholder value for the code that
+    // follows it (which never runs)
+    fn fail(&mut self, func: &mut Func, msg: &str) -> String {
+        let call = self.fail_call(msg);
+        func.line(&call);
+        "(i64.const 0)".to_owned()
+    }
+
+    fn label(&self, name: &str) -> Option<usize> {
+        self.tree
+            .iter()
+            .position(|op| matches!(op, Op::Branch(n, _) if n[1..] == name[1..]))
+    }
+
+    // Emits the instructions that read `op` as a `ty`, returning an expression
+    // holding it
+    fn read(&mut self, func: &mut Func, op: &Op, ty: Ty) -> Result<String> {
+        if let Some(msg) = not_integer(op) {
+            return Ok(self.fail(func, &msg));
+        }
+
+        let raw = match op {
+            Op::Numeric(val) => format!("(i64.const {val})"),
+
+            Op::Char(chr) => format!("(i64.const {})", *chr as u8),
+
+            Op::Memory(ident, addr) => {
+                let pos = self.read(func, addr, Ty::Usize)?;
+                let bits = match ident {
+                    '#' => 8,
+                    '$' => 16,
+                    _ => 32,
+                };
+                format!("(call $m_read{bits} {pos})")
+            }
+
+            Op::Register(name) => match register(name) {
+                Some(Reg::Low(a) | Reg::High(a)) => format!("(call $reg8 (i32.const {a}))"),
+                Some(Reg::Wide(a)) => format!("(call $reg16 (i32.const {a}))"),
+                Some(Reg::Pair(a, b)) => {
+                    format!("(call $reg32 (i32.const {a}) (i32.const {b}))")
+                }
+                Some(Reg::Stack) => "(i64.extend_i32_u (global.get $s_len))".to_owned(),
+                None => return Ok(self.fail(func, &format!("Invalid register: {name}"))),
+            },
+
+            Op::Label(name) => match self.label(name) {
+                Some(ind) => format!("(i64.const {ind})"),
+                None => return Ok(self.fail(func, &format!("No matching branch for label {name}"))),
+            },
+
+            Op::Cmd(name, args, span) => {
+                let cmd = self.cmd(name, args, *span)?;
+                let (at, at_len) = (func.local("i32"), func.local("i32"));
+                func.line(&format!(
+                    "(local.set {at} (global.get $at)) (local.set {at_len} (global.get $at_len))"
+                ));
+                func.line(&format!("(drop (call {cmd}))"));
+                func.line(&format!(
+                    "(global.set $at (local.get {at})) (global.set $at_len (local.get {at_len}))"
+                ));
+                "(global.get $cmd_val)".to_owned()
+            }
+
+            Op::BinOp(..) => return Ok(self.fail(func, "Math unimplemented")),
+
+            #[cfg(feature = "inline-python")]
+            Op::InlinePy(_) => return err!("Inline python can't be compiled to WebAssembly"),
+
+            _ => return Ok(self.fail(func, &format!("Invalid numeric literal: {op:?}"))),
+        };
+
+        let var = func.local("i64");
+        func.line(&format!("(local.set {var} (call {} {raw}))", ty.cast()));
+        Ok(format!("(local.get {var})"))
+    }
+
+    // Emits the instructions that read `op` as a float, returning an
+    // expression holding it. Integers are converted
+    fn read_float(&mut self, func: &mut Func, op: &Op) -> Result<String> {
+        let raw = match op {
+            Op::Float(val) => format!("(f64.const {})", wat_float(*val)),
+            _ => match float_register(op) {
+                Some(reg) => format!("(f64.load (i32.const {}))", reg.offset()),
+                None => format!("(f64.convert_i64_s {})", self.read(func, op, Ty::I32)?),
+            },
+        };
+
+        let var = func.local("f64");
+        func.line(&format!("(local.set {var} {raw})"));
+        Ok(format!("(local.get {var})"))
+    }
+
+    // Emits the instructions that find where on the stack the `len` bytes
+    // `lds`/`sts` (from the top) or `ldf`/`stf` (from the frame pointer)
+    // access with `offset` start, returning an expression holding it
+    fn stack_pos(
+        &mut self,
+        func: &mut Func,
+        name: &str,
+        offset: &Op,
+        len: usize,
+    ) -> Result<String> {
+        let var = func.local("i64");
+        if name.ends_with('s') {
+            let offset = self.read(func, offset, Ty::Usize)?;
+            func.line(&format!(
+                "(local.set {var} (i64.sub (i64.sub (i64.extend_i32_u (global.get $s_len)) {offset}) (i64.const {len})))"
+            ));
+        } else {
+            let offset = self.read(func, offset, Ty::I32)?;
+            func.line(&format!(
+                "(local.set {var} (i64.add (call $reg16 (i32.const {FRAME_POINTER})) {offset}))"
+            ));
+        }
+        Ok(format!("(local.get {var})"))
+    }
+
+    // Reads where the value to write comes from
+    fn value(&mut self, func: &mut Func, src: &Src<'_>, ty: Ty) -> Result<String> {
+        let raw = match src {
+            Src::Op(op) => return self.read(func, op, ty),
+            Src::Expr(expr) => expr.clone(),
+            Src::Pop => format!("(call $s_pop (i32.const {}))", ty.len()),
+        };
+
+        let var = func.local("i64");
+        func.line(&format!("(local.set {var} (call {} {raw}))", ty.cast()));
+        Ok(format!("(local.get {var})"))
+    }
+
+    // Emits the instructions that write `src` to `dest`
+    fn write(&mut self, func: &mut Func, dest: &Op, src: &Src<'_>) -> Result<()> {
+        if let Some(msg) = not_integer(dest) {
+            self.fail(func, &msg);
+            return Ok(());
+        }
+
+        match dest {
+            Op::Register(name) => {
+                let Some(reg) = register(name) else {
+                    self.fail(func, &format!("Invalid register: {name}"));
+                    return Ok(());
+                };
+
+                let ty = match reg {
+                    Reg::Low(_) | Reg::High(_) => Ty::U8,
+                    Reg::Wide(_) | Reg::Stack => Ty::I16,
+                    Reg::Pair(..) => Ty::I32,
+                };
+                let val = self.value(func, src, ty)?;
+                func.line(&match reg {
+                    Reg::Low(a) | Reg::High(a) => format!("(call $set_reg8 (i32.const {a}) {val})"),
+                    Reg::Wide(a) => format!("(call $set_reg16 (i32.const {a}) {val})"),
+                    Reg::Pair(a, b) => {
+                        format!("(call $set_reg32 (i32.const {a}) (i32.const {b}) {val})")
+                    }
+                    Reg::Stack => format!("(call $set_sp {val})"),
+                });
+            }
+
+            Op::Memory(ident, addr) => {
+                let pos = self.read(func, addr, Ty::Usize)?;
+                let ty = match ident {
+                    '#' => Ty::U8,
+                    '$' => Ty::I16,
+                    _ => Ty::I32,
+                };
+                let val = self.value(func, src, ty)?;
+                func.line(&format!("(call $m_write{} {pos} {val})", ty.len() * 8));
+            }
+
+            _ => {
+                self.fail(func, &format!("Invalid parameter: {dest:?}"));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Generates a function running the command, returning its name. The
+    // function returns whether it jumped, and leaves its value in `$cmd_val`
+    fn cmd(&mut self, name: &str, args: &[Op], span: Span) -> Result<String> {
+        let mut func = Func::default();
+        let f = &mut func;
+        if CONDITIONALS.iter().any(|(cond, _)| *cond == name) {
+            f.line(&format!(
+                "(if (i32.eqz (i32.load8_u (i32.const {REGISTRY_OFFSET}))) (then (return (i32.const 0))))"
+            ));
+        }
+
+        match name {
+            "mov" | "cmo" => self.write(f, &args[0], &Src::Op(&args[1]))?,
+
+            "inc" | "cin" | "dec" | "cde" => {
+                let val = self.read(f, &args[0], Ty::I32)?;
+                let (op, msg) = if name == "inc" || name == "cin" {
+                    ("add", "attempt to add with overflow")
+                } else {
+                    ("sub", "attempt to subtract with overflow")
+                };
+                let msg = self.string(msg);
+                self.write(
+                    f,
+                    &args[0],
+                    &Src::Expr(format!(
+                        "(call $checked (i64.{op} {val} (i64.const 1)) {msg})"
+                    )),
+                )?;
+            }
+
+            "out" | "cou" => {
+                let val = self.read(f, &args[0], Ty::I32)?;
+                f.line(&format!("(call $host_out (i32.wrap_i64 {val}))"));
+            }
+
+            "chr" | "cch" => {
+                let val = self.read(f, &args[0], Ty::U8)?;
+                f.line(&format!("(call $host_chr (i32.wrap_i64 {val}))"));
+            }
+
+            "err" => {
+                let val = self.read(f, &args[0], Ty::I32)?;
+                f.line(&format!("(call $host_err (i32.wrap_i64 {val}))"));
+            }
+
+            "errc" => {
+                let val = self.read(f, &args[0], Ty::U8)?;
+                f.line(&format!("(call $host_errc (i32.wrap_i64 {val}))"));
+            }
+
+            "jmp" | "cjm" => {
+                let val = self.read(f, &args[0], Ty::Usize)?;
+                f.line(&format!("(call $set_ind {val})"));
+                f.line("(return (i32.const 1))");
+            }
+
+            "mul" | "cmu" | "div" | "cdi" | "add" | "cad" | "sub" | "csu" | "lsh" | "cls"
+            | "rsh" | "crs" | "or" | "cor" | "xor" | "cxo" | "and" | "can" => {
+                let l = self.read(f, &args[0], Ty::I32)?;
+                let r = self.read(f, &args[1], Ty::I32)?;
+                let expr = match name {
+                    "mul" | "cmu" => {
+                        let msg = self.string("attempt to multiply with overflow");
+                        format!("(call $checked (i64.mul {l} {r}) {msg})")
+                    }
+                    "div" | "cdi" => {
+                        let zero = self.fail_call("attempt to divide by zero");
+                        let overflow = self.fail_call("attempt to divide with overflow");
+                        f.line(&format!("(if (i64.eqz {r}) (then {zero}))"));
+                        f.line(&format!(
+                            "(if (i32.and (i64.eq {l} (i64.const {})) (i64.eq {r} (i64.const -1))) (then {overflow}))",
+                            i32::MIN
+                        ));
+                        format!("(i64.div_s {l} {r})")
+                    }
+                    "add" | "cad" => {
+                        let msg = self.string("attempt to add with overflow");
+                        format!("(call $checked (i64.add {l} {r}) {msg})")
+                    }
+                    "sub" | "csu" => {
+                        let msg = self.string("attempt to subtract with overflow");
+                        format!("(call $checked (i64.sub {l} {r}) {msg})")
+                    }
+                    // Bits shifted out are lost, only the amount is checked
+                    "lsh" | "cls" => {
+                        let msg = self.string("attempt to shift left with overflow");
+                        format!("(call $wrap (i64.shl {l} (call $shift {r} {msg})))")
+                    }
+                    "rsh" | "crs" => {
+                        let msg = self.string("attempt to shift right with overflow");
+                        format!("(i64.shr_s {l} (call $shift {r} {msg}))")
+                    }
+                    "or" | "cor" => format!("(i64.or {l} {r})"),
+                    "xor" | "cxo" => format!("(i64.xor {l} {r})"),
+                    _ => format!("(i64.and {l} {r})"),
+                };
+                self.write(f, &args[0], &Src::Expr(expr))?;
+            }
+
+            "ceq" | "cne" | "cl" | "cle" | "cg" | "cge" => {
+                let l = self.read(f, &args[0], Ty::I32)?;
+                let r = self.read(f, &args[1], Ty::I32)?;
+                let op = match name {
+                    "ceq" => "eq",
+                    "cne" => "ne",
+                    "cl" => "lt_s",
+                    "cle" => "le_s",
+                    "cg" => "gt_s",
+                    _ => "ge_s",
+                };
+                f.line(&format!(
+                    "(i32.store8 (i32.const {REGISTRY_OFFSET}) (i64.{op} {l} {r}))"
+                ));
+            }
+
+            "cz" => {
+                let val = self.read(f, &args[0], Ty::I32)?;
+                f.line(&format!(
+                    "(i32.store8 (i32.const {REGISTRY_OFFSET}) (i64.eqz {val}))"
+                ));
+            }
+
+            "str" => match &args[0] {
+                Op::String(text) => {
+                    for (i, chr) in text.chars().enumerate() {
+                        f.line(&format!(
+                            "(call $m_write8 (i64.const {i}) (i64.const {}))",
+                            chr as u8
+                        ));
+                    }
+                    let term = self.read(f, &args[1], Ty::U8)?;
+                    f.line(&format!(
+                        "(call $m_write8 (i64.const {}) {term})",
+                        text.len()
+                    ));
+                }
+                _ => {
+                    self.fail(
+                        f,
+                        "Argument #0 for command 'str' must be of type Op::String",
+                    );
+                }
+            },
+
+            "stk" => {
+                let val = self.read(f, &args[0], Ty::Usize)?;
+                f.line(&format!("(call $resize_stack {val})"));
+            }
+
+            "psh" | "cps" => {
+                let size = self.read(f, &args[0], Ty::I32)?;
+                f.line("(block $psh");
+                for ty in [Ty::U8, Ty::I16, Ty::I32] {
+                    let len = ty.len();
+                    f.line(&format!("(if (i64.eq {size} (i64.const {len})) (then"));
+                    let val = self.read(f, &args[1], ty)?;
+                    f.line(&format!("(call $s_push (i32.const {len}) {val})"));
+                    f.line("(br $psh)))");
+                }
+                self.fail(f, "Cannot push a number of that size to the stack");
+                f.line(")");
+            }
+
+            "pop" | "cpo" => self.write(f, &args[0], &Src::Pop)?,
+
+            "enter" => {
+                let len = self.read(f, &args[0], Ty::Usize)?;
+                f.line(&format!("(call $enter {len})"));
+            }
+
+            "leave" => f.line("(call $leave)"),
+
+            "lds" | "ldf" => {
+                let Some(bits) = checker::width(&args[0]) else {
+                    self.fail(f, &format!("Invalid parameter: {:?}", args[0]));
+                    return Ok(self.finish(name, span, &func));
+                };
+                let len = bits as usize / 8;
+                let pos = self.stack_pos(f, name, &args[1], len)?;
+                let val = f.local("i64");
+                f.line(&format!(
+                    "(local.set {val} (call $s_read {pos} (i32.const {len})))"
+                ));
+                self.write(f, &args[0], &Src::Expr(format!("(local.get {val})")))?;
+            }
+
+            "sts" | "stf" => {
+                let size = self.read(f, &args[0], Ty::Usize)?;
+                f.line("(block $sts");
+                for ty in [Ty::U8, Ty::I16, Ty::I32] {
+                    let len = ty.len();
+                    f.line(&format!("(if (i64.eq {size} (i64.const {len})) (then"));
+                    let pos = self.stack_pos(f, name, &args[1], len)?;
+                    let val = self.read(f, &args[2], ty)?;
+                    f.line(&format!("(call $s_write {pos} (i32.const {len}) {val})"));
+                    f.line("(br $sts)))");
+                }
+                self.fail(f, "Cannot store a number of that size on the stack");
+                f.line(")");
+            }
+
+            "not" | "cno" => {
+                let val = self.read(f, &args[0], Ty::I32)?;
+                self.write(
+                    f,
+                    &args[0],
+                    &Src::Expr(format!("(i64.xor {val} (i64.const -1))")),
+                )?;
+            }
+
+            "swp" | "csw" => {
+                self.write(f, &args[1], &Src::Op(&args[0]))?;
+                self.write(f, &args[0], &Src::Op(&args[1]))?;
+            }
+
+            "db" => {
+                let start = self.read(f, &args[0], Ty::Usize)?;
+                let term = self.read(f, &args[1], Ty::U8)?;
+                let (i, len) = (f.local("i64"), f.local("i64"));
+                f.line(&format!("(local.set {i} {start})"));
+                f.line(&format!(
+                    "(block $done (loop $next\n      (br_if $done (i64.eq (call $m_read8 (local.get {i})) {term}))\n      (local.set {len} (i64.add (local.get {len}) (i64.const 1)))\n      (local.set {i} (i64.add (local.get {i}) (i64.const 1)))\n      (br $next)))"
+                ));
+                f.line(&format!("(global.set $cmd_val (local.get {len}))"));
+            }
+
+            "in" => f.line("(global.set $cmd_val (i64.extend_i32_u (call $read_in)))"),
+            "eof" => f.line(
+                "(global.set $cmd_val (i64.extend_i32_u (i32.lt_s (call $peek_in) (i32.const 0))))",
+            ),
+
+            "rdn" => self.write(f, &args[0], &Src::Expr("(call $read_int)".to_owned()))?,
+
+            "rdl" => {
+                let start = self.read(f, &args[0], Ty::Usize)?;
+                let max = self.read(f, &args[1], Ty::Usize)?;
+                f.line(&format!(
+                    "(global.set $cmd_val (call $read_line {start} {max}))"
+                ));
+            }
+
+            "hex" | "bin" => {
+                let val = self.read(f, &args[0], Ty::I32)?;
+                let width = self.read(f, &args[1], Ty::Usize)?;
+                let mask = (1_i64 << checker::width(&args[0]).unwrap_or(32)) - 1;
+                let base = if name == "hex" { 16 } else { 2 };
+                f.line(&format!(
+                    "(call $put_base (i64.and {val} (i64.const {mask})) (i64.const {base}) {width})"
+                ));
+            }
+
+            "pad" => {
+                let val = self.read(f, &args[0], Ty::I32)?;
+                let width = self.read(f, &args[1], Ty::Usize)?;
+                f.line(&format!("(call $put_padded {val} {width})"));
+            }
+
+            "prs" => {
+                let start = self.read(f, &args[0], Ty::Usize)?;
+                let term = self.read(f, &args[1], Ty::U8)?;
+                let i = f.local("i64");
+                f.line(&format!("(local.set {i} {start})"));
+                f.line(&format!(
+                    "(block $done (loop $next\n      (br_if $done (i64.eq (call $m_read8 (local.get {i})) {term}))\n      (call $host_chr (i32.wrap_i64 (call $m_read8 (local.get {i}))))\n      (local.set {i} (i64.add (local.get {i}) (i64.const 1)))\n      (br $next)))"
+                ));
+            }
+
+            "fmov" | "fadd" | "fsub" | "fmul" | "fdiv" | "fld" => {
+                let Some(reg) = float_register(&args[0]) else {
+                    self.fail(f, "Expected a float register to write to");
+                    return Ok(self.finish(name, span, &func));
+                };
+                let val = if name == "fld" {
+                    let pos = self.read(f, &args[1], Ty::Usize)?;
+                    format!("(f64.load (call $cell {pos} (i32.const 8)))")
+                } else {
+                    self.read_float(f, &args[1])?
+                };
+                let addr = reg.offset();
+                let expr = match name {
+                    "fadd" | "fsub" | "fmul" | "fdiv" => {
+                        format!("(f64.{} (f64.load (i32.const {addr})) {val})", &name[1..])
+                    }
+                    _ => val,
+                };
+                f.line(&format!("(f64.store (i32.const {addr}) {expr})"));
+            }
+
+            "ftoi" => {
+                let val = self.read_float(f, &args[1])?;
+                self.write(f, &args[0], &Src::Expr(format!("(call $f_to_i32 {val})")))?;
+            }
+
+            "feq" | "fne" | "flt" | "fle" | "fgt" | "fge" => {
+                let l = self.read_float(f, &args[0])?;
+                let r = self.read_float(f, &args[1])?;
+                f.line(&format!(
+                    "(i32.store8 (i32.const {REGISTRY_OFFSET}) (f64.{} {l} {r}))",
+                    &name[1..]
+                ));
+            }
+
+            "fst" => {
+                let pos = self.read(f, &args[0], Ty::Usize)?;
+                let val = self.read_float(f, &args[1])?;
+                f.line(&format!(
+                    "(f64.store (call $cell {pos} (i32.const 8)) {val})"
+                ));
+            }
+
+            "fout" => {
+                let val = self.read_float(f, &args[0])?;
+                let digits = self.read(f, &args[1], Ty::Usize)?;
+                f.line(&format!("(call $host_fout {val} (i32.wrap_i64 {digits}))"));
+            }
+
+            "ret" | "cre" => f.line("(return (call $ret))"),
+
+            "hlt" | "chl" => {
+                let val = self.read(f, &args[0], Ty::I32)?;
+                f.line(&format!("(global.set $exit_code (i32.wrap_i64 {val}))"));
+                f.line("(global.set $halted (i32.const 1))");
+                f.line("(return (i32.const 1))");
+            }
+
+            // There are no arguments for modules
+            "argc" => f.line("(global.set $cmd_val (i64.const 0))"),
+
+            "alloc" => {
+                let len = self.read(f, &args[0], Ty::Usize)?;
+                f.line(&format!("(global.set $cmd_val (call $heap_alloc {len}))"));
+                f.line(&format!(
+                    "(i32.store8 (i32.const {REGISTRY_OFFSET}) (i64.ge_s (global.get $cmd_val) (i64.const 0)))"
+                ));
+            }
+
+            "free" => {
+                let pos = self.read(f, &args[0], Ty::Usize)?;
+                f.line(&format!("(call $heap_free {pos})"));
+            }
+
+            // Modules can't be given arguments, be allowed into any directory
+            // or look up any environment variable, so these always fail
+            "argv" | "env" | "fop" | "frd" | "fwr" | "fsk" | "fcl" => {
+                for arg in args {
+                    self.read(f, arg, Ty::I32)?;
+                }
+                f.line(&format!(
+                    "(i32.store8 (i32.const {REGISTRY_OFFSET}) (i32.const 0))"
+                ));
+                f.line("(global.set $cmd_val (i64.const -1))");
+            }
+
+            "assert" => {
+                let call = match &args[0] {
+                    Op::String(msg) => self.fail_call(&format!("Assertion failed: {msg}")),
+                    _ => self.fail_call("Assertion message must be a string"),
+                };
+                f.line(&format!(
+                    "(if (i32.eqz (i32.load8_u (i32.const {REGISTRY_OFFSET}))) (then {call}))"
+                ));
+            }
+
+            "assert_eq" | "assert_ne" => {
+                let l = self.read(f, &args[0], Ty::I32)?;
+                let r = self.read(f, &args[1], Ty::I32)?;
+                let op = if name == "assert_eq" { "ne" } else { "eq" };
+                let call = match &args[2] {
+                    Op::String(msg) => {
+                        format!("(call $fail_values {} {l} {r})", self.string(msg))
+                    }
+                    _ => self.fail_call("Assertion message must be a string"),
+                };
+                f.line(&format!("(if (i64.{op} {l} {r}) (then {call}))"));
+            }
+
+            _ => {
+                self.fail(f, &format!("Command: {name} unrecognized"));
+            }
+        }
+
+        Ok(self.finish(name, span, &func))
+    }
+
+    // Generates the function for the command `name` out of `func`, returning
+    // its name
+    fn finish(&mut self, name: &str, span: Span, func: &Func) -> String {
+        let name_ref = format!("$cmd{}", self.funcs.len());
+        let at = self.string(&span.to_string());
+        self.funcs.push(format!(
+            ";; {name} at {span}\n  (func {name_ref} (result i32){}\n    (global.set $cmd_val (i64.const 0))\n    (call $set_at {at})\n{}    (i32.const 0))\n",
+            func.locals(),
+            func.body
+        ));
+        name_ref
+    }
+
+    // Generates a function running `op`, returning its name. `top_level` is set
+    // for ops directly inside of the program
+    fn op(&mut self, op: &Op, top_level: bool) -> Result<String> {
+        match op {
+            Op::Cmd(name, args, span) => {
+                let cmd = self.cmd(name, args, *span)?;
+                if !top_level {
+                    return Ok(cmd);
+                }
+
+                // Commands outside of any branch forget where jumps came from
+                let func = format!("$top{}", self.funcs.len());
+                self.funcs.push(format!(
+                    "(func {func} (result i32)\n    (global.set $jumps_len (i32.const 0))\n    (call {cmd}))\n"
+                ));
+                Ok(func)
+            }
+
+            Op::Branch(name, ops) => {
+                let mut funcs = Vec::new();
+                for op in ops {
+                    funcs.push(self.op(op, false)?);
+                }
+
+                let func = format!("$branch{}", self.funcs.len());
+                self.funcs.push(format!(
+                    ";; {name}\n  (func {func} (result i32) (local $i i64) (local $jumped i32)\n    (local.set $i (global.get $pos1))\n    (block $done (loop $next\n      (br_if $done (i64.ge_u (local.get $i) (i64.const {})))\n{}      (if (local.get $jumped) (then (return (i32.const 1))))\n      (global.set $pos1 (i64.add (global.get $pos1) (i64.const 1)))\n      (local.set $i (i64.add (local.get $i) (i64.const 1)))\n      (br $next)))\n    (i32.const 0))\n",
+                    ops.len(),
+                    switch("$i", &funcs)
+                ));
+                Ok(func)
+            }
+
+            #[cfg(feature = "inline-python")]
+            Op::InlinePy(_) => err!("Inline python can't be compiled to WebAssembly"),
+
+            _ => {
+                let mut func = Func::default();
+                self.fail(&mut func, &format!("Invalid top-level op: {op:?}"));
+                let name = format!("$invalid{}", self.funcs.len());
+                self.funcs.push(format!(
+                    "(func {name} (result i32)\n{}    (i32.const 0))\n",
+                    func.body
+                ));
+                Ok(name)
+            }
+        }
+    }
+
+    // The functions every module shares. It mirrors `utils/mem.rs` byte for
+    // byte: registers, then the flags byte, then the stack, and the cell
+    // memory after that
+    fn prelude(&mut self, ops: usize, is_branch: usize, heap: usize, jumps: usize) -> String {
+        let u8_fail = self.fail_call("Could not convert value to type <u8>");
+        let i16_fail = self.fail_call("Could not convert value to type <i16>");
+        let i32_fail = self.fail_call("Could not convert value to type <i32>");
+        let usize_fail = self.fail_call("Could not convert value to type <usize>");
+        let bounds_fail = self.fail_call("Memory access out of bounds");
+        let full_fail = self.fail_call("The stack is full");
+        let empty_fail = self.fail_call("Attempted to pop from empty stack");
+        let sp_fail = self.fail_call("The stack pointer has to stay inside of the stack");
+        let stack_fail = self.fail_call("Stack access out of bounds");
+        let frame_fail = self.fail_call("There is no frame to leave");
+        let fit_fail = self.fail_call("The stack does not fit into memory");
+        let resize_fail = self.fail_call("The stack can't be resized while memory is allocated");
+        let free_fail = format!(
+            "(call $fail_value {} (local.get $pos))",
+            self.string("Address {} is not the start of an allocated block")
+        );
+        let oom_fail = self.fail_call("Out of memory");
+        let expected_fail = self.fail_call("Expected an integer in the input");
+        let range_fail = self.fail_call("Integer in the input does not fit into <i32>");
+        let float_fail = self.fail_call("Float does not fit into <i32>");
+        let ret_fail = self.fail_call("Cannot return");
+        let end_fail = self.fail_call("Returned past the end of the program");
+        let stack_max = MEM_SIZE - OFFSET;
+        let heap_free = heap + 4 * MEM_SIZE;
+
+        format!(
+            r#"(import "arsm" "out" (func $host_out (param i32)))
+  (import "arsm" "chr" (func $host_chr (param i32)))
+  (import "arsm" "fout" (func $host_fout (param f64 i32)))
+  (import "arsm" "err" (func $host_err (param i32)))
+  (import "arsm" "errc" (func $host_errc (param i32)))
+  (import "arsm" "in" (func $host_in (result i32)))
+  (import "arsm" "fail" (func $host_fail (param i32 i32 i32 i32)))
+  (import "arsm" "fail_value" (func $host_fail_value (param i32 i32 i32 i32 i32)))
+  (import "arsm" "fail_values" (func $host_fail_values (param i32 i32 i32 i32 i32 i32)))
+
+  (global $s_size (mut i32) (i32.const 0))
+  (global $s_len (mut i32) (i32.const 0))
+
+  ;; Where execution is, as (top-level op, op inside of its branch), where the
+  ;; last jump went, and how many return points are saved at {jumps}
+  (global $pos0 (mut i64) (i64.const 0))
+  (global $pos1 (mut i64) (i64.const 0))
+  (global $target (mut i64) (i64.const 0))
+  (global $jumps_len (mut i32) (i32.const 0))
+
+  (global $halted (mut i32) (i32.const 0))
+  (global $exit_code (mut i32) (i32.const 0))
+  (global $cmd_val (mut i64) (i64.const 0))
+  (global $at (mut i32) (i32.const 0))
+  (global $at_len (mut i32) (i32.const 0))
+  (global $peeked (mut i32) (i32.const -2))
+
+  (func $set_at (param $at i32) (param $len i32)
+    (global.set $at (local.get $at))
+    (global.set $at_len (local.get $len)))
+
+  (func $fail (param $msg i32) (param $len i32)
+    (call $host_fail (global.get $at) (global.get $at_len) (local.get $msg) (local.get $len))
+    unreachable)
+
+  (func $fail_value (param $msg i32) (param $len i32) (param $value i64)
+    (call $host_fail_value (global.get $at) (global.get $at_len) (local.get $msg) (local.get $len)
+      (i32.wrap_i64 (local.get $value)))
+    unreachable)
+
+  (func $fail_values (param $msg i32) (param $len i32) (param $left i64) (param $right i64)
+    (call $host_fail_values (global.get $at) (global.get $at_len) (local.get $msg) (local.get $len)
+      (i32.wrap_i64 (local.get $left)) (i32.wrap_i64 (local.get $right)))
+    unreachable)
+
+  (func $as_u8 (param $v i64) (result i64)
+    (if (i64.gt_u (local.get $v) (i64.const 255)) (then {u8_fail}))
+    (local.get $v))
+
+  (func $as_i16 (param $v i64) (result i64)
+    (if (i64.gt_u (i64.add (local.get $v) (i64.const 32768)) (i64.const 65535)) (then {i16_fail}))
+    (local.get $v))
+
+  (func $as_i32 (param $v i64) (result i64)
+    (if (i64.ne (local.get $v) (call $wrap (local.get $v))) (then {i32_fail}))
+    (local.get $v))
+
+  (func $as_usize (param $v i64) (result i64)
+    (if (i64.lt_s (local.get $v) (i64.const 0)) (then {usize_fail}))
+    (local.get $v))
+
+  (func $wrap (param $v i64) (result i64)
+    (i64.extend_i32_s (i32.wrap_i64 (local.get $v))))
+
+  (func $checked (param $v i64) (param $msg i32) (param $len i32) (result i64)
+    (if (i64.ne (local.get $v) (call $wrap (local.get $v)))
+      (then (call $fail (local.get $msg) (local.get $len))))
+    (local.get $v))
+
+  (func $shift (param $r i64) (param $msg i32) (param $len i32) (result i64)
+    (if (i64.gt_u (local.get $r) (i64.const 31))
+      (then (call $fail (local.get $msg) (local.get $len))))
+    (local.get $r))
+
+  ;; Rounds towards zero, like casting does, but fails instead of overflowing
+  (func $f_to_i32 (param $v f64) (result i64)
+    (if (i32.eqz (i32.and (f64.gt (local.get $v) (f64.const -2147483649))
+        (f64.lt (local.get $v) (f64.const 2147483648))))
+      (then {float_fail}))
+    (i64.trunc_f64_s (local.get $v)))
+
+  (func $reg8 (param $a i32) (result i64)
+    (i64.load8_u (local.get $a)))
+
+  (func $reg16 (param $a i32) (result i64)
+    (i64.load16_s (local.get $a)))
+
+  (func $reg32 (param $a i32) (param $b i32) (result i64)
+    (i64.extend_i32_s (i32.or (i32.load16_u (local.get $a))
+      (i32.shl (i32.load16_u (local.get $b)) (i32.const 16)))))
+
+  (func $set_reg8 (param $a i32) (param $v i64)
+    (i64.store8 (local.get $a) (local.get $v)))
+
+  (func $set_reg16 (param $a i32) (param $v i64)
+    (i64.store16 (local.get $a) (local.get $v)))
+
+  (func $set_reg32 (param $a i32) (param $b i32) (param $v i64)
+    (i64.store16 (local.get $a) (local.get $v))
+    (i64.store16 (local.get $b) (i64.shr_u (local.get $v) (i64.const 16))))
+
+  (func $cell (param $pos i64) (param $len i32) (result i32) (local $start i32)
+    (local.set $start (i32.add (i32.const {OFFSET}) (global.get $s_size)))
+    (if (i32.or (i64.ge_u (local.get $pos) (i64.const {MEM_SIZE}))
+        (i32.gt_u (i32.add (i32.add (local.get $start) (i32.wrap_i64 (local.get $pos))) (local.get $len))
+          (i32.const {MEM_SIZE})))
+      (then {bounds_fail}))
+    (i32.add (local.get $start) (i32.wrap_i64 (local.get $pos))))
+
+  (func $m_read8 (param $pos i64) (result i64)
+    (i64.load8_u (call $cell (local.get $pos) (i32.const 1))))
+
+  (func $m_read16 (param $pos i64) (result i64)
+    (i64.load16_s (call $cell (local.get $pos) (i32.const 2))))
+
+  (func $m_read32 (param $pos i64) (result i64)
+    (i64.load32_s (call $cell (local.get $pos) (i32.const 4))))
+
+  (func $m_write8 (param $pos i64) (param $v i64)
+    (i64.store8 (call $cell (local.get $pos) (i32.const 1)) (local.get $v)))
+
+  (func $m_write16 (param $pos i64) (param $v i64)
+    (i64.store16 (call $cell (local.get $pos) (i32.const 2)) (local.get $v)))
+
+  (func $m_write32 (param $pos i64) (param $v i64)
+    (i64.store32 (call $cell (local.get $pos) (i32.const 4)) (local.get $v)))
+
+  (func $s_push (param $len i32) (param $v i64) (local $a i32)
+    (if (i32.gt_u (i32.add (global.get $s_len) (local.get $len)) (global.get $s_size)) (then {full_fail}))
+    (local.set $a (i32.add (i32.const {OFFSET}) (global.get $s_len)))
+    (if (i32.eq (local.get $len) (i32.const 1))
+      (then (i64.store8 (local.get $a) (local.get $v)))
+      (else (if (i32.eq (local.get $len) (i32.const 2))
+        (then (i64.store16 (local.get $a) (local.get $v)))
+        (else (i64.store32 (local.get $a) (local.get $v))))))
+    (global.set $s_len (i32.add (global.get $s_len) (local.get $len))))
+
+  (func $set_sp (param $v i64)
+    (if (i64.gt_u (local.get $v) (i64.extend_i32_u (global.get $s_size))) (then {sp_fail}))
+    (block $done (loop $next
+      (br_if $done (i32.le_u (global.get $s_len) (i32.wrap_i64 (local.get $v))))
+      (global.set $s_len (i32.sub (global.get $s_len) (i32.const 1)))
+      (i32.store8 (i32.add (i32.const {OFFSET}) (global.get $s_len)) (i32.const 0))
+      (br $next)))
+    (global.set $s_len (i32.wrap_i64 (local.get $v))))
+
+  ;; Where the `$len` bytes `$pos` bytes above the bottom of the stack are,
+  ;; failing unless all of them are on the stack
+  (func $s_at (param $pos i64) (param $len i32) (result i32)
+    (if (i32.or (i64.lt_s (local.get $pos) (i64.const 0))
+        (i64.gt_s (i64.add (local.get $pos) (i64.extend_i32_u (local.get $len)))
+          (i64.extend_i32_u (global.get $s_len))))
+      (then {stack_fail}))
+    (i32.add (i32.const {OFFSET}) (i32.wrap_i64 (local.get $pos))))
+
+  (func $s_read (param $pos i64) (param $len i32) (result i64) (local $a i32)
+    (local.set $a (call $s_at (local.get $pos) (local.get $len)))
+    (if (result i64) (i32.eq (local.get $len) (i32.const 1))
+      (then (i64.load8_u (local.get $a)))
+      (else (if (result i64) (i32.eq (local.get $len) (i32.const 2))
+        (then (i64.load16_s (local.get $a)))
+        (else (i64.load32_s (local.get $a)))))))
+
+  (func $s_write (param $pos i64) (param $len i32) (param $v i64) (local $a i32)
+    (local.set $a (call $s_at (local.get $pos) (local.get $len)))
+    (if (i32.eq (local.get $len) (i32.const 1))
+      (then (i64.store8 (local.get $a) (local.get $v)))
+      (else (if (i32.eq (local.get $len) (i32.const 2))
+        (then (i64.store16 (local.get $a) (local.get $v)))
+        (else (i64.store32 (local.get $a) (local.get $v)))))))
+
+  (func $enter (param $len i64)
+    (if (i64.gt_u (i64.add (i64.extend_i32_u (i32.add (global.get $s_len) (i32.const 2))) (local.get $len))
+        (i64.extend_i32_u (global.get $s_size)))
+      (then {full_fail}))
+    (call $s_push (i32.const 2) (call $reg16 (i32.const {FRAME_POINTER})))
+    (call $set_reg16 (i32.const {FRAME_POINTER}) (i64.extend_i32_u (global.get $s_len)))
+    (call $set_sp (i64.add (i64.extend_i32_u (global.get $s_len)) (local.get $len))))
+
+  (func $leave (local $fp i64)
+    (local.set $fp (call $reg16 (i32.const {FRAME_POINTER})))
+    (if (i32.or (i64.lt_s (local.get $fp) (i64.const 2))
+        (i64.gt_s (local.get $fp) (i64.extend_i32_u (global.get $s_len))))
+      (then {frame_fail}))
+    (call $set_sp (local.get $fp))
+    (call $set_reg16 (i32.const {FRAME_POINTER}) (call $s_pop (i32.const 2))))
+
+  (func $s_pop (param $len i32) (result i64) (local $a i32) (local $v i64)
+    (if (i32.lt_u (global.get $s_len) (local.get $len)) (then {empty_fail}))
+    (global.set $s_len (i32.sub (global.get $s_len) (local.get $len)))
+    (local.set $a (i32.add (i32.const {OFFSET}) (global.get $s_len)))
+    (if (i32.eq (local.get $len) (i32.const 1))
+      (then
+        (local.set $v (i64.load8_u (local.get $a)))
+        (i32.store8 (local.get $a) (i32.const 0)))
+      (else (if (i32.eq (local.get $len) (i32.const 2))
+        (then
+          (local.set $v (i64.load16_s (local.get $a)))
+          (i32.store16 (local.get $a) (i32.const 0)))
+        (else
+          (local.set $v (i64.load32_s (local.get $a)))
+          (i32.store (local.get $a) (i32.const 0))))))
+    (local.get $v))
+
+  ;; The heap, like `utils/heap.rs` keeps it. For every cell, {heap} has how
+  ;; long the block starting there is (0 if none does) and {heap_free} whether
+  ;; it is free. The blocks cover the last `$heap_len` cells
+  (global $heap_len (mut i32) (i32.const 0))
+
+  (func $block_len (param $p i32) (result i32)
+    (i32.load (i32.add (i32.const {heap}) (i32.shl (local.get $p) (i32.const 2)))))
+
+  (func $block_free (param $p i32) (result i32)
+    (i32.load8_u (i32.add (i32.const {heap_free}) (local.get $p))))
+
+  (func $set_block (param $p i32) (param $len i32) (param $free i32)
+    (i32.store (i32.add (i32.const {heap}) (i32.shl (local.get $p) (i32.const 2))) (local.get $len))
+    (i32.store8 (i32.add (i32.const {heap_free}) (local.get $p)) (local.get $free)))
+
+  (func $heap_alloc (param $len i64) (result i64)
+      (local $top i32) (local $n i32) (local $p i32) (local $l i32) (local $i i32)
+    (local.set $top (i32.sub (i32.const {stack_max}) (global.get $s_size)))
+    (if (i64.gt_u (local.get $len) (i64.extend_i32_u (local.get $top)))
+      (then (return (i64.const -1))))
+    (local.set $n (select (i32.wrap_i64 (local.get $len)) (i32.const 1)
+      (i64.gt_u (local.get $len) (i64.const 0))))
+    (local.set $p (i32.sub (local.get $top) (global.get $heap_len)))
+    (block $found (loop $find
+      (br_if $found (i32.ge_u (local.get $p) (local.get $top)))
+      (local.set $l (call $block_len (local.get $p)))
+      (br_if $found (i32.and (call $block_free (local.get $p))
+        (i32.ge_u (local.get $l) (local.get $n))))
+      (local.set $p (i32.add (local.get $p) (local.get $l)))
+      (br $find)))
+    (if (i32.lt_u (local.get $p) (local.get $top))
+      (then
+        (if (i32.gt_u (local.get $l) (local.get $n))
+          (then (call $set_block (i32.add (local.get $p) (local.get $n))
+            (i32.sub (local.get $l) (local.get $n)) (i32.const 1)))))
+      (else
+        (if (i32.gt_u (i32.add (global.get $heap_len) (local.get $n)) (local.get $top))
+          (then (return (i64.const -1))))
+        (global.set $heap_len (i32.add (global.get $heap_len) (local.get $n)))
+        (local.set $p (i32.sub (local.get $top) (global.get $heap_len)))))
+    (call $set_block (local.get $p) (local.get $n) (i32.const 0))
+    (local.set $i (i32.add (i32.add (i32.const {OFFSET}) (global.get $s_size)) (local.get $p)))
+    (local.set $l (i32.add (local.get $i) (local.get $n)))
+    (block $done (loop $zero
+      (br_if $done (i32.ge_u (local.get $i) (local.get $l)))
+      (i32.store8 (local.get $i) (i32.const 0))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $zero)))
+    (i64.extend_i32_u (local.get $p)))
+
+  (func $heap_free (param $pos i64)
+      (local $top i32) (local $prev i32) (local $p i32) (local $len i32) (local $next i32)
+    (local.set $top (i32.sub (i32.const {stack_max}) (global.get $s_size)))
+    (local.set $prev (local.get $top))
+    (local.set $p (i32.sub (local.get $top) (global.get $heap_len)))
+    (block $done (loop $find
+      (br_if $done (i32.or (i32.ge_u (local.get $p) (local.get $top))
+        (i64.ge_u (i64.extend_i32_u (local.get $p)) (local.get $pos))))
+      (local.set $prev (local.get $p))
+      (local.set $p (i32.add (local.get $p) (call $block_len (local.get $p))))
+      (br $find)))
+    (if (i32.or (i32.or (i32.ge_u (local.get $p) (local.get $top))
+          (i64.ne (i64.extend_i32_u (local.get $p)) (local.get $pos)))
+        (call $block_free (local.get $p)))
+      (then {free_fail}))
+
+    (local.set $len (call $block_len (local.get $p)))
+    (local.set $next (i32.add (local.get $p) (local.get $len)))
+    (if (i32.and (i32.lt_u (local.get $next) (local.get $top)) (call $block_free (local.get $next)))
+      (then
+        (local.set $len (i32.add (local.get $len) (call $block_len (local.get $next))))
+        (call $set_block (local.get $next) (i32.const 0) (i32.const 0))))
+    (if (i32.and (i32.lt_u (local.get $prev) (local.get $top)) (call $block_free (local.get $prev)))
+      (then
+        (call $set_block (local.get $p) (i32.const 0) (i32.const 0))
+        (local.set $p (local.get $prev))
+        (local.set $len (i32.add (local.get $len) (call $block_len (local.get $prev))))))
+
+    ;; Free space at the bottom of the heap goes back to the cell memory
+    (if (i32.eq (local.get $p) (i32.sub (local.get $top) (global.get $heap_len)))
+      (then
+        (call $set_block (local.get $p) (i32.const 0) (i32.const 0))
+        (global.set $heap_len (i32.sub (global.get $heap_len) (local.get $len))))
+      (else (call $set_block (local.get $p) (local.get $len) (i32.const 1)))))
+
+  (func $resize_stack (param $size i64) (local $i i32)
+    (if (i32.and (i64.ne (local.get $size) (i64.extend_i32_u (global.get $s_size)))
+        (i32.ne (global.get $heap_len) (i32.const 0)))
+      (then {resize_fail}))
+    (if (i64.gt_u (local.get $size) (i64.const {stack_max})) (then {fit_fail}))
+    (local.set $i (global.get $s_size))
+    (block $done (loop $zero
+      (br_if $done (i32.ge_u (local.get $i) (i32.wrap_i64 (local.get $size))))
+      (i32.store8 (i32.add (i32.const {OFFSET}) (local.get $i)) (i32.const 0))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $zero)))
+    (global.set $s_size (i32.wrap_i64 (local.get $size)))
+    (if (i32.gt_u (global.get $s_len) (global.get $s_size))
+      (then (global.set $s_len (global.get $s_size)))))
+
+  ;; The next byte of input without reading it, or -1 once there is none
+  ;; left. -2 in `$peeked` means nothing was peeked at yet
+  (func $peek_in (result i32)
+    (if (i32.eq (global.get $peeked) (i32.const -2))
+      (then (global.set $peeked (call $host_in))))
+    (global.get $peeked))
+
+  ;; Like `in`, 0 once there is none left
+  (func $read_in (result i32) (local $v i32)
+    (local.set $v (call $peek_in))
+    (global.set $peeked (i32.const -2))
+    (select (i32.const 0) (local.get $v) (i32.lt_s (local.get $v) (i32.const 0))))
+
+  (func $is_space (param $c i32) (result i32)
+    (i32.or (i32.or (i32.eq (local.get $c) (i32.const 32)) (i32.eq (local.get $c) (i32.const 9)))
+      (i32.or (i32.eq (local.get $c) (i32.const 10))
+        (i32.or (i32.eq (local.get $c) (i32.const 12)) (i32.eq (local.get $c) (i32.const 13))))))
+
+  (func $read_int (result i64) (local $neg i32) (local $digits i32) (local $v i64) (local $c i32)
+    (block $done (loop $skip
+      (br_if $done (i32.eqz (call $is_space (call $peek_in))))
+      (drop (call $read_in))
+      (br $skip)))
+    (if (i32.lt_s (call $peek_in) (i32.const 0))
+      (then
+        (i32.store8 (i32.const {REGISTRY_OFFSET}) (i32.const 0))
+        (return (i64.const 0))))
+
+    (local.set $c (call $peek_in))
+    (if (i32.or (i32.eq (local.get $c) (i32.const 45)) (i32.eq (local.get $c) (i32.const 43)))
+      (then
+        (local.set $neg (i32.eq (local.get $c) (i32.const 45)))
+        (drop (call $read_in))))
+    (block $done (loop $digit
+      (local.set $c (i32.sub (call $peek_in) (i32.const 48)))
+      (br_if $done (i32.gt_u (local.get $c) (i32.const 9)))
+      (drop (call $read_in))
+      (local.set $digits (i32.const 1))
+      (if (i64.le_u (local.get $v) (i64.const 2147483648))
+        (then (local.set $v (i64.add (i64.mul (local.get $v) (i64.const 10))
+          (i64.extend_i32_u (local.get $c))))))
+      (br $digit)))
+    (if (i32.eqz (local.get $digits)) (then {expected_fail}))
+    (if (i64.gt_u (local.get $v) (i64.add (i64.const 2147483647) (i64.extend_i32_u (local.get $neg))))
+      (then {range_fail}))
+
+    (i32.store8 (i32.const {REGISTRY_OFFSET}) (i32.const 1))
+    (if (result i64) (local.get $neg)
+      (then (i64.sub (i64.const 0) (local.get $v)))
+      (else (local.get $v))))
+
+  (func $read_line (param $start i64) (param $max i64) (result i64) (local $len i64)
+    (i32.store8 (i32.const {REGISTRY_OFFSET}) (i32.ge_s (call $peek_in) (i32.const 0)))
+    (block $done (loop $next
+      (br_if $done (i64.ge_u (local.get $len) (local.get $max)))
+      (br_if $done (i32.lt_s (call $peek_in) (i32.const 0)))
+      (br_if $done (i32.eq (call $peek_in) (i32.const 10)))
+      (call $m_write8 (i64.add (local.get $start) (local.get $len)) (i64.extend_i32_u (call $read_in)))
+      (local.set $len (i64.add (local.get $len) (i64.const 1)))
+      (br $next)))
+    (if (i32.eq (call $peek_in) (i32.const 10)) (then (drop (call $read_in))))
+    (call $m_write8 (i64.add (local.get $start) (local.get $len)) (i64.const 0))
+    (local.get $len))
+
+  ;; Prints `v` in `base`, with enough zeros in front to make it at least
+  ;; `width` digits long
+  (func $put_base (param $v i64) (param $base i64) (param $width i64) (local $d i32)
+    (if (i64.ge_u (local.get $v) (local.get $base))
+      (then (call $put_base (i64.div_u (local.get $v) (local.get $base)) (local.get $base)
+        (i64.sub (local.get $width) (i64.const 1))))
+      (else (block $done (loop $pad
+        (br_if $done (i64.le_s (local.get $width) (i64.const 1)))
+        (call $host_chr (i32.const 48))
+        (local.set $width (i64.sub (local.get $width) (i64.const 1)))
+        (br $pad)))))
+    (local.set $d (i32.wrap_i64 (i64.rem_u (local.get $v) (local.get $base))))
+    (call $host_chr (i32.add (local.get $d)
+      (select (i32.const 48) (i32.const 87) (i32.lt_u (local.get $d) (i32.const 10))))))
+
+  (func $put_padded (param $v i64) (param $width i64) (local $len i64) (local $u i64)
+    (local.set $len (i64.extend_i32_u (i64.lt_s (local.get $v) (i64.const 0))))
+    (local.set $u (select (i64.sub (i64.const 0) (local.get $v)) (local.get $v) (i64.lt_s (local.get $v) (i64.const 0))))
+    (block $done (loop $digit
+      (local.set $len (i64.add (local.get $len) (i64.const 1)))
+      (local.set $u (i64.div_u (local.get $u) (i64.const 10)))
+      (br_if $digit (i64.ne (local.get $u) (i64.const 0)))))
+    (block $done (loop $pad
+      (br_if $done (i64.ge_s (local.get $len) (local.get $width)))
+      (call $host_chr (i32.const 32))
+      (local.set $len (i64.add (local.get $len) (i64.const 1)))
+      (br $pad)))
+    (call $host_out (i32.wrap_i64 (local.get $v))))
+
+  (func $set_ind (param $n i64) (local $a i32)
+    (local.set $a (i32.add (i32.const {jumps}) (i32.shl (global.get $jumps_len) (i32.const 4))))
+    (if (i32.gt_u (i32.add (local.get $a) (i32.const 16)) (i32.mul (memory.size) (i32.const {PAGE_SIZE})))
+      (then (if (i32.eq (memory.grow (i32.const 1)) (i32.const -1)) (then {oom_fail}))))
+    (i64.store (local.get $a) (global.get $pos0))
+    (i64.store offset=8 (local.get $a) (global.get $pos1))
+    (global.set $jumps_len (i32.add (global.get $jumps_len) (i32.const 1)))
+    (global.set $target (local.get $n))
+    (global.set $pos0 (local.get $n))
+    (global.set $pos1 (i64.const 0)))
+
+  (func $ret (result i32) (local $a i32) (local $left i64) (local $right i64)
+    (if (i32.eqz (global.get $jumps_len)) (then {ret_fail}))
+    (global.set $jumps_len (i32.sub (global.get $jumps_len) (i32.const 1)))
+    (local.set $a (i32.add (i32.const {jumps}) (i32.shl (global.get $jumps_len) (i32.const 4))))
+    (local.set $left (i64.load (local.get $a)))
+    (local.set $right (i64.load offset=8 (local.get $a)))
+    (if (i64.ge_u (local.get $left) (i64.const {ops})) (then {end_fail}))
+    (if (i32.load8_u (i32.add (i32.const {is_branch}) (i32.wrap_i64 (local.get $left))))
+      (then
+        (global.set $pos0 (local.get $left))
+        (global.set $pos1 (i64.add (local.get $right) (i64.const 1))))
+      (else
+        (local.set $left (i64.add (local.get $left) (i64.const 1)))
+        (global.set $pos0 (local.get $left))
+        (global.set $pos1 (local.get $right))))
+    (global.set $target (local.get $left))
+    (i32.const 1))
+"#
+        )
+    }
+}
+
+// Calls the function at index `ind` in `funcs`, leaving whether it jumped in
+// `$jumped`. Written as flat instructions, since `br_table` needs one block per
+// case and folding them would nest as deep as there are cases
+fn switch(ind: &str, funcs: &[String]) -> String {
+    let mut res = String::from("      block $switch\n");
+    for i in (0..funcs.len()).rev() {
+        let _ = writeln!(res, "      block $case{i}");
+    }
+
+    let targets = (0..funcs.len()).fold(String::new(), |mut res, i| {
+        let _ = write!(res, "$case{i} ");
+        res
+    });
+    let _ = writeln!(
+        res,
+        "      (br_table {targets}$switch (i32.wrap_i64 (local.get {ind})))"
+    );
+    for (i, func) in funcs.iter().enumerate() {
+        let _ = writeln!(
+            res,
+            "      end\n      (local.set $jumped (call {func}))\n      (br $switch)"
+        );
+        if i + 1 == funcs.len() {
+            res.push_str("      end\n");
+        }
+    }
+    if funcs.is_empty() {
+        res.push_str("      end\n");
+    }
+    res
+}
+
+// Translates `tree` into a WebAssembly text module, which behaves exactly like
+// running the tree would. Its linear memory holds the registers, flags, stack
+// and cells at the same offsets the interpreter uses, and it talks to the
+// outside world through the functions it imports from `arsm`:
+//   out(i32)          - prints a number
+//   chr(i32)          - prints a character
+//   fout(f64, i32)    - prints a float with that many digits after the point
+//   in() -> i32       - reads a byte of input, or 0 once there is none left
+//   fail(at, msg)     - reports an error (both are (address, length) strings)
+//   fail_value(at, msg, value) - like `fail`, with `value` in place of `{}`
+//   fail_values(at, msg, left, right) - reports a failed `assert_eq`/`assert_ne`
+// The exported `main` runs the program and returns its exit code
+pub fn emit(tree: &[Op]) -> Result<String> {
+    let mut gen = Gen {
+        tree,
+        funcs: Vec::new(),
+        data: Vec::new(),
+        strings: HashMap::new(),
+    };
+
+    let mut funcs = Vec::new();
+    for op in tree {
+        funcs.push(gen.op(op, true)?);
+    }
+
+    let is_branch = MEM_SIZE + gen.data.len();
+    let ops = tree.len();
+    gen.data.extend(
+        tree.iter()
+            .map(|op| u8::from(matches!(op, Op::Branch(..))))
+            .chain(std::iter::once(0)),
+    );
+    // The prelude's messages go into the data segment too, so it has to be
+    // generated once before the return points can be placed after it
+    gen.prelude(ops, is_branch, 0, 0);
+    // The heap's blocks take 4 bytes for their length and 1 for whether they
+    // are free, for every cell
+    let heap = (MEM_SIZE + gen.data.len() + 15) & !15;
+    let jumps = heap + 5 * MEM_SIZE;
+    let prelude = gen.prelude(ops, is_branch, heap, jumps);
+    let pages = (jumps + 16).div_ceil(PAGE_SIZE);
+
+    let mut res = format!(
+        ";; Generated by `arsm emit-wat`\n(module\n  {prelude}\n  (memory (export \"memory\") {pages})\n  (data (i32.const {MEM_SIZE}) {})\n",
+        wat_str(&gen.data)
+    );
+    for func in &gen.funcs {
+        res.push_str("\n  ");
+        res.push_str(func);
+    }
+
+    let _ = write!(
+        res,
+        "
+  (func (export \"main\") (result i32) (local $ind i64) (local $jumped i32)
+    (block $done (loop $next
+      (br_if $done (i64.ge_u (local.get $ind) (i64.const {ops})))
+      (local.set $jumped (i32.const 0))
+{}      (if (local.get $jumped)
+        (then (local.set $ind (global.get $target)))
+        (else
+          (local.set $ind (i64.add (local.get $ind) (i64.const 1)))
+          (global.set $pos0 (i64.add (global.get $pos0) (i64.const 1)))))
+      (br_if $done (global.get $halted))
+      (br $next)))
+    (global.get $exit_code))
+)
+",
+        switch("$ind", &funcs)
+    );
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod emit_wat_tests {
+    use super::*;
+    use crate::utils::traits::FloatRegister;
+
+    fn emit_program(program: &str) -> String {
+        emit(&crate::compile(program).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_layout() {
+        let res = emit_program("ceq 1 1\ncou 2");
+        assert!(res.contains(&format!("(i32.const {OFFSET}) (global.get $s_size)")));
+        assert!(res.contains(&format!("(i32.load8_u (i32.const {REGISTRY_OFFSET}))")));
+        assert!(res.contains("(memory (export \"memory\") 1)"));
+    }
+
+    #[test]
+    fn test_registers() {
+        let res = emit_program("mov abx 70000\nmov ch 3\nmov dl 4\nmov ex 5");
+        assert!(res.contains("(call $set_reg32 (i32.const 0) (i32.const 2) "));
+        assert!(res.contains("(call $set_reg8 (i32.const 5) "));
+        assert!(res.contains("(call $set_reg8 (i32.const 6) "));
+        assert!(res.contains("(call $set_reg16 (i32.const 8) "));
+    }
+
+    #[test]
+    fn test_errors_at_runtime() {
+        // Mistakes only fail once (and if) they are reached, like in the parser
+        let res = emit_program("out 1\njmp :missing");
+        assert!(res.contains("No matching branch for label :missing"));
+    }
+
+    #[test]
+    fn test_overflow() {
+        // Math fails with the same messages as the interpreter instead of wrapping
+        let res = emit_program("inc abx\nmul ax 3\nlsh ax bx");
+        assert!(res.contains("(call $checked (i64.add "));
+        assert!(res.contains("(call $checked (i64.mul "));
+        assert!(res.contains("(call $shift "));
+        assert!(res.contains("attempt to shift left with overflow"));
+        wat::parse_str(&res).unwrap();
+    }
+
+    #[test]
+    fn test_valid_module() {
+        let res = emit_program(".loop\n    out 1\n    jmp :loop\n.\n.empty\n.\nret\nhlt 2");
+        wat::parse_str(&res).unwrap();
+    }
+
+    #[test]
+    fn test_floats() {
+        let res = emit_program("fmov f3 0.1\nfmul f3 2\nfout f3 2\nftoi ax f3\nfle f3 1.5");
+        assert!(res.contains(&format!(
+            "(f64.store (i32.const {}) (local.get $t0))",
+            FloatRegister(3).offset()
+        )));
+        wat::parse_str(&res).unwrap();
+    }
+
+    #[test]
+    fn test_frames() {
+        let res = emit_program("stk 8\nenter 2\nstf 2 0 5\nlds bl 1\nsts 1 0 bl\nldf ax 0\nleave");
+        assert!(res.contains("(call $enter "));
+        assert!(res.contains("(call $s_write "));
+        wat::parse_str(&res).unwrap();
+    }
+
+    #[test]
+    fn test_wat_str() {
+        assert_eq!(wat_str(b"a\"b\\\n"), "\"a\\\"b\\\\\\0a\"");
+    }
+}
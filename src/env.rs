@@ -1,14 +1,44 @@
+use std::collections::HashMap;
 use std::fmt::{self, Debug, Formatter};
+use std::io::Write;
 
+#[cfg(feature = "jit")]
+use crate::jit::{Compiled, Jit};
 #[cfg(feature = "inline-python")]
 use crate::utils::PyGuard;
-use crate::utils::{iter::BufIter, mem::Memory, token::Op};
+use crate::utils::{
+    consts::MEM_SIZE,
+    files::Files,
+    history::Frame,
+    input::Input,
+    mem::Memory,
+    output::{self, Output},
+    token::{Op, Span},
+};
 
 // Simple environment structure that holds the memory, stdin, and a few useful items
 pub struct Environment {
     pub mem: Memory,
 
-    pub stdin: BufIter<u8>,
+    // Where `in` and friends read from. Defaults to the process' stdin
+    pub stdin: Input,
+
+    // Where `out` and `chr` write to. Defaults to the process' stdout
+    pub out: Box<dyn Output>,
+
+    // Where `err` and `errc` write to. Defaults to the process' stderr
+    pub err: Box<dyn Output>,
+
+    // The files opened with `fop`, and where they may be opened
+    pub files: Files,
+
+    // What `argc` and `argv` get, starting with the program's path when run
+    // from the command line
+    pub args: Vec<String>,
+
+    // The environment variables `env` may look up. None are, unless they
+    // were allowed
+    pub vars: HashMap<String, String>,
 
     parent_ast: Option<Vec<Op>>,
 
@@ -16,18 +46,49 @@ pub struct Environment {
 
     pub pos: (usize, usize),
 
+    // Set by `hlt`, stops execution once it is `Some`
+    pub exit: Option<i32>,
+
+    // How many commands were run, and how many may be before the program is
+    // paused, right before the next one
+    pub steps: u64,
+    pub step_limit: Option<u64>,
+
+    // The state before every command that ran, while a history is kept
+    pub history: Option<Vec<Frame>>,
+
+    // Whether the program pauses right after a command that accessed one of
+    // the memory's watches. Otherwise, the accesses are logged to `err`
+    pub break_on_watch: bool,
+
+    // Compiles hot branches, see `jit.rs`
+    #[cfg(feature = "jit")]
+    pub jit: Jit,
+
     #[cfg(feature = "inline-python")]
     pub py: PyGuard,
 }
 
 impl Environment {
-    pub fn new(buf: &mut [u8]) -> Self {
+    pub fn new() -> Self {
         Self {
-            mem: Memory::init(1024, 0),
-            stdin: BufIter::new(buf),
+            mem: Memory::init(MEM_SIZE, 0),
+            stdin: Input::stdin(),
+            out: output::stdout(),
+            err: output::stderr(),
+            files: Files::default(),
+            args: Vec::new(),
+            vars: HashMap::new(),
             parent_ast: None,
             jump_point: Vec::with_capacity(5),
             pos: (0, 0),
+            exit: None,
+            steps: 0,
+            step_limit: None,
+            history: None,
+            break_on_watch: false,
+            #[cfg(feature = "jit")]
+            jit: Jit::new(),
             #[cfg(feature = "inline-python")]
             py: PyGuard::new(),
         }
@@ -35,32 +96,139 @@ impl Environment {
 
     pub fn set_parent(&mut self, ast: &[Op]) {
         self.parent_ast = Some(ast.to_owned());
+        #[cfg(feature = "jit")]
+        self.jit.reset();
+    }
+
+    pub fn get_parent(&self) -> Option<&[Op]> {
+        self.parent_ast.as_deref()
+    }
+
+    // Whether the program ran as many commands as it may, or accessed a
+    // watch it should pause for
+    pub fn paused(&self) -> bool {
+        self.step_limit.is_some_and(|limit| self.steps >= limit)
+            || (self.break_on_watch && self.mem.has_hits())
+    }
+
+    // Called right after every command runs, with where it is
+    pub fn end_step(&mut self, span: Span) -> std::io::Result<()> {
+        if self.break_on_watch || !self.mem.has_hits() {
+            return Ok(());
+        }
+        for hit in self.mem.take_hits() {
+            writeln!(
+                self.err,
+                "Watch: {} was {} by #{} at {span}",
+                hit.name, hit.access, self.steps
+            )?;
+        }
+        Ok(())
+    }
+
+    // Keeps a history of everything the program does from here on, so that
+    // it can be stepped back through
+    pub fn record_history(&mut self) {
+        self.history = Some(Vec::new());
+        self.mem.log = Some(Vec::new());
+        self.stdin.remember();
     }
 
-    pub fn get_parent(&self) -> &Option<Vec<Op>> {
-        &self.parent_ast
+    // Called right before every command runs
+    pub fn begin_step(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.push(Frame {
+                step: self.steps,
+                pos: self.pos,
+                changes: self.mem.log.as_ref().map_or(0, Vec::len),
+                s_size: self.mem.s_size,
+                s_len: self.mem.s_len,
+                heap: self.mem.heap.clone(),
+                jumps: self.jump_point.len(),
+                last_jump: self.jump_point.last().copied(),
+                stdin: self.stdin.consumed(),
+            });
+        }
+        self.steps += 1;
+    }
+
+    // Undoes the last command that ran, as if it never did. Returns false if
+    // there is none in the history. What it printed stays printed, and files
+    // it touched stay touched
+    pub fn step_back(&mut self) -> bool {
+        let Some(frame) = self.history.as_mut().and_then(Vec::pop) else {
+            return false;
+        };
+
+        self.mem.undo(frame.changes);
+        self.mem.s_size = frame.s_size;
+        self.mem.s_len = frame.s_len;
+        self.mem.heap = frame.heap;
+        self.jump_point.truncate(frame.jumps.saturating_sub(1));
+        self.jump_point.extend(frame.last_jump);
+        self.stdin.rewind(self.stdin.consumed() - frame.stdin);
+        self.pos = frame.pos;
+        self.steps = frame.step;
+        self.exit = None;
+        true
+    }
+
+    // Records that the branch `body` is being entered, returning its compiled
+    // code once it is hot. Compiled code accesses memory directly, past the
+    // heap's checks and the history, and runs many commands at once, which
+    // can't be paused between or watched, so nothing is compiled while any
+    // of those are used
+    #[cfg(feature = "jit")]
+    pub fn jit_branch(&mut self, body: &[Op]) -> Option<Compiled> {
+        if self.mem.heap.debug
+            || self.step_limit.is_some()
+            || self.history.is_some()
+            || !self.mem.watches.is_empty()
+        {
+            return None;
+        }
+        let parent = self.parent_ast.as_deref().unwrap_or(&[]);
+        self.jit.branch(body, parent)
     }
 
     // Only used by inline-python
     #[cfg(feature = "inline-python")]
     pub fn shallow_copy(&self) -> Self {
         Self {
-            mem: self.mem,
-            stdin: BufIter::new(&mut []),
+            mem: self.mem.clone(),
+            stdin: Input::from(Vec::new()),
+            out: Box::new(std::io::sink()),
+            err: Box::new(std::io::sink()),
+            files: Files::default(),
+            args: Vec::new(),
+            vars: HashMap::new(),
             parent_ast: None,
             jump_point: Vec::with_capacity(5),
             pos: (0, 0),
+            exit: None,
+            steps: 0,
+            step_limit: None,
+            history: None,
+            break_on_watch: false,
+            #[cfg(feature = "jit")]
+            jit: Jit::new(),
             #[cfg(feature = "inline-python")]
             py: PyGuard::new(),
         }
     }
 }
 
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Debug for Environment {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("Environment")
             .field("mem", &self.mem)
             .field("stdin", &self.stdin)
-            .finish()
+            .finish_non_exhaustive()
     }
 }
@@ -0,0 +1,337 @@
+use crate::utils::{
+    consts::COMMANDS,
+    error::Result,
+    token::{float_text, Node, Span},
+};
+use crate::{ast, lexer};
+
+const INDENT: &str = "    ";
+
+// A single line of formatted output, before indentation and alignment
+#[derive(Debug)]
+enum Line {
+    // `.name`
+    Open(String),
+
+    // `.`
+    Close,
+
+    // `..name`, closing the current branch and opening a new one
+    Reopen(String),
+
+    // A command and its operands, or a stray token on its own
+    Cmd(String, Vec<String>),
+
+    Comment(String),
+}
+
+#[derive(Debug)]
+struct Entry {
+    line: Line,
+
+    // The first and last source lines the entry was built from
+    start: usize,
+    end: usize,
+
+    // A comment sitting on the same line, after the entry
+    comment: Option<String>,
+}
+
+fn render(node: &Node) -> String {
+    match node {
+        Node::Keyword(name) | Node::Register(name) => name.to_lowercase(),
+        Node::Numeric(val) => val.to_string(),
+        Node::Float(val) => float_text(*val),
+        Node::String(str) => format!("\"{str}\""),
+        Node::Branch(name) => name.clone(),
+        Node::Punctuation(chr) => chr.to_string(),
+        Node::Char(chr) => format!("'{chr}"),
+        Node::Comment(text) => format!(";{text}"),
+        #[cfg(feature = "inline-python")]
+        Node::InlinePy(code) => format!("{{{code}}}"),
+    }
+}
+
+// The last source line `node` (starting at `span`) covers
+fn end_line(node: &Node, span: Span) -> usize {
+    match node {
+        Node::String(str) => span.line + str.matches('\n').count(),
+        #[cfg(feature = "inline-python")]
+        Node::InlinePy(code) => span.line + code.matches('\n').count(),
+        _ => span.line,
+    }
+}
+
+// Groups the token stream into lines, using the arity of each command to know
+// where its operands end
+struct Grouper<'a> {
+    nodes: &'a [(Node, Span)],
+    ind: usize,
+
+    // Comments found in the middle of the entry currently being built
+    comments: Vec<String>,
+    end: usize,
+}
+
+impl<'a> Grouper<'a> {
+    fn next_node(&mut self) -> Option<&'a (Node, Span)> {
+        while let Some(node) = self.nodes.get(self.ind) {
+            self.ind += 1;
+            self.end = self.end.max(end_line(&node.0, node.1));
+
+            match &node.0 {
+                Node::Comment(text) => self.comments.push(text.clone()),
+                _ => return Some(node),
+            }
+        }
+
+        None
+    }
+
+    fn operand(&mut self) -> String {
+        let Some((node, _)) = self.next_node() else {
+            return String::new();
+        };
+
+        match node {
+            Node::Keyword(name) => {
+                let count = COMMANDS.get(name).copied().unwrap_or(0);
+                let mut res = render(node);
+                for _ in 0..count {
+                    res.push(' ');
+                    res.push_str(&self.operand());
+                }
+                res
+            }
+
+            Node::Punctuation(chr @ ('#' | '$' | '@')) => {
+                // Skip the `[`, validation already ensured it is there
+                self.next_node();
+                let body = self.operand();
+                self.next_node();
+                format!("{chr}[{body}]")
+            }
+
+            _ => render(node),
+        }
+    }
+
+    fn entry(&mut self) -> Option<Entry> {
+        let &(ref node, span) = self.nodes.get(self.ind)?;
+        self.ind += 1;
+        self.end = end_line(node, span);
+        self.comments.clear();
+
+        let line = match node {
+            Node::Comment(text) => Line::Comment(text.clone()),
+
+            Node::Branch(name) if name == "." => match self.nodes.get(self.ind) {
+                Some((Node::Branch(next), at))
+                    if next.len() > 1
+                        && next.starts_with('.')
+                        && at.line == span.line
+                        && at.col == span.col + 1 =>
+                {
+                    self.ind += 1;
+                    Line::Reopen(next.clone())
+                }
+                _ => Line::Close,
+            },
+
+            Node::Branch(name) if name.starts_with('.') => Line::Open(name.clone()),
+
+            Node::Keyword(name) => {
+                let count = COMMANDS.get(name).copied().unwrap_or(0);
+                let operands = (0..count).map(|_| self.operand()).collect();
+                Line::Cmd(name.clone(), operands)
+            }
+
+            _ => Line::Cmd(render(node), Vec::new()),
+        };
+
+        let mut comment = if self.comments.is_empty() {
+            None
+        } else {
+            Some(self.comments.join(" ;"))
+        };
+
+        // Pick up a comment trailing on the same line
+        if comment.is_none() {
+            if let Some((Node::Comment(text), at)) = self.nodes.get(self.ind) {
+                if at.line == self.end && !matches!(line, Line::Comment(_)) {
+                    self.ind += 1;
+                    comment = Some(text.clone());
+                }
+            }
+        }
+
+        Some(Entry {
+            line,
+            start: span.line,
+            end: self.end,
+            comment,
+        })
+    }
+}
+
+// Formats `program` canonically: branch bodies are indented, operands are
+// separated by a single space and aligned across consecutive commands,
+// registers and commands are lowercase, and comments and (single) blank lines
+// are kept where they were. Fails if the program isn't valid
+pub fn format(program: &str) -> Result<String> {
+    let nodes: Vec<(Node, Span)> = lexer::lex_spanned(program)?
+        .into_iter()
+        .map(|(node, span)| match node {
+            Node::Keyword(name) => (Node::Keyword(name.to_lowercase()), span),
+            Node::Register(name) => (Node::Register(name.to_lowercase()), span),
+            node => (node, span),
+        })
+        .collect();
+    ast::construct_tree(&nodes)?;
+
+    let mut grouper = Grouper {
+        nodes: &nodes,
+        ind: 0,
+        comments: Vec::new(),
+        end: 0,
+    };
+    let mut entries = Vec::new();
+    while let Some(entry) = grouper.entry() {
+        entries.push(entry);
+    }
+
+    let mut res = String::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < entries.len() {
+        if i > 0 && entries[i].start > entries[i - 1].end + 1 {
+            res.push('\n');
+        }
+
+        match &entries[i].line {
+            Line::Cmd(..) => {
+                // Commands on consecutive lines form a block, whose operands
+                // are aligned to the longest command name
+                let mut j = i + 1;
+                while j < entries.len()
+                    && matches!(entries[j].line, Line::Cmd(..))
+                    && entries[j].start <= entries[j - 1].end + 1
+                {
+                    j += 1;
+                }
+
+                let width = entries[i..j]
+                    .iter()
+                    .map(|entry| match &entry.line {
+                        Line::Cmd(name, _) => name.len(),
+                        _ => 0,
+                    })
+                    .max()
+                    .unwrap_or(0);
+
+                for entry in &entries[i..j] {
+                    if let Line::Cmd(name, operands) = &entry.line {
+                        res.push_str(&INDENT.repeat(depth));
+                        if operands.is_empty() {
+                            res.push_str(name);
+                        } else {
+                            res.push_str(name);
+                            res.push_str(&" ".repeat(width + 1 - name.len()));
+                            res.push_str(&operands.join(" "));
+                        }
+                    }
+                    push_comment(&mut res, entry.comment.as_deref());
+                }
+
+                i = j;
+                continue;
+            }
+
+            Line::Open(name) => {
+                res.push_str(&INDENT.repeat(depth));
+                res.push_str(name);
+                depth += 1;
+            }
+
+            Line::Close => {
+                depth = depth.saturating_sub(1);
+                res.push_str(&INDENT.repeat(depth));
+                res.push('.');
+            }
+
+            Line::Reopen(name) => {
+                depth = depth.saturating_sub(1);
+                res.push_str(&INDENT.repeat(depth));
+                res.push('.');
+                res.push_str(name);
+                depth += 1;
+            }
+
+            Line::Comment(text) => {
+                res.push_str(&INDENT.repeat(depth));
+                res.push(';');
+                res.push_str(text);
+            }
+        }
+
+        push_comment(&mut res, entries[i].comment.as_deref());
+        i += 1;
+    }
+
+    Ok(res)
+}
+
+fn push_comment(res: &mut String, comment: Option<&str>) {
+    if let Some(text) = comment {
+        res.push_str(" ;");
+        res.push_str(text);
+    }
+    res.push('\n');
+}
+
+#[cfg(test)]
+mod fmt_tests {
+    use super::*;
+
+    #[test]
+    fn test_indent() {
+        assert_eq!(
+            format("str \"Hi\" 10\n.loop\nchr #[ eh ]\n  inc   eh\n..skip\nhlt 0").unwrap(),
+            "str \"Hi\" 10\n.loop\n    chr #[eh]\n    inc eh\n..skip\n    hlt 0\n"
+        );
+    }
+
+    #[test]
+    fn test_align() {
+        assert_eq!(
+            format(".main\n    cz al\n    chl 0\n\n    ceq db 0 10 eh\n    jmp :main\n.").unwrap(),
+            ".main\n    cz  al\n    chl 0\n\n    ceq db 0 10 eh\n    jmp :main\n.\n"
+        );
+    }
+
+    #[test]
+    fn test_case() {
+        assert_eq!(format("MOV AX 'A\nOut Ax").unwrap(), "mov ax 'A\nout ax\n");
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            format("; header\n\n\n\n.loop ;the loop\nout 1 ; one\n; alone\njmp :loop").unwrap(),
+            "; header\n\n.loop ;the loop\n    out 1 ; one\n    ; alone\n    jmp :loop\n"
+        );
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let once =
+            format("stk 10\n.add\npsh 1 al\n inc al\nceq al 10 ; done?\ncjm :test\n..test\nout bl")
+                .unwrap();
+        assert_eq!(format(&once).unwrap(), once);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(format("mov ax").is_err());
+    }
+}
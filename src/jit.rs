@@ -0,0 +1,638 @@
+// Compiles branches that run often to native code with Cranelift. Only the
+// commands that compute with registers, memory and the flags are compiled;
+// anything else (I/O, the stack, jumps, ...) is left to the interpreter. The
+// same goes for anything a command would fail on, like converting a value
+// that doesn't fit or overflowing: the compiled code checks for it before
+// touching memory and hands the command back to the interpreter, which then
+// fails exactly like it always does
+use std::collections::HashMap;
+
+use cranelift_codegen::ir::{
+    condcodes::IntCC, types, AbiParam, Block, InstBuilder, MemFlags, Value,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use crate::utils::{
+    consts::{CONDITIONALS, OFFSET, REGISTRY_OFFSET},
+    mem::Memory,
+    token::Op,
+    traits::{register, Reg},
+};
+
+// How many times a branch has to be entered before it gets compiled
+const THRESHOLD: usize = 64;
+
+// The commands the compiled code runs itself
+const SUPPORTED: &[&str] = &[
+    "mov", "cmo", "inc", "cin", "dec", "cde", "mul", "cmu", "div", "cdi", "add", "cad", "sub",
+    "csu", "lsh", "cls", "rsh", "crs", "or", "cor", "xor", "cxo", "and", "can", "not", "cno",
+    "ceq", "cne", "cl", "cle", "cg", "cge", "cz",
+];
+
+// A compiled branch. Takes the memory, its size, the stack size and the index
+// of the op to start at, and returns the index of the first op it didn't run
+// (the length of the branch if it ran all of them)
+#[derive(Clone, Copy)]
+pub struct Compiled(unsafe extern "C" fn(*mut u8, usize, usize, usize) -> usize);
+
+impl Compiled {
+    pub fn run(self, mem: &mut Memory, start: usize) -> usize {
+        // Safety: the compiled code only accesses `mem` within its size, which
+        // it checks before every access outside of the registers and flags
+        unsafe { (self.0)(mem.as_mut_ptr(), mem.size, mem.s_size, start) }
+    }
+}
+
+enum Entry {
+    // Entered this many times so far
+    Cold(usize),
+    Compiled(Compiled),
+
+    // Nothing in the branch can be compiled
+    Interpreted,
+}
+
+pub struct Jit {
+    pub threshold: usize,
+
+    // `None` if Cranelift doesn't support the host
+    module: Option<JITModule>,
+
+    // Keyed by the address of the branch's body, which doesn't move while a
+    // program runs
+    branches: HashMap<*const Op, Entry>,
+}
+
+impl Jit {
+    pub fn new() -> Self {
+        Self {
+            threshold: THRESHOLD,
+            module: new_module(),
+            branches: HashMap::new(),
+        }
+    }
+
+    // Forgets every branch, as the program they belonged to is gone
+    pub fn reset(&mut self) {
+        self.branches.clear();
+    }
+
+    // How many branches have been compiled
+    pub fn compiled(&self) -> usize {
+        self.branches
+            .values()
+            .filter(|entry| matches!(entry, Entry::Compiled(_)))
+            .count()
+    }
+
+    // Records that `body` is being entered, returning its compiled code once
+    // it is hot. `parent` is the program, to find the branches labels refer to
+    pub fn branch(&mut self, body: &[Op], parent: &[Op]) -> Option<Compiled> {
+        let entry = self.branches.entry(body.as_ptr()).or_insert(Entry::Cold(0));
+
+        match entry {
+            Entry::Compiled(func) => return Some(*func),
+            Entry::Interpreted => return None,
+            Entry::Cold(count) if *count < self.threshold => {
+                *count += 1;
+                return None;
+            }
+            Entry::Cold(_) => {}
+        }
+
+        let res = self
+            .module
+            .as_mut()
+            .and_then(|module| compile(module, body, parent));
+        self.branches.insert(
+            body.as_ptr(),
+            res.map_or(Entry::Interpreted, Entry::Compiled),
+        );
+        res
+    }
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Safety: `Compiled` functions never outlive the `Jit` they came
+            // from, as they're only handed out while running a branch
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+fn new_module() -> Option<JITModule> {
+    let mut flags = settings::builder();
+    flags.set("use_colocated_libcalls", "false").ok()?;
+    flags.set("is_pic", "false").ok()?;
+    flags.set("opt_level", "speed").ok()?;
+    let isa = cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(flags))
+        .ok()?;
+
+    // The compiled code passes `usize`s around as 64 bit integers
+    if isa.pointer_type() != types::I64 {
+        return None;
+    }
+    Some(JITModule::new(JITBuilder::with_isa(
+        isa,
+        default_libcall_names(),
+    )))
+}
+
+// Compiles `body`, or returns `None` if none of it can be
+fn compile(module: &mut JITModule, body: &[Op], parent: &[Op]) -> Option<Compiled> {
+    let mut ctx = module.make_context();
+    for _ in 0..4 {
+        ctx.func.signature.params.push(AbiParam::new(types::I64));
+    }
+    ctx.func.signature.returns.push(AbiParam::new(types::I64));
+
+    let mut builder_ctx = FunctionBuilderContext::new();
+    let mut b = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+    let entry = b.create_block();
+    b.append_block_params_for_function_params(entry);
+    b.switch_to_block(entry);
+    let params = b.block_params(entry).to_vec();
+
+    // One block per op, and one for the end of the branch
+    let blocks: Vec<Block> = (0..=body.len()).map(|_| b.create_block()).collect();
+    let mut switch = Switch::new();
+    for (i, &block) in blocks.iter().enumerate() {
+        switch.set_entry(i as u128, block);
+    }
+    switch.emit(&mut b, params[3], blocks[body.len()]);
+
+    let mut compiler = Compiler {
+        b,
+        parent,
+        mem: params[0],
+        size: params[1],
+        s_size: params[2],
+        fail: None,
+    };
+    let mut supported = 0;
+    for (i, op) in body.iter().enumerate() {
+        compiler.b.switch_to_block(blocks[i]);
+        compiler.fail = None;
+
+        let done = match op {
+            Op::Cmd(name, args, _) => compiler.cmd(name, args, blocks[i + 1]).is_some(),
+            Op::Empty => {
+                compiler.b.ins().jump(blocks[i + 1], &[]);
+                continue;
+            }
+            _ => false,
+        };
+        if done {
+            supported += 1;
+        } else {
+            compiler.give_up(i);
+        }
+
+        if let Some(fail) = compiler.fail {
+            compiler.b.switch_to_block(fail);
+            compiler.give_up(i);
+        }
+    }
+    compiler.b.switch_to_block(blocks[body.len()]);
+    compiler.give_up(body.len());
+
+    compiler.b.seal_all_blocks();
+    compiler.b.finalize();
+    if supported == 0 {
+        module.clear_context(&mut ctx);
+        return None;
+    }
+
+    let id = module
+        .declare_anonymous_function(&ctx.func.signature)
+        .ok()?;
+    let res = module.define_function(id, &mut ctx);
+    module.clear_context(&mut ctx);
+    res.ok()?;
+    module.finalize_definitions().ok()?;
+
+    // Safety: the function was declared with exactly this signature
+    Some(Compiled(unsafe {
+        std::mem::transmute::<*const u8, unsafe extern "C" fn(*mut u8, usize, usize, usize) -> usize>(
+            module.get_finalized_function(id),
+        )
+    }))
+}
+
+// The register `name` refers to, unless it is the stack pointer, which is
+// the length of the stack and so isn't in memory
+fn compiled_register(name: &str) -> Option<Reg> {
+    register(name).filter(|reg| !matches!(reg, Reg::Stack))
+}
+
+// An index or offset as an immediate. They index into the program or the
+// memory, so they're nowhere near big enough to wrap
+#[allow(clippy::cast_possible_wrap)]
+fn imm(val: usize) -> i64 {
+    val as i64
+}
+
+// The types values get converted to before they are used, matching the types
+// the parser converts to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    U8,
+    I16,
+    I32,
+    Usize,
+}
+
+// Where the value written by `write` comes from
+enum Src<'a> {
+    Op(&'a Op),
+
+    // A value the command computed, which is always in range of an `i32`
+    Value(Value),
+}
+
+struct Compiler<'a, 'b> {
+    b: FunctionBuilder<'b>,
+    parent: &'a [Op],
+
+    // The function's parameters
+    mem: Value,
+    size: Value,
+    s_size: Value,
+
+    // Where the op being compiled goes when one of its checks fails
+    fail: Option<Block>,
+}
+
+impl Compiler<'_, '_> {
+    // Returns from the function, leaving the op at `ind` to the interpreter
+    fn give_up(&mut self, ind: usize) {
+        let ind = self.b.ins().iconst(types::I64, imm(ind));
+        self.b.ins().return_(&[ind]);
+    }
+
+    // Continues only if `ok` is set, and leaves the op to the interpreter if not
+    fn check(&mut self, ok: Value) {
+        let fail = match self.fail {
+            Some(fail) => fail,
+            None => *self.fail.insert(self.b.create_block()),
+        };
+        let next = self.b.create_block();
+        self.b.ins().brif(ok, next, &[], fail, &[]);
+        self.b.switch_to_block(next);
+    }
+
+    fn check_range(&mut self, val: Value, ty: Ty) {
+        let ok = match ty {
+            Ty::U8 => self.b.ins().icmp_imm(IntCC::UnsignedLessThan, val, 256),
+            Ty::I16 => {
+                let shifted = self.b.ins().iadd_imm(val, 32768);
+                self.b
+                    .ins()
+                    .icmp_imm(IntCC::UnsignedLessThan, shifted, 65536)
+            }
+            Ty::I32 => {
+                let narrow = self.b.ins().ireduce(types::I32, val);
+                let wide = self.b.ins().sextend(types::I64, narrow);
+                self.b.ins().icmp(IntCC::Equal, wide, val)
+            }
+            Ty::Usize => self
+                .b
+                .ins()
+                .icmp_imm(IntCC::SignedGreaterThanOrEqual, val, 0),
+        };
+        self.check(ok);
+    }
+
+    fn reg_addr(&mut self, offset: usize) -> Value {
+        self.b.ins().iadd_imm(self.mem, imm(offset))
+    }
+
+    // The address of `len` bytes at `pos` in the cell memory
+    fn cell(&mut self, pos: Value, len: i64) -> Value {
+        let start = self.b.ins().iadd_imm(self.s_size, imm(OFFSET));
+        let offset = self.b.ins().iadd(start, pos);
+        let end = self.b.ins().iadd_imm(offset, len);
+        let ok = self
+            .b
+            .ins()
+            .icmp(IntCC::UnsignedLessThanOrEqual, end, self.size);
+        self.check(ok);
+        self.b.ins().iadd(self.mem, offset)
+    }
+
+    fn load_reg(&mut self, reg: &Reg) -> Value {
+        let flags = MemFlags::new();
+        match *reg {
+            Reg::Low(a) | Reg::High(a) => {
+                let addr = self.reg_addr(a);
+                self.b.ins().uload8(types::I64, flags, addr, 0)
+            }
+            Reg::Wide(a) => {
+                let addr = self.reg_addr(a);
+                self.b.ins().sload16(types::I64, flags, addr, 0)
+            }
+            Reg::Pair(a, b) => {
+                let (low, high) = (self.reg_addr(a), self.reg_addr(b));
+                let low = self.b.ins().uload16(types::I64, flags, low, 0);
+                let high = self.b.ins().sload16(types::I64, flags, high, 0);
+                let high = self.b.ins().ishl_imm(high, 16);
+                self.b.ins().bor(high, low)
+            }
+            Reg::Stack => unreachable!("the stack pointer is never compiled"),
+        }
+    }
+
+    fn store_reg(&mut self, reg: &Reg, val: Value) {
+        let flags = MemFlags::new();
+        match *reg {
+            Reg::Low(a) | Reg::High(a) => {
+                let addr = self.reg_addr(a);
+                self.b.ins().istore8(flags, val, addr, 0);
+            }
+            Reg::Wide(a) => {
+                let addr = self.reg_addr(a);
+                self.b.ins().istore16(flags, val, addr, 0);
+            }
+            Reg::Pair(a, b) => {
+                let (low, high) = (self.reg_addr(a), self.reg_addr(b));
+                self.b.ins().istore16(flags, val, low, 0);
+                let val = self.b.ins().sshr_imm(val, 16);
+                self.b.ins().istore16(flags, val, high, 0);
+            }
+            Reg::Stack => unreachable!("the stack pointer is never compiled"),
+        }
+    }
+
+    // The value of `op`, before it is converted to anything, or `None` if it
+    // can't be compiled
+    fn value(&mut self, op: &Op) -> Option<Value> {
+        Some(match op {
+            Op::Numeric(val) => self.b.ins().iconst(types::I64, i64::from(*val)),
+
+            Op::Char(chr) => self.b.ins().iconst(types::I64, i64::from(*chr as u8)),
+
+            Op::Register(name) => {
+                let reg = compiled_register(name)?;
+                self.load_reg(&reg)
+            }
+
+            Op::Memory(ident, addr) => {
+                let len = match ident {
+                    '#' => 1,
+                    '$' => 2,
+                    '@' => 4,
+                    _ => return None,
+                };
+                let pos = self.read(addr, Ty::Usize)?;
+                let addr = self.cell(pos, len);
+                let flags = MemFlags::new();
+                match len {
+                    1 => self.b.ins().uload8(types::I64, flags, addr, 0),
+                    2 => self.b.ins().sload16(types::I64, flags, addr, 0),
+                    _ => self.b.ins().sload32(flags, addr, 0),
+                }
+            }
+
+            Op::Label(name) => {
+                let pos = self
+                    .parent
+                    .iter()
+                    .position(|op| matches!(op, Op::Branch(n, _) if n[1..] == name[1..]))?;
+                self.b.ins().iconst(types::I64, imm(pos))
+            }
+
+            _ => return None,
+        })
+    }
+
+    // Reads `op` as a `ty`
+    fn read(&mut self, op: &Op, ty: Ty) -> Option<Value> {
+        let val = self.value(op)?;
+        self.check_range(val, ty);
+        Some(val)
+    }
+
+    fn src(&mut self, src: &Src<'_>, ty: Ty) -> Option<Value> {
+        match src {
+            Src::Op(op) => self.read(op, ty),
+            Src::Value(val) => {
+                self.check_range(*val, ty);
+                Some(*val)
+            }
+        }
+    }
+
+    // Writes `src` to `dest`, like `parser::modify_memory`
+    fn write(&mut self, dest: &Op, src: &Src<'_>) -> Option<()> {
+        match dest {
+            Op::Register(name) => {
+                let reg = compiled_register(name)?;
+                let ty = match reg {
+                    Reg::Low(_) | Reg::High(_) => Ty::U8,
+                    Reg::Wide(_) | Reg::Stack => Ty::I16,
+                    Reg::Pair(..) => Ty::I32,
+                };
+                let val = self.src(src, ty)?;
+                self.store_reg(&reg, val);
+            }
+
+            Op::Memory(ident, addr) => {
+                let (ty, len) = match ident {
+                    '#' => (Ty::U8, 1),
+                    '$' => (Ty::I16, 2),
+                    '@' => (Ty::I32, 4),
+                    _ => return None,
+                };
+                let pos = self.read(addr, Ty::Usize)?;
+                let val = self.src(src, ty)?;
+                let addr = self.cell(pos, len);
+                let flags = MemFlags::new();
+                match len {
+                    1 => self.b.ins().istore8(flags, val, addr, 0),
+                    2 => self.b.ins().istore16(flags, val, addr, 0),
+                    _ => self.b.ins().istore32(flags, val, addr, 0),
+                };
+            }
+
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    // Computes a binary operation on two `i32`s, leaving results the
+    // interpreter would fail on to it
+    fn arith(&mut self, name: &str, l: Value, r: Value) -> Value {
+        let val = match name {
+            "mul" | "cmu" => self.b.ins().imul(l, r),
+            "add" | "cad" => self.b.ins().iadd(l, r),
+            "sub" | "csu" => self.b.ins().isub(l, r),
+            "div" | "cdi" => {
+                let nonzero = self.b.ins().icmp_imm(IntCC::NotEqual, r, 0);
+                self.check(nonzero);
+                let min = self.b.ins().icmp_imm(IntCC::Equal, l, i64::from(i32::MIN));
+                let minus_one = self.b.ins().icmp_imm(IntCC::Equal, r, -1);
+                let overflow = self.b.ins().band(min, minus_one);
+                let ok = self.b.ins().icmp_imm(IntCC::Equal, overflow, 0);
+                self.check(ok);
+                return self.b.ins().sdiv(l, r);
+            }
+            "lsh" | "cls" | "rsh" | "crs" => {
+                let ok = self.b.ins().icmp_imm(IntCC::UnsignedLessThan, r, 32);
+                self.check(ok);
+                if name == "rsh" || name == "crs" {
+                    return self.b.ins().sshr(l, r);
+                }
+
+                // Shifting left never overflows, the bits fall off the end
+                let val = self.b.ins().ishl(l, r);
+                let val = self.b.ins().ireduce(types::I32, val);
+                return self.b.ins().sextend(types::I64, val);
+            }
+            "or" | "cor" => return self.b.ins().bor(l, r),
+            "xor" | "cxo" => return self.b.ins().bxor(l, r),
+            _ => return self.b.ins().band(l, r),
+        };
+
+        self.check_range(val, Ty::I32);
+        val
+    }
+
+    fn cmd(&mut self, name: &str, args: &[Op], next: Block) -> Option<()> {
+        if !SUPPORTED.contains(&name) {
+            return None;
+        }
+
+        if CONDITIONALS.iter().any(|(cond, _)| *cond == name) {
+            let addr = self.reg_addr(REGISTRY_OFFSET);
+            let flag = self.b.ins().uload8(types::I32, MemFlags::new(), addr, 0);
+            let body = self.b.create_block();
+            self.b.ins().brif(flag, body, &[], next, &[]);
+            self.b.switch_to_block(body);
+        }
+
+        match name {
+            "mov" | "cmo" => self.write(&args[0], &Src::Op(&args[1]))?,
+
+            "inc" | "cin" | "dec" | "cde" => {
+                let val = self.read(&args[0], Ty::I32)?;
+                let by = if name == "inc" || name == "cin" {
+                    1
+                } else {
+                    -1
+                };
+                let val = self.b.ins().iadd_imm(val, by);
+                self.check_range(val, Ty::I32);
+                self.write(&args[0], &Src::Value(val))?;
+            }
+
+            "not" | "cno" => {
+                let val = self.read(&args[0], Ty::I32)?;
+                let val = self.b.ins().bnot(val);
+                self.write(&args[0], &Src::Value(val))?;
+            }
+
+            "ceq" | "cne" | "cl" | "cle" | "cg" | "cge" => {
+                let l = self.read(&args[0], Ty::I32)?;
+                let r = self.read(&args[1], Ty::I32)?;
+                let cond = match name {
+                    "ceq" => IntCC::Equal,
+                    "cne" => IntCC::NotEqual,
+                    "cl" => IntCC::SignedLessThan,
+                    "cle" => IntCC::SignedLessThanOrEqual,
+                    "cg" => IntCC::SignedGreaterThan,
+                    _ => IntCC::SignedGreaterThanOrEqual,
+                };
+                let flag = self.b.ins().icmp(cond, l, r);
+                let addr = self.reg_addr(REGISTRY_OFFSET);
+                self.b.ins().store(MemFlags::new(), flag, addr, 0);
+            }
+
+            "cz" => {
+                let val = self.read(&args[0], Ty::I32)?;
+                let flag = self.b.ins().icmp_imm(IntCC::Equal, val, 0);
+                let addr = self.reg_addr(REGISTRY_OFFSET);
+                self.b.ins().store(MemFlags::new(), flag, addr, 0);
+            }
+
+            _ => {
+                let l = self.read(&args[0], Ty::I32)?;
+                let r = self.read(&args[1], Ty::I32)?;
+                let val = self.arith(name, l, r);
+                self.write(&args[0], &Src::Value(val))?;
+            }
+        }
+
+        self.b.ins().jump(next, &[]);
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod jit_tests {
+    use super::*;
+
+    // Returns the `Jit` too, as the code only lives as long as it does
+    fn compile_branch(program: &str) -> (Jit, Option<Compiled>) {
+        let tree = crate::compile(program).unwrap();
+        let Op::Branch(_, body) = &tree[0] else {
+            panic!("Expected a branch");
+        };
+        let mut jit = Jit::new();
+        jit.threshold = 0;
+        let func = jit.branch(body, &tree);
+        (jit, func)
+    }
+
+    #[test]
+    fn test_compiled() {
+        let (_jit, func) =
+            compile_branch(".f\n    mov ax 5\n    add ax 7\n    mov @[3] ax\n    cg ax 10");
+        let func = func.unwrap();
+        let mut mem = Memory::init(1024, 0);
+
+        assert_eq!(func.run(&mut mem, 0), 4);
+        assert_eq!(mem.r_read::<i16>(&'a'), 12);
+        assert_eq!(mem.m_read::<i32>(3).unwrap(), 12);
+        assert!(mem.flag_read_cmp());
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let (_jit, func) = compile_branch(".f\n    inc bl\n    out bl\n    mov cl 300");
+        let func = func.unwrap();
+        let mut mem = Memory::init(1024, 0);
+
+        // `out` is left to the interpreter
+        assert_eq!(func.run(&mut mem, 0), 1);
+        assert_eq!(
+            mem.r_read::<u8>(&('b', crate::utils::traits::Pos::Lower)),
+            1
+        );
+
+        // So is a value that doesn't fit, without changing anything
+        assert_eq!(func.run(&mut mem, 2), 2);
+        assert_eq!(
+            mem.r_read::<u8>(&('c', crate::utils::traits::Pos::Lower)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_nothing_to_compile() {
+        assert!(compile_branch(".f\n    out 1\n    jmp :f").1.is_none());
+    }
+}
@@ -1,45 +1,96 @@
-use crate::utils::consts::{PUNCTUATION, REGISTERS, REGISTER_ENDINGS};
-use crate::utils::token::Node;
+#[cfg(not(feature = "inline-python"))]
+use crate::err;
+use crate::utils::consts::{COMMANDS, POINTERS, PUNCTUATION, REGISTERS, REGISTER_ENDINGS};
+use crate::utils::error::Result;
+use crate::utils::token::{Node, Span};
+use crate::utils::traits::FloatRegister;
 
 #[inline]
-fn flush(buf: &mut String, chr: char) {
+fn flush(buf: &mut String, start: &mut Span, chr: char, at: Span) {
     buf.clear();
     if !chr.is_whitespace() {
         buf.push(chr);
+        *start = at;
     }
 }
 
-pub fn lex(program: &str) -> Vec<Node> {
+// Whether `chr` continues the identifier in `buf`. Digits only can after the
+// first letter, like in `f0`
+#[inline]
+fn is_ident(buf: &str, chr: char) -> bool {
+    chr.is_ascii_alphabetic()
+        || chr == '_'
+        || (chr.is_ascii_digit() && buf.starts_with(|chr: char| chr.is_ascii_alphabetic()))
+}
+
+pub fn lex(program: &str) -> Result<Vec<Node>> {
+    Ok(lex_spanned(program)?
+        .into_iter()
+        .map(|(node, _)| node)
+        .collect())
+}
+
+// Same as `lex`, but also returns where each node starts in the source
+pub fn lex_spanned(program: &str) -> Result<Vec<(Node, Span)>> {
     let mut prg = program.to_string();
     prg.push('\n');
-    let mut sep = prg.chars();
+
+    let mut at = Span { line: 1, col: 1 };
+    let mut chars = Vec::with_capacity(prg.len());
+    for chr in prg.chars() {
+        chars.push((chr, at));
+        if chr == '\n' {
+            at = Span {
+                line: at.line + 1,
+                col: 1,
+            };
+        } else {
+            at.col += 1;
+        }
+    }
+    let mut sep = chars.into_iter().peekable();
 
     // The number of spaces will give a rough estimate of how large the returned
     // `Vec` will be, improving performance
     let mut res = Vec::with_capacity(program.matches(' ').count());
     let mut buf = String::new();
+    let mut start = Span { line: 1, col: 1 };
 
-    while let Some(chr) = sep.next() {
+    while let Some((chr, at)) = sep.next() {
         if buf.parse::<i32>().is_ok() {
+            // A point followed by a digit makes it a float
+            if chr == '.' && sep.peek().is_some_and(|(next, _)| next.is_ascii_digit()) {
+                buf.push(chr);
+                continue;
+            }
+
             buf.push(chr);
             if buf.parse::<i32>().is_err() {
                 buf.pop();
-                res.push(Node::Numeric(buf.parse::<i32>().unwrap()));
+                res.push((Node::Numeric(buf.parse::<i32>().unwrap()), start));
 
-                flush(&mut buf, chr);
+                flush(&mut buf, &mut start, chr, at);
+            }
+        } else if buf.starts_with(|chr: char| chr.is_ascii_digit()) && buf.contains('.') {
+            if chr.is_ascii_digit() {
+                buf.push(chr);
+            } else {
+                res.push((Node::Float(buf.parse().unwrap()), start));
+
+                flush(&mut buf, &mut start, chr, at);
             }
         } else if buf.starts_with(':') || buf.starts_with('.') {
-            if chr.is_whitespace() || !chr.is_alphabetic() {
-                res.push(Node::Branch(buf.clone()));
+            if chr.is_whitespace() || !(chr.is_alphabetic() || chr == '_') {
+                res.push((Node::Branch(buf.clone()), start));
 
-                flush(&mut buf, chr);
+                flush(&mut buf, &mut start, chr, at);
             } else {
                 buf.push(chr);
             }
         } else if PUNCTUATION.contains(&&*buf) {
-            res.push(Node::Punctuation(buf.chars().next().unwrap()));
+            res.push((Node::Punctuation(buf.chars().next().unwrap()), start));
 
-            flush(&mut buf, chr);
+            flush(&mut buf, &mut start, chr, at);
         } else if buf == "\"" {
             buf.clear();
             buf.push(chr);
@@ -47,7 +98,7 @@ pub fn lex(program: &str) -> Vec<Node> {
             // but that leads to errors with mutable borrowing. This is the next best thing. Also,
             // turns out clippy errors here even though using `for str_chr in sep` won't work
             #[allow(clippy::while_let_on_iterator)]
-            while let Some(str_chr) = sep.next() {
+            while let Some((str_chr, _)) = sep.next() {
                 if str_chr == '"' {
                     break;
                 }
@@ -55,11 +106,28 @@ pub fn lex(program: &str) -> Vec<Node> {
                 buf.push(str_chr);
             }
 
-            res.push(Node::String(buf.clone()));
+            res.push((Node::String(buf.clone()), start));
+            buf.clear();
+        } else if buf == ";" {
+            // Comments run until the end of the line
+            buf.clear();
+            if chr != '\n' {
+                buf.push(chr);
+                #[allow(clippy::while_let_on_iterator)]
+                while let Some((comment_chr, _)) = sep.next() {
+                    if comment_chr == '\n' {
+                        break;
+                    }
+
+                    buf.push(comment_chr);
+                }
+            }
+
+            res.push((Node::Comment(buf.trim_end().to_string()), start));
             buf.clear();
         } else if buf == "{" {
             #[cfg(not(feature = "inline-python"))]
-            panic!("Cannot use inline python code when the feature is disabled");
+            return err!(at start; "Cannot use inline python code when the feature is disabled");
 
             #[cfg(feature = "inline-python")]
             {
@@ -67,8 +135,8 @@ pub fn lex(program: &str) -> Vec<Node> {
                 buf.push(chr);
 
                 // Same as above
-                #[allow(clippy::clippy::while_let_on_iterator)]
-                while let Some(py_chr) = sep.next() {
+                #[allow(clippy::while_let_on_iterator)]
+                while let Some((py_chr, _)) = sep.next() {
                     if py_chr == '}' {
                         break;
                     }
@@ -76,30 +144,50 @@ pub fn lex(program: &str) -> Vec<Node> {
                     buf.push(py_chr);
                 }
 
-                res.push(Node::InlinePy(buf.clone()));
+                res.push((Node::InlinePy(buf.clone()), start));
                 buf.clear();
             }
         } else if buf.starts_with('\'') {
-            res.push(Node::Char(chr));
+            res.push((Node::Char(chr), start));
             buf.clear();
-        } else if !chr.is_ascii_alphabetic() {
-            if buf.ends_with(REGISTER_ENDINGS)
-                && buf[..buf.len() - 1]
-                    .chars()
-                    .all(|chr| REGISTERS.contains(&chr))
-            {
-                res.push(Node::Register(buf.clone()));
+        } else if !is_ident(&buf, chr) {
+            if is_register(&buf) {
+                // Registers may be written in either case, but are only known
+                // by their lowercase names past here
+                res.push((Node::Register(buf.to_lowercase()), start));
             } else if !buf.is_empty() {
-                res.push(Node::Keyword(buf.clone()));
+                res.push((Node::Keyword(buf.clone()), start));
             }
 
-            flush(&mut buf, chr);
+            flush(&mut buf, &mut start, chr, at);
         } else {
+            if buf.is_empty() {
+                start = at;
+            }
             buf.push(chr);
         }
     }
 
-    res
+    Ok(res)
+}
+
+// Whether `name` is a register: a single one (`ax`, `ah`, `al`), a pair (`abx`)
+// a pointer or a float register. Some commands, like `chl` and `hex`, are spelt almost or
+// exactly like pairs, and stay commands
+fn is_register(name: &str) -> bool {
+    let is_reg = |chr: char| REGISTERS.contains(&chr);
+    POINTERS.contains(&name)
+        || FloatRegister::parse(name).is_some()
+        || match name.chars().collect::<Vec<_>>()[..] {
+            [r, end] => is_reg(r) && REGISTER_ENDINGS.contains(&end),
+            [r1, r2, end] => {
+                is_reg(r1)
+                    && is_reg(r2)
+                    && matches!(end, 'x' | 'X')
+                    && !COMMANDS.contains_key(&name.to_lowercase())
+            }
+            _ => false,
+        }
 }
 
 #[cfg(test)]
@@ -108,13 +196,13 @@ mod lex_tests {
 
     #[test]
     fn test_empty() {
-        assert!(lex("").is_empty());
+        assert!(lex("").unwrap().is_empty());
     }
 
     #[test]
     fn test_full() {
         assert_eq!(
-            lex("mov eh abx 13 @ +"),
+            lex("mov eh abx 13 @ +").unwrap(),
             vec![
                 Node::Keyword("mov".to_string()),
                 Node::Register("eh".to_string()),
@@ -126,10 +214,22 @@ mod lex_tests {
         );
     }
 
+    #[test]
+    fn test_uppercase() {
+        assert_eq!(
+            lex("mov AX Fh").unwrap(),
+            vec![
+                Node::Keyword("mov".to_string()),
+                Node::Register("ax".to_string()),
+                Node::Register("fh".to_string())
+            ]
+        );
+    }
+
     #[test]
     fn test_strings() {
         assert_eq!(
-            lex("\"Hello\" eh + 4"),
+            lex("\"Hello\" eh + 4").unwrap(),
             vec![
                 Node::String("Hello".to_string()),
                 Node::Register("eh".to_string()),
@@ -138,4 +238,87 @@ mod lex_tests {
             ]
         );
     }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            lex("out 1;one\n; two\nout 2 ;\n").unwrap(),
+            vec![
+                Node::Keyword("out".to_string()),
+                Node::Numeric(1),
+                Node::Comment("one".to_string()),
+                Node::Comment(" two".to_string()),
+                Node::Keyword("out".to_string()),
+                Node::Numeric(2),
+                Node::Comment(String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let spans: Vec<Span> = lex_spanned("mov ax 13\n  chr '!")
+            .unwrap()
+            .into_iter()
+            .map(|(_, span)| span)
+            .collect();
+
+        assert_eq!(
+            spans,
+            vec![
+                Span { line: 1, col: 1 },
+                Span { line: 1, col: 5 },
+                Span { line: 1, col: 8 },
+                Span { line: 2, col: 3 },
+                Span { line: 2, col: 7 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_underscores() {
+        assert_eq!(
+            lex("assert_eq ax 1 .test_add").unwrap(),
+            vec![
+                Node::Keyword("assert_eq".to_string()),
+                Node::Register("ax".to_string()),
+                Node::Numeric(1),
+                Node::Branch(".test_add".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(
+            lex("fmov f0 12.25\nfmov f1 3.\n.x").unwrap(),
+            vec![
+                Node::Keyword("fmov".to_string()),
+                Node::Register("f0".to_string()),
+                Node::Float(12.25),
+                Node::Keyword("fmov".to_string()),
+                Node::Register("f1".to_string()),
+                Node::Numeric(3),
+                Node::Branch(".".to_string()),
+                Node::Branch(".x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_registers() {
+        assert_eq!(
+            lex("chl hex hx cl fp bgx f7 f8").unwrap(),
+            vec![
+                Node::Keyword("chl".to_string()),
+                Node::Keyword("hex".to_string()),
+                Node::Register("hx".to_string()),
+                Node::Register("cl".to_string()),
+                Node::Register("fp".to_string()),
+                Node::Register("bgx".to_string()),
+                Node::Register("f7".to_string()),
+                Node::Keyword("f8".to_string()),
+            ]
+        );
+    }
 }
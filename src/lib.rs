@@ -0,0 +1,64 @@
+#![deny(rust_2018_idioms, clippy::all)]
+#![warn(clippy::pedantic)]
+#![allow(
+    clippy::similar_names,
+    clippy::module_name_repetitions,
+    clippy::if_not_else,
+    clippy::option_if_let_else,
+    clippy::wildcard_imports,
+    clippy::too_many_lines,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::must_use_candidate,
+    clippy::non_std_lazy_statics
+)]
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod ast;
+pub mod binary;
+pub mod checker;
+pub mod debugger;
+pub mod disasm;
+pub mod dump;
+pub mod emit_c;
+pub mod emit_wat;
+pub mod env;
+pub mod formatter;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod lexer;
+pub mod lsp;
+pub mod optimizer;
+pub mod parser;
+pub mod snapshot;
+pub mod tester;
+#[macro_use]
+pub mod utils;
+
+pub use env::Environment;
+pub use utils::error::{Error, Result};
+
+// Lexes and builds `program` into the tree that gets run
+pub fn compile(program: &str) -> Result<Vec<utils::token::Op>> {
+    ast::construct_tree(&lexer::lex_spanned(program)?)
+}
+
+// Builds the tree for either a compiled program or source code
+pub fn load(bytes: &[u8]) -> Result<Vec<utils::token::Op>> {
+    if binary::is_binary(bytes) {
+        return binary::Program::from_bytes(bytes)?.to_tree();
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(program) => compile(program),
+        Err(_) => err!("Not a compiled arsm program or valid UTF-8 source"),
+    }
+}
+
+// Lexes, builds and runs `program` inside `env`, returning the exit code it
+// halted with
+pub fn run(program: &str, env: &mut Environment) -> Result<i32> {
+    parser::parse(&compile(program)?, env)
+}
@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::checker::{self, Severity};
+use crate::lexer;
+use crate::utils::{
+    consts::{COMMANDS, CONDITIONALS, FLOAT_REGISTER_COUNT, POINTERS, REGISTERS},
+    token::{float_text, Node, Span},
+};
+
+// Usage and a short description of every (non-conditional) command, shown on hover
+const COMMAND_DOCS: &[(&str, &str, &str)] = &[
+    ("mov", "mov <A> <B>", "Move B into A"),
+    ("inc", "inc <A>", "Increment A"),
+    ("dec", "dec <A>", "Decrement A"),
+    ("out", "out <A>", "Print A with no newline"),
+    ("jmp", "jmp <A>", "Goto the branch entitled A (a label), `ret` comes back here"),
+    ("mul", "mul <A> <B>", "Multiply A by B, store in A"),
+    ("div", "div <A> <B>", "Divide A by B, store in A"),
+    ("add", "add <A> <B>", "Add A and B, store in A"),
+    ("sub", "sub <A> <B>", "Subtract B from A, store in A"),
+    ("lsh", "lsh <A> <B>", "A << B, store in A"),
+    ("rsh", "rsh <A> <B>", "A >> B, store in A"),
+    ("or", "or <A> <B>", "A | B, store in A"),
+    ("xor", "xor <A> <B>", "A ^ B, store in A"),
+    ("and", "and <A> <B>", "A & B, store in A"),
+    ("not", "not <A>", "~A, store in A"),
+    ("str", "str <A> <B>", "Place string A in memory, with final character B"),
+    ("db", "db <A> <B>", "Get length of data, starting at point A in memory and ending when the point in memory equals B"),
+    ("in", "in", "Get next byte of STDIN or a null-byte (`0`) if none left"),
+    ("eof", "eof", "1 once STDIN has run out, otherwise 0. Tells the end of STDIN apart from a null-byte"),
+    ("chr", "chr <A>", "Print A as a character instead of number"),
+    ("err", "err <A>", "Print A to STDERR, with no newline"),
+    ("errc", "errc <A>", "Print A to STDERR as a character instead of number"),
+    ("argc", "argc", "Get how many arguments the program was given, counting its own path"),
+    ("argv", "argv <I> <A> <B>", "Copy at most B bytes of argument I into memory at point A, followed by a null-byte. Gets its length, or -1 and resets the conditional flag if there is no such argument"),
+    ("env", "env <A> <B> <C>", "Copy at most C bytes of the environment variable named by the null-terminated string at point A into memory at point B, followed by a null-byte. Gets its length, or -1 and resets the conditional flag if it isn't set or allowed"),
+    ("alloc", "alloc <A>", "Take A zeroed bytes of memory from the heap. Gets the point they start at, or -1 and resets the conditional flag if they don't fit"),
+    ("free", "free <A>", "Give the bytes taken by `alloc` at point A back to the heap"),
+    ("fmov", "fmov <F> <A>", "Move A into the float register F. Integers are converted to floats"),
+    ("fadd", "fadd <F> <A>", "Add A to the float register F"),
+    ("fsub", "fsub <F> <A>", "Subtract A from the float register F"),
+    ("fmul", "fmul <F> <A>", "Multiply the float register F by A"),
+    ("fdiv", "fdiv <F> <A>", "Divide the float register F by A"),
+    ("ftoi", "ftoi <A> <F>", "Move the float F into A, rounded towards zero. Fails if it doesn't fit into 32 bits"),
+    ("feq", "feq <F> <G>", "Sets conditional flag if the floats F == G"),
+    ("fne", "fne <F> <G>", "Sets conditional flag if the floats F != G"),
+    ("flt", "flt <F> <G>", "Sets conditional flag if the floats F < G"),
+    ("fle", "fle <F> <G>", "Sets conditional flag if the floats F <= G"),
+    ("fgt", "fgt <F> <G>", "Sets conditional flag if the floats F > G"),
+    ("fge", "fge <F> <G>", "Sets conditional flag if the floats F >= G"),
+    ("fld", "fld <F> <A>", "Load the 8 byte float at point A in memory into the float register F"),
+    ("fst", "fst <A> <F>", "Store the float F as 8 bytes at point A in memory"),
+    ("fout", "fout <F> <A>", "Print the float F with A digits after the point"),
+    ("fop", "fop <A> <B>", "Open the file whose path is the null-terminated string at point A in memory, for reading (B = 0), writing (1) or appending (2). Gets its handle, or -1 and resets the conditional flag if it can't be opened"),
+    ("frd", "frd <H> <A> <B>", "Read up to B bytes from file H into memory at point A. Gets how many were read"),
+    ("fwr", "fwr <H> <A> <B>", "Write B bytes from memory at point A to file H"),
+    ("fsk", "fsk <H> <A>", "Move to byte A of file H"),
+    ("fcl", "fcl <H>", "Close file H"),
+    ("rdn", "rdn <A>", "Read a whitespace-delimited integer from STDIN into A, setting the conditional flag unless there was none left"),
+    ("rdl", "rdl <A> <B>", "Read a line of at most B bytes from STDIN into memory at point A, followed by a null-byte. Gets its length"),
+    ("hex", "hex <A> <B>", "Print A in hexadecimal, padded with zeros to B digits"),
+    ("bin", "bin <A> <B>", "Print A in binary, padded with zeros to B digits"),
+    ("pad", "pad <A> <B>", "Print A, padded with spaces to B characters"),
+    ("prs", "prs <A> <B>", "Print the string starting at point A in memory and ending when the point in memory equals B"),
+    ("hlt", "hlt <A>", "Terminates program with exit code A"),
+    ("ret", "ret", "Return to the point at which this jump was called from"),
+    ("stk", "stk <A>", "Resizes stack to size A. Defaults to 0"),
+    ("psh", "psh <A> <B>", "Pushes A byte number B to stack"),
+    ("pop", "pop <A>", "Pops N bytes (enough to fill A) and move to A"),
+    ("enter", "enter <A>", "Push fp, point fp at the top of the stack and make room for A zeroed bytes of locals above it"),
+    ("leave", "leave", "Drop everything above fp and pop fp back, undoing `enter`"),
+    ("lds", "lds <A> <O>", "Move the N bytes (enough to fill A) that end O bytes below the top of the stack into A"),
+    ("sts", "sts <N> <O> <B>", "Store N byte number B so it ends O bytes below the top of the stack"),
+    ("ldf", "ldf <A> <O>", "Move the N bytes (enough to fill A) at fp + O on the stack into A"),
+    ("stf", "stf <N> <O> <B>", "Store N byte number B at fp + O on the stack"),
+    ("swp", "swp <A> <B>", "Swaps A and B's values"),
+    ("cmp", "cmp <A> <B>", "Reserved, not implemented yet"),
+    ("ceq", "ceq <A> <B>", "Sets conditional flag if A == B"),
+    ("cz", "cz <A>", "Sets conditional flag if A is 0"),
+    ("cne", "cne <A> <B>", "Sets conditional flag if A != B"),
+    ("cg", "cg <A> <B>", "Sets conditional flag if A > B"),
+    ("cge", "cge <A> <B>", "Sets conditional flag if A >= B"),
+    ("cl", "cl <A> <B>", "Sets conditional flag if A < B"),
+    ("cle", "cle <A> <B>", "Sets conditional flag if A <= B"),
+    ("assert", "assert <A>", "Fails with message A unless the conditional flag is set"),
+    ("assert_eq", "assert_eq <A> <B> <C>", "Fails with message C unless A == B"),
+    ("assert_ne", "assert_ne <A> <B> <C>", "Fails with message C unless A != B"),
+];
+
+// Markdown documentation for `cmd`, if it is a command
+pub fn command_doc(cmd: &str) -> Option<String> {
+    if let Some(&(_, base)) = CONDITIONALS.iter().find(|(cond, _)| *cond == cmd) {
+        let (_, usage, doc) = COMMAND_DOCS.iter().find(|(name, ..)| *name == base)?;
+        let usage = usage.replacen(base, cmd, 1);
+        return Some(format!(
+            "```asm\n{usage}\n```\nConditional `{base}`, only runs if the conditional flag is set: {doc}"
+        ));
+    }
+
+    let (_, usage, doc) = COMMAND_DOCS.iter().find(|(name, ..)| *name == cmd)?;
+    Some(format!("```asm\n{usage}\n```\n{doc}"))
+}
+
+// Every register name: 16-bit (`ax`), 8-bit halves (`ah`, `al`), 32-bit
+// pairs (`abx`) and the pointers (`sp`, `fp`)
+fn register_names() -> Vec<String> {
+    let regs: Vec<char> = REGISTERS
+        .iter()
+        .copied()
+        .filter(char::is_ascii_lowercase)
+        .collect();
+
+    let mut res: Vec<String> = POINTERS.iter().map(|&name| name.to_owned()).collect();
+    for &a in &regs {
+        res.push(format!("{a}x"));
+        res.push(format!("{a}h"));
+        res.push(format!("{a}l"));
+    }
+    for &a in &regs {
+        for &b in regs.iter().filter(|&&b| b != a) {
+            res.push(format!("{a}{b}x"));
+        }
+    }
+    res.extend((0..FLOAT_REGISTER_COUNT).map(|i| format!("f{i}")));
+    res
+}
+
+// The number of characters `node` takes up in the source
+fn node_len(node: &Node) -> usize {
+    match node {
+        Node::Keyword(str) | Node::Register(str) | Node::Branch(str) => str.chars().count(),
+        Node::Numeric(val) => val.to_string().len(),
+        Node::Float(val) => float_text(*val).len(),
+        Node::String(str) => str.chars().count() + 2,
+        Node::Char(_) => 2,
+        Node::Punctuation(_) => 1,
+        Node::Comment(str) => str.chars().count() + 1,
+        #[cfg(feature = "inline-python")]
+        Node::InlinePy(str) => str.chars().count() + 2,
+    }
+}
+
+fn range(span: Span, len: usize) -> Value {
+    json!({
+        "start": { "line": span.line - 1, "character": span.col - 1 },
+        "end": { "line": span.line - 1, "character": span.col - 1 + len },
+    })
+}
+
+fn location(uri: &str, span: Span, len: usize) -> Value {
+    json!({ "uri": uri, "range": range(span, len) })
+}
+
+// A lexed document, along with where its top-level branches are defined
+struct Document {
+    nodes: Vec<(Node, Span)>,
+    definitions: Vec<(String, Span, usize)>,
+}
+
+impl Document {
+    fn new(text: &str) -> Self {
+        let nodes = lexer::lex_spanned(text).unwrap_or_default();
+
+        // Branches only end at a `.`, so anything opened before that is nested
+        // and can't be jumped to
+        let mut depth = 0_usize;
+        let mut definitions = Vec::new();
+        for (node, span) in &nodes {
+            if let Node::Branch(name) = node {
+                if name == "." {
+                    depth = depth.saturating_sub(1);
+                } else if let Some(def) = name.strip_prefix('.') {
+                    if depth == 0 {
+                        definitions.push((def.to_owned(), *span, name.chars().count()));
+                    }
+                    depth += 1;
+                }
+            }
+        }
+
+        Self { nodes, definitions }
+    }
+
+    // The node under a 0-indexed LSP position
+    fn node_at(&self, pos: &Value) -> Option<&(Node, Span)> {
+        let line = usize::try_from(pos["line"].as_u64()?).ok()? + 1;
+        let col = usize::try_from(pos["character"].as_u64()?).ok()? + 1;
+
+        self.nodes.iter().find(|(node, span)| {
+            span.line == line && span.col <= col && col < span.col + node_len(node).max(1)
+        })
+    }
+
+    // The name of the branch or label under `pos`
+    fn branch_at(&self, pos: &Value) -> Option<&str> {
+        match self.node_at(pos)? {
+            (Node::Branch(name), _) if name.len() > 1 => Some(&name[1..]),
+            _ => None,
+        }
+    }
+
+    fn definition(&self, name: &str) -> Option<(Span, usize)> {
+        self.definitions
+            .iter()
+            .find(|(def, ..)| def == name)
+            .map(|&(_, span, len)| (span, len))
+    }
+
+    fn references(&self, name: &str) -> Vec<(Span, usize)> {
+        self.nodes
+            .iter()
+            .filter_map(|(node, span)| match node {
+                Node::Branch(label) if label.starts_with(':') && &label[1..] == name => {
+                    Some((*span, label.chars().count()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// A language server speaking LSP over a pair of streams (normally stdio)
+pub struct Server<W: Write> {
+    out: W,
+    docs: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            docs: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    fn send(&mut self, msg: &Value) -> io::Result<()> {
+        let body = msg.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let text = self.docs.get(uri).map_or("", String::as_str);
+        let doc = Document::new(text);
+
+        let diagnostics: Vec<Value> = checker::check(text)
+            .into_iter()
+            .map(|d| {
+                let span = Span {
+                    line: d.span.line.max(1),
+                    col: d.span.col.max(1),
+                };
+                let len = doc
+                    .nodes
+                    .iter()
+                    .find(|(_, at)| *at == span)
+                    .map_or(1, |(node, _)| node_len(node));
+
+                json!({
+                    "range": range(span, len),
+                    "severity": if d.severity == Severity::Error { 1 } else { 2 },
+                    "source": "arsm",
+                    "message": d.msg,
+                })
+            })
+            .collect();
+
+        self.send(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn document<'a>(&'a self, params: &'a Value) -> Option<(&'a str, Document)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let text = self.docs.get(uri)?;
+        Some((uri, Document::new(text)))
+    }
+
+    fn definition(&self, params: &Value) -> Option<Value> {
+        let (uri, doc) = self.document(params)?;
+        let name = doc.branch_at(&params["position"])?;
+        let (span, len) = doc.definition(name)?;
+        Some(location(uri, span, len))
+    }
+
+    fn references(&self, params: &Value) -> Option<Value> {
+        let (uri, doc) = self.document(params)?;
+        let name = doc.branch_at(&params["position"])?;
+
+        let mut res = Vec::new();
+        if params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(true)
+        {
+            if let Some((span, len)) = doc.definition(name) {
+                res.push(location(uri, span, len));
+            }
+        }
+        for (span, len) in doc.references(name) {
+            res.push(location(uri, span, len));
+        }
+
+        Some(Value::Array(res))
+    }
+
+    fn hover(&self, params: &Value) -> Option<Value> {
+        let (_, doc) = self.document(params)?;
+        let (node, span) = doc.node_at(&params["position"])?;
+        let Node::Keyword(name) = node else {
+            return None;
+        };
+
+        Some(json!({
+            "contents": { "kind": "markdown", "value": command_doc(name)? },
+            "range": range(*span, node_len(node)),
+        }))
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let mut commands: Vec<&String> = COMMANDS.keys().collect();
+        commands.sort();
+
+        let mut items: Vec<Value> = commands
+            .into_iter()
+            .map(|cmd| {
+                json!({
+                    "label": cmd,
+                    "kind": 14,
+                    "documentation": { "kind": "markdown", "value": command_doc(cmd).unwrap_or_default() },
+                })
+            })
+            .collect();
+        items.extend(
+            register_names()
+                .into_iter()
+                .map(|reg| json!({ "label": reg, "kind": 6 })),
+        );
+        if let Some((_, doc)) = self.document(params) {
+            items.extend(
+                doc.definitions
+                    .iter()
+                    .map(|(name, ..)| json!({ "label": format!(":{name}"), "kind": 18 })),
+            );
+        }
+
+        Value::Array(items)
+    }
+
+    // Handles a single message, returning `false` once the client asks the
+    // server to exit
+    pub fn handle(&mut self, msg: &Value) -> io::Result<bool> {
+        let method = msg["method"].as_str().unwrap_or_default();
+        let params = &msg["params"];
+
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": [":"] },
+                },
+                "serverInfo": { "name": "arsm", "version": env!("CARGO_PKG_VERSION") },
+            })),
+
+            "shutdown" => {
+                self.shutdown = true;
+                Some(Value::Null)
+            }
+
+            "exit" => return Ok(false),
+
+            "textDocument/didOpen" => {
+                if let (Some(uri), Some(text)) = (
+                    params["textDocument"]["uri"].as_str(),
+                    params["textDocument"]["text"].as_str(),
+                ) {
+                    self.docs.insert(uri.to_owned(), text.to_owned());
+                    self.publish_diagnostics(uri)?;
+                }
+                None
+            }
+
+            "textDocument/didChange" => {
+                // Only full document syncs are supported, so the last change
+                // holds the whole text
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let (Some(uri), Some(text)) = (params["textDocument"]["uri"].as_str(), text) {
+                    self.docs.insert(uri.to_owned(), text.to_owned());
+                    self.publish_diagnostics(uri)?;
+                }
+                None
+            }
+
+            "textDocument/didClose" => {
+                if let Some(uri) = params["textDocument"]["uri"].as_str() {
+                    self.docs.remove(uri);
+                    self.publish_diagnostics(uri)?;
+                }
+                None
+            }
+
+            "textDocument/definition" => Some(self.definition(params).unwrap_or(Value::Null)),
+
+            "textDocument/references" => Some(self.references(params).unwrap_or(Value::Null)),
+
+            "textDocument/hover" => Some(self.hover(params).unwrap_or(Value::Null)),
+
+            "textDocument/completion" => Some(self.completion(params)),
+
+            _ => None,
+        };
+
+        // Notifications don't get a response
+        if let Some(id) = msg.get("id") {
+            let response = match result {
+                Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                None => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": format!("Unknown method {method}") },
+                }),
+            };
+            self.send(&response)?;
+        }
+
+        Ok(true)
+    }
+}
+
+// Reads a single `Content-Length` framed message, or `None` at the end of the
+// stream
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(val) = line.strip_prefix("Content-Length:") {
+            len = val.trim().parse().ok();
+        }
+    }
+
+    let Some(len) = len else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Message is missing a Content-Length header",
+        ));
+    };
+    let mut buf = vec![0; len];
+    input.read_exact(&mut buf)?;
+    Ok(Some(serde_json::from_slice(&buf)?))
+}
+
+// Serves requests from `input` until the client exits, returning whether it
+// shut down cleanly first
+pub fn serve(mut input: impl BufRead, out: impl Write) -> io::Result<bool> {
+    let mut server = Server::new(out);
+    while let Some(msg) = read_message(&mut input)? {
+        if !server.handle(&msg)? {
+            break;
+        }
+    }
+
+    Ok(server.shutdown)
+}
+
+#[cfg(test)]
+mod lsp_tests {
+    use super::*;
+
+    const URI: &str = "file:///prog.asm";
+    const PROGRAM: &str =
+        ".loop\n    mov eh in\n    cz eh\n    cjm :end\n    jmp :loop\n..end\n    jmp :missing";
+
+    fn frame(msg: &Value) -> String {
+        let body = msg.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    // Opens `PROGRAM`, sends `msgs`, and returns every message the server sent
+    fn run(msgs: &[Value]) -> Vec<Value> {
+        let mut input = frame(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "languageId": "arsm", "version": 1, "text": PROGRAM } },
+        }));
+        for msg in msgs {
+            input.push_str(&frame(msg));
+        }
+
+        let mut out = Vec::new();
+        serve(input.as_bytes(), &mut out).unwrap();
+
+        let mut out = &out[..];
+        let mut res = Vec::new();
+        while let Some(msg) = read_message(&mut out).unwrap() {
+            res.push(msg);
+        }
+        res
+    }
+
+    fn request(id: u64, method: &str, line: u64, character: u64) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": true },
+            },
+        })
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let res = run(&[]);
+        let diagnostics = &res[0]["params"]["diagnostics"];
+
+        assert_eq!(res[0]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(
+            diagnostics[0]["message"],
+            "No matching branch for label :missing"
+        );
+        assert_eq!(diagnostics[0]["severity"], 1);
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({ "line": 6, "character": 4 })
+        );
+    }
+
+    #[test]
+    fn test_definition() {
+        let res = run(&[request(1, "textDocument/definition", 3, 10)]);
+
+        assert_eq!(res[1]["id"], 1);
+        assert_eq!(
+            res[1]["result"]["range"],
+            json!({ "start": { "line": 5, "character": 1 }, "end": { "line": 5, "character": 5 } })
+        );
+    }
+
+    #[test]
+    fn test_references() {
+        let res = run(&[request(1, "textDocument/references", 0, 2)]);
+        let lines: Vec<&Value> = res[1]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|loc| &loc["range"]["start"]["line"])
+            .collect();
+
+        assert_eq!(lines, vec![0, 4]);
+    }
+
+    #[test]
+    fn test_hover() {
+        let res = run(&[request(1, "textDocument/hover", 3, 5)]);
+        let doc = res[1]["result"]["contents"]["value"].as_str().unwrap();
+
+        assert!(doc.starts_with("```asm\ncjm <A>\n```\nConditional `jmp`"));
+        assert!(command_doc("assert_eq").is_some());
+        assert!(COMMANDS.keys().all(|cmd| command_doc(cmd).is_some()));
+    }
+
+    #[test]
+    fn test_completion() {
+        let res = run(&[request(1, "textDocument/completion", 0, 0)]);
+        let labels: Vec<&str> = res[1]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect();
+
+        assert!(labels.contains(&"mov"));
+        assert!(labels.contains(&"abx"));
+        assert!(labels.contains(&"eh"));
+        assert!(labels.contains(&":end"));
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let mut input = frame(&json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" }));
+        input.push_str(&frame(&json!({ "jsonrpc": "2.0", "method": "exit" })));
+        let mut out = Vec::new();
+
+        assert!(serve(input.as_bytes(), &mut out).unwrap());
+    }
+}
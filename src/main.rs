@@ -1,65 +1,220 @@
 #![deny(rust_2018_idioms, clippy::all)]
-#![deny(mutable_borrow_reservation_conflict)]
 #![warn(clippy::pedantic)]
-#![allow(
-    clippy::similar_names,
-    clippy::module_name_repetitions,
-    clippy::pub_enum_variant_names,
-    clippy::if_not_else,
-    clippy::option_if_let_else,
-    clippy::wildcard_imports,
-    clippy::too_many_lines
-)]
 
 #[macro_use]
 extern crate clap;
-#[macro_use]
-extern crate lazy_static;
-
-mod ast;
-mod env;
-mod lexer;
-mod parser;
-#[macro_use]
-mod utils;
 
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::ops::Range;
 use std::time::Instant;
 
-use clap::ArgMatches;
+use arsm::binary::{self, Program};
+use arsm::checker::{self, Severity};
+use arsm::optimizer::{self, Rule};
+use arsm::utils::{
+    input::{Input, Recorder},
+    token::Op,
+    watch::Watch,
+};
+use arsm::{
+    debugger::Debugger, disasm, dump, emit_c, emit_wat, formatter, lsp, parser, snapshot, tester,
+    Environment, Error,
+};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-fn main() {
-    let matches = clap_app!(arsm =>
+fn app() -> App<'static, 'static> {
+    clap_app!(arsm =>
         (version: "0.2.4")
         (author: "Joshua B. <zippymagician1@gmail.com>")
         (about: "A toy assembly flavor written in rust")
         (@arg INPUT: +required "The input file to be run")
-        (@arg STDIN: "STDIN for the program")
+        (@arg STDIN: "The program's input with `-u`, or the file it is in with `-f`. Read from stdin otherwise")
         (@group stdin =>
-            (@arg user: -u --user "The program's input")
-            (@arg file: -f --file "The program's input, through a file")
+            (@arg user: -u --user "Takes the program's input from STDIN")
+            (@arg file: -f --file "Takes the program's input from the file STDIN")
+            (@arg replay: --replay +takes_value "Takes the program's input from a log written with --record")
         )
+        (@arg record: --record +takes_value conflicts_with[replay]
+            "Logs the input the program reads to this file, so the run can be reproduced with --replay")
+        (@arg snapshot: --snapshot +takes_value "Saves the program's state to this file once it stops, to resume it from later")
+        (@arg resume: --resume +takes_value "Resumes the program from a snapshot saved with --snapshot")
+        (@arg steps: --steps +takes_value "Pauses the program once it ran this many commands")
+        (@arg interactive: -i --interactive conflicts_with[steps]
+            "Runs the program in a debugger that can step through it, forwards and backwards")
+        (@arg watch: --watch +takes_value +multiple number_of_values(1)
+            "Reports every write to this register, `flag`, `stack` or memory (like #[3] or #[3..10])")
+        (@arg rwatch: --rwatch +takes_value +multiple number_of_values(1)
+            "Reports every read of this register, `flag`, `stack` or memory")
+        (@arg awatch: --awatch +takes_value +multiple number_of_values(1)
+            "Reports every read of and write to this register, `flag`, `stack` or memory")
         (@arg debug: -d --debug "Use this flag to enable some debug features")
+        (@arg dump_range: --("dump-range") +takes_value requires[debug]
+            "Only dumps the cells in this range of addresses, like 16..64, 16.. or ..64")
+        (@arg dump_json: --("dump-json") requires[debug] "Dumps memory as JSON")
         (@arg timed: -t --time "Times how long the program took and outputs it after running")
+        (@arg ARGS: +multiple +last "Arguments for the program, after `--`")
+        (@arg allow_env: --("allow-env") +takes_value +multiple number_of_values(1)
+            "Lets the program look up this environment variable. It can't look up any otherwise")
+        (@arg allow_dir: --("allow-dir") +takes_value +multiple number_of_values(1)
+            "Lets the program open files inside of this directory. No files can be opened otherwise")
+        (@arg optimize: -O --optimize "Runs the peephole optimizer over the program first")
+        (@arg no_opt: --("no-opt") +takes_value +multiple number_of_values(1) requires[optimize]
+            "Turns one of the optimizer's rules off (inc-dec, self-mov, push-pop or const-cmp)")
     )
-    .get_matches();
+}
+
+fn subcommands() -> Vec<App<'static, 'static>> {
+    vec![
+        clap_app!(test =>
+            (about: "Runs every branch whose name starts with `test` and reports the results")
+            (@arg INPUT: +required "The file containing the tests")
+        ),
+        clap_app!(fmt =>
+            (about: "Formats source files in place")
+            (@arg check: --check "Only check whether the files are formatted, failing if not")
+            (@arg INPUT: +required +multiple "The files to format")
+        ),
+        clap_app!(check =>
+            (about: "Looks for mistakes in source files without running them")
+            (@arg INPUT: +required +multiple "The files to check")
+        ),
+        clap_app!(build =>
+            (about: "Compiles a source file into a binary that `arsm` can run directly")
+            (@arg INPUT: +required "The file to compile")
+            (@arg output: -o --output +takes_value "Where to write the binary, defaults to INPUT with an `.arsmb` extension")
+            (@arg lines: -g --("debug-info") "Include line info, so runtime errors point into the source")
+        ),
+        clap_app!(disasm =>
+            (about: "Prints a compiled program, or the instructions a source file compiles to, as source")
+            (@arg INPUT: +required "The program to disassemble")
+        ),
+        clap_app!(lsp =>
+            (about: "Runs a language server, speaking LSP over stdio")
+        ),
+        // `clap_app!` can't name subcommands with a `-` in them
+        emit_subcommand(
+            "emit-c",
+            "Translates a program into a self-contained C program",
+        ),
+        emit_subcommand(
+            "emit-wat",
+            "Translates a program into a WebAssembly text module",
+        ),
+    ]
+}
+
+// Parses the arguments `arsm` was called with. Subcommands are only looked for
+// if the first argument names one exactly (or is a flag, like `--help`), since
+// clap otherwise takes a program named like a subcommand, or close to one (like
+// `ch` or `tests.asm`), for a misspelt subcommand
+fn matches() -> ArgMatches<'static> {
+    let args: Vec<_> = std::env::args_os().collect();
+    let subcommands = subcommands();
+    let wants_subcommand = args.get(1).and_then(|arg| arg.to_str()).is_none_or(|arg| {
+        arg.starts_with('-') || arg == "help" || subcommands.iter().any(|app| app.get_name() == arg)
+    });
+
+    if wants_subcommand {
+        app()
+            .setting(AppSettings::SubcommandsNegateReqs)
+            // So flags followed by a program named like a subcommand still run it
+            .setting(AppSettings::ArgsNegateSubcommands)
+            .subcommands(subcommands)
+            .get_matches_from(args)
+    } else {
+        app().get_matches_from(args)
+    }
+}
+
+// Runs the subcommand in `matches`, returning the exit code, or `None` if there
+// isn't one and the input should be run
+fn run_subcommand(matches: &ArgMatches<'_>) -> Option<i32> {
+    let (name, Some(matches)) = matches.subcommand() else {
+        return None;
+    };
+
+    Some(match name {
+        "test" => run_tests(matches.value_of("INPUT").unwrap()),
+
+        "fmt" => {
+            let check = matches.is_present("check");
+            matches
+                .values_of("INPUT")
+                .unwrap()
+                .map(|file| format_file(file, check))
+                .fold(0, i32::max)
+        }
+
+        "check" => matches
+            .values_of("INPUT")
+            .unwrap()
+            .map(check_file)
+            .fold(0, i32::max),
+
+        "build" => build_file(
+            matches.value_of("INPUT").unwrap(),
+            matches.value_of("output"),
+            matches.is_present("lines"),
+        ),
+
+        "disasm" => disasm_file(matches.value_of("INPUT").unwrap()),
+
+        "emit-c" => emit_file(
+            matches.value_of("INPUT").unwrap(),
+            matches.value_of("output"),
+            emit_c::emit,
+        ),
+
+        "emit-wat" => emit_file(
+            matches.value_of("INPUT").unwrap(),
+            matches.value_of("output"),
+            emit_wat::emit,
+        ),
+
+        "lsp" => {
+            let stdin = std::io::stdin();
+            match lsp::serve(stdin.lock(), std::io::stdout()) {
+                Ok(true) => 0,
+                Ok(false) => 1,
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    1
+                }
+            }
+        }
+
+        _ => return None,
+    })
+}
+
+fn main() {
+    let matches = matches();
+    if let Some(code) = run_subcommand(&matches) {
+        std::process::exit(code);
+    }
 
     // You can call `unwrap` here as INPUT is required
     let file = matches.value_of("INPUT").unwrap();
-    if let Ok(program) = handle_input(file) {
-        if matches.is_present("timed") {
+    if let Ok(program) = handle_binary_input(file) {
+        let code = if matches.is_present("timed") {
             let t0 = Instant::now();
-            run_program(&*program, &matches);
+            let code = run_program(file, &program, &matches);
             let t1 = Instant::now();
             println!("\nTime taken: {:?}", t1 - t0);
+            code
         } else {
-            run_program(&*program, &matches);
-        }
+            run_program(file, &program, &matches)
+        };
+
+        std::process::exit(code);
     } else {
-        panic!("File not found: {}", file);
+        eprintln!("File not found: {file}");
+        std::process::exit(1);
     }
 }
 
-#[inline(always)]
+#[inline]
 fn handle_input(c: &str) -> Result<String, std::io::Error> {
     #[cfg(feature = "literal-code")]
     return Ok(c.to_owned());
@@ -68,9 +223,378 @@ fn handle_input(c: &str) -> Result<String, std::io::Error> {
     return std::fs::read_to_string(c);
 }
 
+// Like `handle_input`, but also accepts compiled programs
+#[inline]
+fn handle_binary_input(c: &str) -> Result<Vec<u8>, std::io::Error> {
+    #[cfg(feature = "literal-code")]
+    return Ok(c.as_bytes().to_vec());
+
+    #[cfg(not(feature = "literal-code"))]
+    return std::fs::read(c);
+}
+
+// Prints `e`, prefixed by where in `file` it happened
+fn report_error(file: &str, e: &Error) {
+    match e.span {
+        Some(span) => eprintln!("Error: {}:{}: {}", file, span, e.msg),
+        None => eprintln!("Error: {}", e.msg),
+    }
+}
+
+// The input to run a program with, as picked by `matches`. `None` (after
+// reporting why) if it can't be opened
+fn program_input(matches: &ArgMatches<'_>) -> Option<Input> {
+    let source: Box<dyn Read> = if matches.is_present("file") || matches.is_present("replay") {
+        let path = matches
+            .value_of("replay")
+            .or_else(|| matches.value_of("STDIN"))
+            .unwrap_or_default();
+        let Ok(file) = File::open(path) else {
+            eprintln!("File not found: {path}");
+            return None;
+        };
+        Box::new(file)
+    } else if matches.is_present("user") {
+        Box::new(Cursor::new(
+            matches
+                .value_of("STDIN")
+                .unwrap_or_default()
+                .as_bytes()
+                .to_vec(),
+        ))
+    } else if matches.is_present("interactive") {
+        // The debugger's commands are read from stdin instead
+        Box::new(std::io::empty())
+    } else {
+        Box::new(std::io::stdin())
+    };
+
+    if let Some(path) = matches.value_of("record") {
+        let Ok(log) = File::create(path) else {
+            eprintln!("Could not write {path}");
+            return None;
+        };
+        Some(Input::new(Recorder::new(source, log)))
+    } else {
+        Some(Input::new(source))
+    }
+}
+
+// The watches asked for in `matches`. `None` (after reporting why) if one of
+// them isn't something that can be watched
+fn watches(matches: &ArgMatches<'_>) -> Option<Vec<Watch>> {
+    let mut watches = Vec::new();
+    for (arg, reads, writes) in [
+        ("watch", false, true),
+        ("rwatch", true, false),
+        ("awatch", true, true),
+    ] {
+        for text in matches.values_of(arg).unwrap_or_default() {
+            let Some(watch) = Watch::parse(text, reads, writes) else {
+                eprintln!("Can't watch {text}");
+                return None;
+            };
+            watches.push(watch);
+        }
+    }
+    Some(watches)
+}
+
+// The cells `-d` dumps, as picked by `matches`. `None` (after reporting why)
+// if the range can't be read
+fn dump_range(matches: &ArgMatches<'_>) -> Option<Range<usize>> {
+    let Some(text) = matches.value_of("dump_range") else {
+        return Some(0..usize::MAX);
+    };
+    let bound = |bound: &str, default| {
+        if bound.is_empty() {
+            Some(default)
+        } else {
+            bound.parse().ok()
+        }
+    };
+    let range = text
+        .split_once("..")
+        .and_then(|(start, end)| Some(bound(start, 0)?..bound(end, usize::MAX)?));
+    if range.is_none() {
+        eprintln!("Invalid range: {text}");
+    }
+    range
+}
+
+// Gives the program in `file` its arguments, and access to what `matches`
+// allows. False (after reporting why) if an allowed directory doesn't exist
+fn grant_access(env: &mut Environment, file: &str, matches: &ArgMatches<'_>) -> bool {
+    env.args = std::iter::once(file)
+        .chain(matches.values_of("ARGS").unwrap_or_default())
+        .map(str::to_owned)
+        .collect();
+    for name in matches.values_of("allow_env").unwrap_or_default() {
+        if let Some(val) = std::env::var_os(name) {
+            env.vars
+                .insert(name.to_owned(), val.to_string_lossy().into_owned());
+        }
+    }
+    for dir in matches.values_of("allow_dir").unwrap_or_default() {
+        if env.files.allow(dir).is_err() {
+            eprintln!("Directory not found: {dir}");
+            return false;
+        }
+    }
+    true
+}
+
 #[inline]
-fn run_program(program: &str, matches: &ArgMatches<'_>) {
-    let lexed = lexer::lex(program);
-    let tree = ast::construct_tree(&lexed);
-    parser::parse(&tree, matches);
+fn run_program(file: &str, program: &[u8], matches: &ArgMatches<'_>) -> i32 {
+    let (Some(stdin), Some(watches)) = (program_input(matches), watches(matches)) else {
+        return 1;
+    };
+    let Some(cells) = dump_range(matches) else {
+        return 1;
+    };
+
+    let steps = match matches.value_of("steps").map(str::parse::<u64>) {
+        Some(Ok(steps)) => Some(steps),
+        Some(Err(_)) => {
+            eprintln!(
+                "Invalid number of steps: {}",
+                matches.value_of("steps").unwrap_or_default()
+            );
+            return 1;
+        }
+        None => None,
+    };
+
+    let rules = if matches.is_present("optimize") {
+        let off: Vec<&str> = matches.values_of("no_opt").unwrap_or_default().collect();
+        if let Some(name) = off.iter().find(|name| Rule::from_name(name).is_none()) {
+            eprintln!("Unknown optimizer rule: {name}");
+            return 1;
+        }
+
+        Some(
+            Rule::ALL
+                .iter()
+                .copied()
+                .filter(|rule| !off.contains(&rule.name()))
+                .collect::<Vec<_>>(),
+        )
+    } else {
+        None
+    };
+
+    let mut env = Environment::new();
+    env.stdin = stdin;
+    env.mem.heap.debug = matches.is_present("debug");
+    env.mem.watches = watches;
+    if !grant_access(&mut env, file, matches) {
+        return 1;
+    }
+    let resume = matches.value_of("resume");
+    if let Some(path) = resume {
+        let Ok(snapshot) = std::fs::read_to_string(path) else {
+            eprintln!("File not found: {path}");
+            return 1;
+        };
+        if let Err(e) = snapshot::restore(&mut env, &snapshot, program) {
+            report_error(path, &e);
+            return 1;
+        }
+    }
+    env.step_limit = steps.map(|steps| env.steps.saturating_add(steps));
+
+    let res = arsm::load(program).and_then(|tree| {
+        let tree = match rules {
+            Some(rules) => optimizer::optimize(&tree, &rules),
+            None => tree,
+        };
+        if matches.is_present("interactive") {
+            let source = std::str::from_utf8(program)
+                .ok()
+                .filter(|_| !binary::is_binary(program));
+            Debugger::new(&tree, source, &mut env)
+                .run(std::io::stdin().lock(), &mut std::io::stdout())
+        } else if resume.is_some() {
+            parser::resume(&tree, &mut env)
+        } else {
+            parser::parse(&tree, &mut env)
+        }
+    });
+
+    if let Some(path) = matches.value_of("snapshot") {
+        if std::fs::write(path, snapshot::save(&env, program).to_string()).is_err() {
+            eprintln!("Could not write {path}");
+            return 1;
+        }
+    }
+
+    if matches.is_present("debug") {
+        if matches.is_present("dump_json") {
+            println!("\n{}", dump::json(&env.mem, &cells));
+        } else {
+            print!("\n{}", dump::text(&env.mem, &cells));
+        }
+    }
+
+    match res {
+        Ok(code) => code,
+        Err(e) => {
+            report_error(file, &e);
+            1
+        }
+    }
+}
+
+fn run_tests(file: &str) -> i32 {
+    let Ok(program) = handle_input(file) else {
+        eprintln!("File not found: {file}");
+        return 1;
+    };
+
+    match tester::run_tests(&program) {
+        Ok(results) => match tester::report(file, &results, &mut std::io::stdout()) {
+            Ok(true) => 0,
+            _ => 1,
+        },
+        Err(e) => {
+            report_error(file, &e);
+            1
+        }
+    }
+}
+
+fn format_file(file: &str, check: bool) -> i32 {
+    let Ok(program) = std::fs::read_to_string(file) else {
+        eprintln!("File not found: {file}");
+        return 1;
+    };
+
+    match formatter::format(&program) {
+        Ok(formatted) if formatted == program => 0,
+        Ok(_) if check => {
+            println!("Would reformat: {file}");
+            1
+        }
+        Ok(formatted) => {
+            if let Err(e) = std::fs::write(file, formatted) {
+                eprintln!("Could not write {file}: {e}");
+                return 1;
+            }
+            0
+        }
+        Err(e) => {
+            report_error(file, &e);
+            1
+        }
+    }
+}
+
+fn check_file(file: &str) -> i32 {
+    let Ok(program) = handle_input(file) else {
+        eprintln!("File not found: {file}");
+        return 1;
+    };
+
+    let diagnostics = checker::check(&program);
+    for d in &diagnostics {
+        eprintln!("{}:{}: {}: {}", file, d.span, d.severity, d.msg);
+    }
+
+    i32::from(diagnostics.iter().any(|d| d.severity == Severity::Error))
+}
+
+fn build_file(file: &str, output: Option<&str>, lines: bool) -> i32 {
+    let Ok(program) = handle_input(file) else {
+        eprintln!("File not found: {file}");
+        return 1;
+    };
+
+    let bytes = match arsm::compile(&program).and_then(|tree| Program::from_tree(&tree, lines)) {
+        Ok(program) => program.to_bytes(),
+        Err(e) => {
+            report_error(file, &e);
+            return 1;
+        }
+    };
+
+    let output = output.map_or_else(
+        || std::path::Path::new(file).with_extension("arsmb"),
+        std::path::PathBuf::from,
+    );
+    if let Err(e) = std::fs::write(&output, bytes) {
+        eprintln!("Could not write {}: {}", output.display(), e);
+        return 1;
+    }
+    0
+}
+
+fn disasm_file(file: &str) -> i32 {
+    let Ok(bytes) = handle_binary_input(file) else {
+        eprintln!("File not found: {file}");
+        return 1;
+    };
+
+    let program = if binary::is_binary(&bytes) {
+        Program::from_bytes(&bytes)
+    } else {
+        arsm::load(&bytes).and_then(|tree| Program::from_tree(&tree, true))
+    };
+
+    match program.and_then(|program| disasm::disassemble(&program)) {
+        Ok(listing) => {
+            print!("{listing}");
+            0
+        }
+        Err(e) => {
+            report_error(file, &e);
+            1
+        }
+    }
+}
+
+fn emit_subcommand<'a>(name: &'a str, about: &'a str) -> App<'a, 'a> {
+    SubCommand::with_name(name)
+        .about(about)
+        .arg(
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("The program to translate"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("Where to write the result, defaults to stdout"),
+        )
+}
+
+// Translates `file` with `emit`, writing the result to `output` or stdout
+fn emit_file<F>(file: &str, output: Option<&str>, emit: F) -> i32
+where
+    F: Fn(&[Op]) -> arsm::Result<String>,
+{
+    let Ok(bytes) = handle_binary_input(file) else {
+        eprintln!("File not found: {file}");
+        return 1;
+    };
+
+    let res = match arsm::load(&bytes).and_then(|tree| emit(&tree)) {
+        Ok(res) => res,
+        Err(e) => {
+            report_error(file, &e);
+            return 1;
+        }
+    };
+
+    match output {
+        Some(output) => {
+            if let Err(e) = std::fs::write(output, res) {
+                eprintln!("Could not write {output}: {e}");
+                return 1;
+            }
+        }
+        None => print!("{res}"),
+    }
+    0
 }
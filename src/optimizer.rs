@@ -0,0 +1,426 @@
+// A peephole optimizer over the tree `ast::construct_tree` builds, run with
+// `-O`. Each rule can be turned off on its own, see `Rule`.
+//
+// Removed instructions are replaced by `Op::Empty`, which runs as a no-op,
+// instead of being deleted. Where every op sits is observable (labels and
+// numeric jumps are indices, `ret` comes back to the op after the jump, and a
+// branch that is fallen into starts at whatever index the last one stopped
+// at), so nothing is ever allowed to move. For the same reason a rewrite only
+// looks at instructions that run straight after one another, and never across
+// an op that control can also get to some other way (see `top_entries` and
+// `branch_entries`).
+//
+// The only programs that behave differently are ones that would have failed
+// inside the removed code, like `inc al` with `al` at 255 or a `psh` onto a
+// full stack
+use std::collections::HashSet;
+
+use crate::checker::width;
+use crate::utils::{consts::CONDITIONALS, token::Op};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Rule {
+    // `inc X` directly followed by `dec X`, or the other way around, is removed
+    IncDec,
+    // `mov X X` (or `cmo X X`) for a register `X` is removed
+    SelfMov,
+    // `psh N V` directly followed by a `pop X` of the same size becomes `mov X V`
+    PushPop,
+    // Conditional instructions after a comparison of constants become
+    // unconditional, or are removed if the comparison is false
+    ConstCmp,
+}
+
+impl Rule {
+    pub const ALL: &'static [Self] = &[Self::IncDec, Self::SelfMov, Self::PushPop, Self::ConstCmp];
+
+    // The name the rule goes by on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::IncDec => "inc-dec",
+            Self::SelfMov => "self-mov",
+            Self::PushPop => "push-pop",
+            Self::ConstCmp => "const-cmp",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|rule| rule.name() == name)
+    }
+}
+
+// Commands that may carry on somewhere other than the next op
+const JUMPS: &[&str] = &["jmp", "cjm", "ret", "cre", "hlt", "chl"];
+
+// Commands that never touch the conditional flag
+#[rustfmt::skip]
+const KEEPS_FLAG: &[&str] = &[
+    "mov", "cmo", "inc", "cin", "dec", "cde", "out", "cou", "chr", "cch",
+    "mul", "cmu", "div", "cdi", "add", "cad", "sub", "csu", "str", "db",
+    "in", "psh", "cps", "pop", "cpo", "lsh", "cls", "rsh", "crs", "or",
+    "cor", "xor", "cxo", "and", "can", "not", "cno", "swp", "csw", "jmp",
+    "cjm", "ret", "cre", "hlt", "chl", "assert", "assert_eq", "assert_ne",
+    "hex", "bin", "pad", "prs", "eof", "err", "errc", "argc", "free", "fmov",
+    "fadd", "fsub", "fmul", "fdiv", "ftoi", "fld", "fst", "fout", "enter", "leave",
+    "lds", "sts", "ldf", "stf",
+];
+
+// Returns `tree` with `rules` applied
+pub fn optimize(tree: &[Op], rules: &[Rule]) -> Vec<Op> {
+    let mut tree = tree.to_vec();
+    let Some(stale) = stale_starts(&tree) else {
+        return tree;
+    };
+
+    let entries = top_entries(&tree);
+    optimize_ops(&mut tree, &entries, rules);
+    for op in &mut tree {
+        if let Op::Branch(_, body) = op {
+            let entries = branch_entries(body, &stale);
+            optimize_ops(body, &entries, rules);
+        }
+    }
+
+    tree
+}
+
+fn optimize_ops(ops: &mut [Op], entries: &[bool], rules: &[Rule]) {
+    if rules.contains(&Rule::ConstCmp) {
+        const_cmp(ops, entries);
+    }
+
+    // Removing a pair can leave another one around it, like `inc ax inc bx dec
+    // bx dec ax`, so keep going until nothing changes
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..ops.len() {
+            if rules.contains(&Rule::SelfMov) && is_self_mov(&ops[i]) {
+                ops[i] = Op::Empty;
+                changed = true;
+                continue;
+            }
+
+            let Some(j) = next_op(ops, entries, i) else {
+                continue;
+            };
+            if let Some((first, second)) = pair(&ops[i], &ops[j], rules) {
+                ops[i] = first;
+                ops[j] = second;
+                changed = true;
+            }
+        }
+    }
+}
+
+// The op that runs after `ops[i]`, skipping removed ones, as long as it can
+// only be reached from `ops[i]`
+fn next_op(ops: &[Op], entries: &[bool], i: usize) -> Option<usize> {
+    if ops[i] == Op::Empty {
+        return None;
+    }
+
+    (i + 1..ops.len())
+        .take_while(|&j| !entries[j])
+        .find(|&j| ops[j] != Op::Empty)
+}
+
+fn is_self_mov(op: &Op) -> bool {
+    match op {
+        Op::Cmd(name, args, _) if name == "mov" || name == "cmo" => {
+            matches!((&args[0], &args[1]), (Op::Register(a), Op::Register(b)) if a == b)
+        }
+        _ => false,
+    }
+}
+
+// What the two ops, run one after the other, can be replaced with
+fn pair(first: &Op, second: &Op, rules: &[Rule]) -> Option<(Op, Op)> {
+    let (Op::Cmd(a, a_args, span), Op::Cmd(b, b_args, _)) = (first, second) else {
+        return None;
+    };
+
+    match (a.as_str(), b.as_str()) {
+        ("inc", "dec") | ("dec", "inc")
+            if rules.contains(&Rule::IncDec) && a_args[0] == b_args[0] && is_place(&a_args[0]) =>
+        {
+            Some((Op::Empty, Op::Empty))
+        }
+
+        ("psh", "pop") if rules.contains(&Rule::PushPop) => {
+            let (Op::Numeric(size), val, dest) = (&a_args[0], &a_args[1], &b_args[0]) else {
+                return None;
+            };
+            if !is_value(val)
+                || !is_place(dest)
+                || width(dest).map(|w| i64::from(w / 8)) != Some(i64::from(*size))
+            {
+                return None;
+            }
+
+            let mov = Op::Cmd(String::from("mov"), vec![dest.clone(), val.clone()], *span);
+            Some((mov, Op::Empty))
+        }
+
+        _ => None,
+    }
+}
+
+// Sets off the conditional instructions that follow a comparison of constants
+fn const_cmp(ops: &mut [Op], entries: &[bool]) {
+    for i in 0..ops.len() {
+        let Some(flag) = constant_flag(&ops[i]) else {
+            continue;
+        };
+
+        for j in i + 1..ops.len() {
+            if entries[j] || !keeps_flag(&ops[j]) {
+                break;
+            }
+
+            let Op::Cmd(name, args, span) = &ops[j] else {
+                continue;
+            };
+            let jumps = JUMPS.contains(&name.as_str());
+            if let Some((_, cmd)) = CONDITIONALS.iter().find(|(cond, _)| cond == name) {
+                ops[j] = if flag {
+                    Op::Cmd((*cmd).to_string(), args.clone(), *span)
+                } else {
+                    Op::Empty
+                };
+            }
+
+            if jumps {
+                break;
+            }
+        }
+    }
+}
+
+// The value a comparison sets the flag to, if it only compares constants
+fn constant_flag(op: &Op) -> Option<bool> {
+    let Op::Cmd(name, args, _) = op else {
+        return None;
+    };
+
+    if name == "cz" {
+        return Some(constant(&args[0])? == 0);
+    }
+
+    let (left, right) = (constant(args.first()?)?, constant(args.get(1)?)?);
+    match name.as_str() {
+        "ceq" => Some(left == right),
+        "cne" => Some(left != right),
+        "cl" => Some(left < right),
+        "cle" => Some(left <= right),
+        "cg" => Some(left > right),
+        "cge" => Some(left >= right),
+        _ => None,
+    }
+}
+
+fn constant(op: &Op) -> Option<i32> {
+    match op {
+        Op::Numeric(val) => Some(*val),
+        Op::Char(chr) => Some(i32::from(*chr as u8)),
+        _ => None,
+    }
+}
+
+fn keeps_flag(op: &Op) -> bool {
+    match op {
+        Op::Cmd(name, args, _) => {
+            KEEPS_FLAG.contains(&name.as_str()) && args.iter().all(keeps_flag)
+        }
+        Op::Memory(_, op) => keeps_flag(op),
+        Op::Branch(..) => false,
+        #[cfg(feature = "inline-python")]
+        Op::InlinePy(_) => false,
+        _ => true,
+    }
+}
+
+// Something that can be read without side effects
+fn is_value(op: &Op) -> bool {
+    match op {
+        Op::Numeric(_) | Op::Char(_) | Op::Register(_) => true,
+        Op::Memory(_, addr) => is_value(addr),
+        _ => false,
+    }
+}
+
+// Something that can be written to, and that writing to doesn't move
+fn is_place(op: &Op) -> bool {
+    match op {
+        Op::Register(_) => true,
+        Op::Memory(_, addr) => matches!(**addr, Op::Numeric(_) | Op::Char(_) | Op::Register(_)),
+        _ => false,
+    }
+}
+
+fn jumps(op: &Op) -> bool {
+    matches!(op, Op::Cmd(name, ..) if JUMPS.contains(&name.as_str()))
+}
+
+// Where top-level execution can start other than by falling through from the
+// op before
+fn top_entries(tree: &[Op]) -> Vec<bool> {
+    // Labels are always the index of a branch, but anything else could be any
+    // index at all
+    let computed_jump = tree.iter().any(|op| match op {
+        Op::Cmd(name, args, _) => {
+            (name == "jmp" || name == "cjm") && !matches!(args[0], Op::Label(_))
+        }
+        Op::Branch(_, body) => body.iter().any(|op| {
+            matches!(op, Op::Cmd(name, args, _)
+                if (name == "jmp" || name == "cjm") && !matches!(args[0], Op::Label(_)))
+        }),
+        _ => false,
+    });
+
+    (0..tree.len())
+        .map(|i| computed_jump || i == 0 || jumps(&tree[i - 1]))
+        .collect()
+}
+
+// Where a branch can start other than by falling through from the op before.
+// Branches start at the index the last one stopped at, which is shared
+// between all of them
+fn branch_entries(body: &[Op], stale: &HashSet<usize>) -> Vec<bool> {
+    (0..body.len()).map(|i| stale.contains(&i)).collect()
+}
+
+// Every index a branch can start at: 0 after a jump, the length of a branch
+// that ran to its end, and the op after a jump out of a branch once it's
+// returned to. Returns `None` if the tree does something too hard to follow,
+// like nesting branches or jumping from inside an argument, in which case it
+// isn't optimized at all
+fn stale_starts(tree: &[Op]) -> Option<HashSet<usize>> {
+    let mut stale = HashSet::new();
+    stale.insert(0);
+
+    for op in tree {
+        if nested_jump(op) {
+            return None;
+        }
+
+        if let Op::Branch(_, body) = op {
+            stale.insert(body.len());
+            for (j, op) in body.iter().enumerate() {
+                if matches!(op, Op::Branch(..)) || nested_jump(op) {
+                    return None;
+                }
+                if jumps(op) {
+                    stale.insert(j + 1);
+                }
+            }
+        }
+    }
+
+    Some(stale)
+}
+
+// Whether a command jumps from one of its arguments
+fn nested_jump(op: &Op) -> bool {
+    let Op::Cmd(_, args, _) = op else {
+        return false;
+    };
+
+    args.iter().any(|arg| match arg {
+        Op::Cmd(..) => jumps(arg) || nested_jump(arg),
+        Op::Memory(_, addr) => jumps(addr) || nested_jump(addr),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod optimizer_tests {
+    use super::*;
+    use crate::compile;
+
+    fn optimized(program: &str, rules: &[Rule]) -> Vec<Op> {
+        optimize(&compile(program).unwrap(), rules)
+    }
+
+    // The names of the commands left in `tree`, with `-` for removed ones
+    fn names(tree: &[Op]) -> Vec<&str> {
+        tree.iter()
+            .map(|op| match op {
+                Op::Cmd(name, ..) => name.as_str(),
+                Op::Empty => "-",
+                _ => "?",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_inc_dec() {
+        let tree = optimized("inc ax\ninc bx\ndec bx\ndec ax\ninc al\ndec ah", Rule::ALL);
+        assert_eq!(names(&tree), ["-", "-", "-", "-", "inc", "dec"]);
+
+        let tree = optimized("inc #[3]\ndec #[3]\ninc #[#[0]]\ndec #[#[0]]", Rule::ALL);
+        assert_eq!(names(&tree), ["-", "-", "inc", "dec"]);
+
+        // `ax` is incremented on the way into the branch
+        let tree = optimized("inc ax\njmp :a\ndec ax\n.a\nout ax\n.", Rule::ALL);
+        assert_eq!(names(&tree), ["inc", "jmp", "dec", "?"]);
+
+        assert_eq!(names(&optimized("inc ax\ndec ax", &[])), ["inc", "dec"]);
+    }
+
+    #[test]
+    fn test_self_mov() {
+        let tree = optimized(
+            "mov ax ax\ncmo eax eax\nmov ax bx\nmov #[1] #[1]",
+            Rule::ALL,
+        );
+        assert_eq!(names(&tree), ["-", "-", "mov", "mov"]);
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let tree = optimized("psh 2 300\npop bx\npsh 1 bx\npop ax", Rule::ALL);
+        assert_eq!(names(&tree), ["mov", "-", "psh", "pop"]);
+        assert_eq!(tree[0], compile("mov bx 300").unwrap()[0]);
+
+        let tree = optimized("psh 4 @[2]\npop @[ax]\npsh 1 in\npop al", Rule::ALL);
+        assert_eq!(names(&tree), ["mov", "-", "psh", "pop"]);
+    }
+
+    #[test]
+    fn test_const_cmp() {
+        let tree = optimized("ceq 1 1\ncou 1\ncin ax\ncz 0\ncg 'a 'b\ncou 2", Rule::ALL);
+        assert_eq!(names(&tree), ["ceq", "out", "inc", "cz", "cg", "-"]);
+
+        // The flag is unknown again after anything that could change it
+        let tree = optimized("ceq 1 1\ncz ax\ncou 1\ncne 1 2\njmp :a\ncou 1", Rule::ALL);
+        assert_eq!(names(&tree), ["ceq", "cz", "cou", "cne", "jmp", "cou"]);
+
+        // `b` could also be started at index 2, where `a` ends
+        let tree = optimized(
+            ".a\ncz ax\nout 1\n.\n.b\ncg 2 1\ncou 1\ncou 2\n.",
+            Rule::ALL,
+        );
+        let Op::Branch(_, b) = &tree[1] else {
+            panic!("Expected a branch");
+        };
+        assert_eq!(names(b), ["cg", "out", "cou"]);
+    }
+
+    #[test]
+    fn test_computed_jump() {
+        let tree = optimized("jmp ax\ninc ax\ndec ax", Rule::ALL);
+        assert_eq!(names(&tree), ["jmp", "inc", "dec"]);
+
+        let tree = optimized("mov ax jmp :a\ninc ax\ndec ax\n.a\n.", Rule::ALL);
+        assert_eq!(names(&tree)[1..], ["inc", "dec", "?"]);
+    }
+
+    #[test]
+    fn test_rule_names() {
+        for &rule in Rule::ALL {
+            assert_eq!(Rule::from_name(rule.name()), Some(rule));
+        }
+        assert_eq!(Rule::from_name("inc"), None);
+    }
+}
@@ -1,237 +1,528 @@
-use clap::ArgMatches;
-
-use crate::utils::{token::Op, traits::*};
-use crate::{bx, env::Environment};
-
-pub fn parse(ast: &[Op], matches: &ArgMatches<'_>) {
-    let mut v = if matches.is_present("file") {
-        std::fs::read(matches.value_of("STDIN").unwrap_or_default()).unwrap_or_default()
-    } else if matches.is_present("user") {
-        matches
-            .value_of("STDIN")
-            .unwrap_or_default()
-            .as_bytes()
-            .into()
-    } else {
-        Vec::new()
-    };
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::checker::width as width_of;
+use crate::utils::{
+    consts::{MEM_SIZE, OFFSET},
+    error::Result,
+    token::{float_text, Op},
+    traits::*,
+};
+use crate::{bx, env::Environment, err};
+
+// Runs `ast` inside of `env`, returning the exit code passed to `hlt` (or 0 if
+// the program ran to completion)
+pub fn parse(ast: &[Op], env: &mut Environment) -> Result<i32> {
+    parse_from(ast, env, 0, |_| false)
+}
+
+// Same as `parse`, but starts at the top-level op `start` and stops as soon as
+// execution reaches a top-level op other than `start` that `stop` returns true for
+pub fn parse_from<F>(ast: &[Op], env: &mut Environment, start: usize, stop: F) -> Result<i32>
+where
+    F: Fn(usize) -> bool,
+{
+    env.set_parent(ast);
+    env.pos = (start, 0);
+
+    let res = run_ops(ast, env, start, stop);
+    finish(env, res)
+}
 
-    let mut env = Environment::new(&mut v);
+// Picks running `ast` back up wherever `env` was paused, like after it was
+// restored from a snapshot
+pub fn resume(ast: &[Op], env: &mut Environment) -> Result<i32> {
     env.set_parent(ast);
-    let mut ind = 0;
+    if let Some(code) = env.exit {
+        return Ok(code);
+    }
+
+    let res = run_ops(ast, env, env.pos.0, |_| false);
+    finish(env, res)
+}
+
+// Whatever was printed is flushed whether the program halted or failed
+fn finish(env: &mut Environment, res: Result<()>) -> Result<i32> {
+    let flushed = env.out.flush().and_then(|()| env.err.flush());
+    res?;
+    flushed?;
+    Ok(env.exit.unwrap_or(0))
+}
 
-    while ind < ast.len() {
-        if !run_op(&mut env, &ast, &mut ind).has_jmp() {
+fn run_ops<F>(ast: &[Op], env: &mut Environment, start: usize, stop: F) -> Result<()>
+where
+    F: Fn(usize) -> bool,
+{
+    let mut ind = start;
+    while ind < ast.len() && (ind == start || !stop(ind)) {
+        if !run_op(env, ast, &mut ind)?.has_jmp() {
             ind += 1;
             env.pos.0 += 1;
         }
-    }
 
-    if matches.is_present("debug") {
-        println!("\nDump: {:?}", env);
+        if env.exit.is_some() || env.paused() {
+            break;
+        }
     }
+    Ok(())
 }
 
 // Returns true if the index was manually updated
-fn run_op(env: &mut Environment, ast: &[Op], ind: &mut usize) -> Box<dyn Status> {
+fn run_op(env: &mut Environment, ast: &[Op], ind: &mut usize) -> Result<Box<dyn Status>> {
     match &ast[*ind] {
-        Op::Cmd(name, args) => {
+        Op::Cmd(name, args, span) => {
             // If we are not in a branch, clear the jump_points for performance
-            if !env.jump_point.is_empty() && ast == env.get_parent().clone().unwrap() {
+            if !env.jump_point.is_empty() && Some(ast) == env.get_parent() {
                 env.jump_point.clear();
             }
 
+            // Stopping here leaves `env.pos` at this command, so it runs first
+            // once the program is resumed
+            if env.paused() {
+                return Ok(bx!(true));
+            }
+            env.begin_step();
+
             let shallow_ref: Vec<&Op> = args.iter().collect();
-            run_cmd(env, ast, ind, &*name, &shallow_ref)
+            let res = run_cmd(env, ast, ind, name, &shallow_ref).map_err(|e| e.at(*span));
+            env.end_step(*span)?;
+            res
+        }
+
+        // Left by the optimizer where it removed a command, which it has to
+        // behave like apart from doing nothing
+        Op::Empty => {
+            if !env.jump_point.is_empty() && Some(ast) == env.get_parent() {
+                env.jump_point.clear();
+            }
+
+            Ok(bx!(false))
         }
 
         Op::Branch(_, body) => {
+            #[cfg(feature = "jit")]
+            if let Some(func) = env.jit_branch(body) {
+                return run_compiled(env, body, ind, func);
+            }
+
             for mut i in env.pos.1..body.len() {
-                if run_op(env, &body, &mut i).has_jmp() {
+                if run_op(env, body, &mut i)?.has_jmp() {
                     *ind = i;
-                    return bx!(true);
+                    return Ok(bx!(true));
                 }
 
                 env.pos.1 += 1;
             }
 
-            bx!(false)
+            Ok(bx!(false))
         }
 
         #[cfg(feature = "inline-python")]
         Op::InlinePy(code) => {
-            let (new_stk, _) = env.py.run_python(env, code);
+            // Python prints straight to stdout, so what was printed before has to be out first
+            env.out.flush()?;
+            let (new_stk, _) = env.py.run_python(env, code)?;
             // Update the stack
             if let Some(new_stk) = new_stk {
-                env.mem.write_range(
-                    crate::utils::consts::OFFSET..env.mem.s_size + crate::utils::consts::OFFSET,
-                    &new_stk,
-                );
+                env.mem
+                    .write_range(OFFSET..env.mem.s_size + OFFSET, &new_stk);
                 env.mem.s_len = new_stk.len();
                 env.mem.s_size = env.mem.s_len.max(env.mem.s_size);
             }
 
-            bx!(false)
+            Ok(bx!(false))
         }
 
-        _ => panic!("Invalid top-level op: {:?}", ast[*ind]),
+        _ => err!("Invalid top-level op: {:?}", ast[*ind]),
     }
 }
 
+// Runs a branch like `run_op` does, with the ops `func` can run done natively
+// and the rest interpreted
+#[cfg(feature = "jit")]
+fn run_compiled(
+    env: &mut Environment,
+    body: &[Op],
+    ind: &mut usize,
+    func: crate::jit::Compiled,
+) -> Result<Box<dyn Status>> {
+    let mut i = env.pos.1;
+    while i < body.len() {
+        let stop = func.run(&mut env.mem, i);
+        env.pos.1 += stop - i;
+        i = stop;
+        if i == body.len() {
+            break;
+        }
+
+        let mut j = i;
+        if run_op(env, body, &mut j)?.has_jmp() {
+            *ind = j;
+            return Ok(bx!(true));
+        }
+
+        env.pos.1 += 1;
+        i += 1;
+    }
+
+    Ok(bx!(false))
+}
+
 // Converts op to a numeric value
-pub fn to_numeric<T: Num + Clone>(env: &mut Environment, ast: &[Op], obj: &Op) -> T {
-    match obj {
+pub fn to_numeric<T: Num + Clone>(env: &mut Environment, ast: &[Op], obj: &Op) -> Result<T> {
+    let val: Option<T> = match obj {
         Op::Numeric(val) => num_traits::cast(*val),
 
-        Op::StackMarker => {
-            match T::len() {
-                1 => num_traits::cast(
-                    env.mem
-                        .s_pop_8()
-                        .expect("Attempted to pop from empty stack"),
-                ),
-                2 => num_traits::cast(
-                    env.mem
-                        .s_pop_16()
-                        .expect("Attempted to pop from empty stack"),
-                ),
-                4 | 8 => num_traits::cast(
-                    env.mem
-                        .s_pop_32()
-                        .expect("Attempted to pop from empty stack"),
-                ),
-                // Can't happen
-                _ => panic!(),
-            }
-        }
+        Op::StackMarker => match T::len() {
+            1 => match env.mem.s_pop_8() {
+                Some(val) => num_traits::cast(val),
+                None => return err!("Attempted to pop from empty stack"),
+            },
+            2 => match env.mem.s_pop_16() {
+                Some(val) => num_traits::cast(val),
+                None => return err!("Attempted to pop from empty stack"),
+            },
+            4 | 8 => match env.mem.s_pop_32() {
+                Some(val) => num_traits::cast(val),
+                None => return err!("Attempted to pop from empty stack"),
+            },
+            // Can't happen
+            _ => unreachable!(),
+        },
 
         Op::Memory(ident, op) => {
-            let val = to_numeric(env, ast, &op);
+            let val = to_numeric(env, ast, op)?;
+            check_range(env, val, memory_len(*ident))?;
+            env.mem.heap.check(val, memory_len(*ident))?;
 
             match ident {
-                '#' => num_traits::cast(env.mem.m_read::<u8>(val)),
-                '$' => num_traits::cast(env.mem.m_read::<i16>(val)),
-                '@' => num_traits::cast(env.mem.m_read::<i32>(val)),
-                _ => panic!("Invalid memory identifier: '{}'", ident),
+                '#' => num_traits::cast(env.mem.m_read::<u8>(val)?),
+                '$' => num_traits::cast(env.mem.m_read::<i16>(val)?),
+                '@' => num_traits::cast(env.mem.m_read::<i32>(val)?),
+                _ => return err!("Invalid memory identifier: '{}'", ident),
             }
         }
 
-        Op::Register(name) => {
-            let chrs: Vec<char> = name.chars().collect();
-            if name.ends_with('x') {
-                if name.len() == 3 {
-                    num_traits::cast(env.mem.r_read::<i32>(&(chrs[0], chrs[1])))
+        Op::Register(name) if FloatRegister::parse(name).is_some() => {
+            return err!("Expected an integer, but {} is a float register", name)
+        }
+
+        Op::Float(val) => return err!("Expected an integer, but {} is a float", float_text(*val)),
+
+        Op::Register(name) => match reg(name)? {
+            Reg::Stack => num_traits::cast(env.mem.sp()),
+            reg @ (Reg::Low(_) | Reg::High(_)) => num_traits::cast(env.mem.r_read::<u8>(&reg)),
+            reg @ Reg::Wide(_) => num_traits::cast(env.mem.r_read::<i16>(&reg)),
+            reg @ Reg::Pair(..) => num_traits::cast(env.mem.r_read::<i32>(&reg)),
+        },
+
+        Op::Label(name) => {
+            let pos = env.get_parent().unwrap_or(ast).iter().position(|entry| {
+                if let Op::Branch(n, _) = entry {
+                    n[1..] == name[1..]
                 } else {
-                    num_traits::cast(env.mem.r_read::<i16>(&chrs[0]))
+                    false
                 }
-            } else if name.ends_with('h') {
-                num_traits::cast(env.mem.r_read::<u8>(&(chrs[0], Pos::Upper)))
-            } else {
-                num_traits::cast(env.mem.r_read::<u8>(&(chrs[0], Pos::Lower)))
+            });
+
+            match pos {
+                Some(pos) => num_traits::cast(pos),
+                None => return err!("No matching branch for label {}", name),
             }
         }
 
-        Op::Label(name) => num_traits::cast(
-            env.get_parent()
-                .clone()
-                .unwrap_or_else(|| ast.to_owned())
-                .iter()
-                .position(|entry| {
-                    if let Op::Branch(n, _) = entry {
-                        n[1..] == name[1..]
-                    } else {
-                        false
-                    }
-                })
-                .unwrap_or_else(|| panic!("No matching branch for label {}", name)),
-        ),
-
-        Op::BinOp(..) => todo!("Math unimplemented"),
+        Op::BinOp(..) => return err!("Math unimplemented"),
 
         Op::Char(chr) => num_traits::cast(*chr as u8),
 
-        Op::Cmd(name, args) => {
+        Op::Cmd(name, args, span) => {
             let mut dummy_ind = 0;
             let args: Vec<&Op> = args.iter().collect();
-            num_traits::cast(run_cmd(env, ast, &mut dummy_ind, &*name, &args).get_val())
+            let res = run_cmd(env, ast, &mut dummy_ind, name, &args).map_err(|e| e.at(*span))?;
+            num_traits::cast(res.get_val())
         }
 
         #[cfg(feature = "inline-python")]
         Op::InlinePy(code) => {
-            let (new_stk, res) = env.py.run_python(env, code);
+            // Python prints straight to stdout, so what was printed before has to be out first
+            env.out.flush()?;
+            let (new_stk, res) = env.py.run_python(env, code)?;
             // Update the stack
             if let Some(new_stk) = new_stk {
-                env.mem.write_range(
-                    crate::utils::consts::OFFSET..env.mem.s_size + crate::utils::consts::OFFSET,
-                    &new_stk,
-                );
+                env.mem
+                    .write_range(OFFSET..env.mem.s_size + OFFSET, &new_stk);
                 env.mem.s_len = new_stk.len();
                 env.mem.s_size = env.mem.s_len.max(env.mem.s_size);
             }
             num_traits::cast(res)
         }
 
-        _ => panic!("Invalid numeric literal: {:?}", obj),
-    }
-    .unwrap_or_else(|| {
-        panic!(
+        _ => return err!("Invalid numeric literal: {:?}", obj),
+    };
+
+    match val {
+        Some(val) => Ok(val),
+        None => err!(
             "Could not convert {:?} to type <{}>",
             obj,
             std::any::type_name::<T>()
-        )
-    })
+        ),
+    }
+}
+
+// The register `name` refers to, failing if it isn't one
+fn reg(name: &str) -> Result<Reg> {
+    match register(name) {
+        Some(reg) => Ok(reg),
+        None => err!("Invalid register: {}", name),
+    }
 }
 
 // Pass in the op in which memory is modified, and it will automatically update
 // it with the value
-fn modify_memory(env: &mut Environment, ast: &[Op], obj: &Op, val: &Op) {
+fn modify_memory(env: &mut Environment, ast: &[Op], obj: &Op, val: &Op) -> Result<()> {
     match obj {
-        Op::Register(name) => {
-            let chrs = name.chars().collect::<Vec<char>>();
-            if name.ends_with('x') {
-                if chrs.len() == 3 {
-                    let val: i32 = to_numeric(env, &ast, val);
-                    env.mem.r_write(&(chrs[0], chrs[1]), &val);
-                } else {
-                    let val: i16 = to_numeric(env, &ast, val);
-                    env.mem.r_write(&chrs[0], &val);
+        Op::Register(name) if FloatRegister::parse(name).is_some() => {
+            return err!("Expected an integer, but {} is a float register", name)
+        }
+
+        Op::Register(name) => match reg(name)? {
+            Reg::Stack => {
+                let val: i16 = to_numeric(env, ast, val)?;
+                match usize::try_from(val) {
+                    Ok(sp) => env.mem.set_sp(sp)?,
+                    Err(_) => return err!("The stack pointer has to stay inside of the stack"),
                 }
-            } else if name.ends_with('h') {
-                let val: u8 = to_numeric(env, &ast, val);
-                env.mem.r_write(&(chrs[0], Pos::Upper), &val);
-            } else {
-                let val: u8 = to_numeric(env, &ast, val);
-                env.mem.r_write(&(chrs[0], Pos::Lower), &val);
             }
-        }
+            reg @ (Reg::Low(_) | Reg::High(_)) => {
+                let val: u8 = to_numeric(env, ast, val)?;
+                env.mem.r_write(&reg, &val);
+            }
+            reg @ Reg::Wide(_) => {
+                let val: i16 = to_numeric(env, ast, val)?;
+                env.mem.r_write(&reg, &val);
+            }
+            reg @ Reg::Pair(..) => {
+                let val: i32 = to_numeric(env, ast, val)?;
+                env.mem.r_write(&reg, &val);
+            }
+        },
 
         Op::Memory(ident, op) => {
-            let pos = to_numeric(env, &ast, &op);
+            let pos = to_numeric(env, ast, op)?;
+            check_range(env, pos, memory_len(*ident))?;
+            env.mem.heap.check(pos, memory_len(*ident))?;
             match ident {
                 '#' => {
-                    let val: u8 = to_numeric(env, &ast, val);
-                    env.mem.m_write(pos, &val);
+                    let val: u8 = to_numeric(env, ast, val)?;
+                    env.mem.m_write(pos, &val)?;
                 }
 
                 '$' => {
-                    let val: i16 = to_numeric(env, &ast, val);
-                    env.mem.m_write(pos, &val);
+                    let val: i16 = to_numeric(env, ast, val)?;
+                    env.mem.m_write(pos, &val)?;
                 }
 
                 '@' => {
-                    let val: i32 = to_numeric(env, &ast, val);
-                    env.mem.m_write(pos, &val);
+                    let val: i32 = to_numeric(env, ast, val)?;
+                    env.mem.m_write(pos, &val)?;
                 }
 
-                _ => panic!("Invalid identifier for memory: '{}'", ident),
+                _ => return err!("Invalid identifier for memory: '{}'", ident),
             }
         }
 
-        _ => panic!("Invalid parameter: {:?}", obj),
+        _ => return err!("Invalid parameter: {:?}", obj),
     }
+
+    Ok(())
+}
+
+// Converts op to a float. Integers are converted, so anything that can be read
+// as an integer can be read as a float too
+fn to_float(env: &mut Environment, ast: &[Op], obj: &Op) -> Result<f64> {
+    match obj {
+        Op::Float(val) => Ok(*val),
+        Op::Register(name) => match FloatRegister::parse(name) {
+            Some(reg) => Ok(env.mem.f_read(reg)),
+            None => Ok(f64::from(to_numeric::<i32>(env, ast, obj)?)),
+        },
+        _ => Ok(f64::from(to_numeric::<i32>(env, ast, obj)?)),
+    }
+}
+
+// The float register a float command writes to
+fn float_register(obj: &Op) -> Result<FloatRegister> {
+    match obj {
+        Op::Register(name) => match FloatRegister::parse(name) {
+            Some(reg) => Ok(reg),
+            None => err!("Expected a float register to write to"),
+        },
+        _ => err!("Expected a float register to write to"),
+    }
+}
+
+// Fails unless the float at cell `pos` is inside of the memory, and not in a
+// heap block that can't be used
+fn check_float_cell(env: &Environment, pos: usize) -> Result<()> {
+    check_range(env, pos, 8)?;
+    env.mem.heap.check(pos, 8)
+}
+
+// Where on the stack the `len` bytes that `lds`/`sts` or `ldf`/`stf` access
+// start. The first two count `offset` down from the top of the stack, so an
+// offset of 0 is what `pop` would take, and the others count it up from the
+// frame pointer
+fn stack_pos(
+    env: &mut Environment,
+    ast: &[Op],
+    cmd: &str,
+    offset: &Op,
+    len: usize,
+) -> Result<usize> {
+    let pos = if cmd.ends_with('s') {
+        let offset: usize = to_numeric(env, ast, offset)?;
+        offset
+            .checked_add(len)
+            .and_then(|end| env.mem.sp().checked_sub(end))
+    } else {
+        let offset: i32 = to_numeric(env, ast, offset)?;
+        let fp: i16 = env.mem.r_read(&FramePointer);
+        usize::try_from(i64::from(fp) + i64::from(offset)).ok()
+    };
+
+    match pos {
+        Some(pos) => Ok(pos),
+        None => err!("Stack access out of bounds"),
+    }
+}
+
+// How many bytes a memory access with `ident` covers
+fn memory_len(ident: char) -> usize {
+    match ident {
+        '#' => 1,
+        '$' => 2,
+        _ => 4,
+    }
+}
+
+// The next byte of input without reading it, or `None` once there is none left
+fn peek_in(env: &mut Environment) -> Result<Option<u8>> {
+    flush_before_input(env)?;
+    Ok(env.stdin.peek()?)
+}
+
+fn read_in(env: &mut Environment) -> Result<Option<u8>> {
+    flush_before_input(env)?;
+    Ok(env.stdin.read()?)
+}
+
+// Reading more input may block, so everything printed so far is shown first,
+// otherwise interactive programs could wait for an answer to a hidden prompt
+fn flush_before_input(env: &mut Environment) -> Result<()> {
+    if env.stdin.is_drained() {
+        env.out.flush()?;
+        env.err.flush()?;
+    }
+    Ok(())
+}
+
+// Fails unless the `len` bytes starting at `start` are all inside of the memory
+fn check_range(env: &Environment, start: usize, len: usize) -> Result<()> {
+    env.mem.cells(start, len).map(drop)
+}
+
+// Reads the null-terminated string starting at `start` out of memory
+fn read_str(env: &Environment, mut start: usize) -> Result<String> {
+    let mut bytes = Vec::new();
+    loop {
+        match env.mem.m_read::<u8>(start)? {
+            0 => break,
+            byte => bytes.push(byte),
+        }
+        start += 1;
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
 }
 
-#[inline(always)]
+// Writes at most `max` bytes of `bytes` into memory at `start`, followed by a
+// null-byte, returning how many were written
+fn write_str(env: &mut Environment, start: usize, max: usize, bytes: &[u8]) -> Result<i32> {
+    let len = bytes.len().min(max);
+    check_range(env, start, len + 1)?;
+    for (i, byte) in bytes[..len].iter().chain(&[0]).enumerate() {
+        env.mem.m_write(start + i, byte)?;
+    }
+    Ok(i32::try_from(len).unwrap_or(i32::MAX))
+}
+
+// Runs one of the file commands, returning its result. Memory that is out of
+// bounds fails the program like it does everywhere else, but whatever goes
+// wrong with the file itself is returned, so it can be reported through the flag
+fn run_file_cmd(
+    env: &mut Environment,
+    ast: &[Op],
+    cmd: &str,
+    args: &[&Op],
+) -> Result<io::Result<i32>> {
+    let to_i32 = |val: usize| i32::try_from(val).unwrap_or(i32::MAX);
+
+    Ok(match cmd {
+        "fop" => {
+            let start: usize = to_numeric(env, ast, args[0])?;
+            let mode: i32 = to_numeric(env, ast, args[1])?;
+            let path = PathBuf::from(read_str(env, start)?);
+            env.files.open(&path, mode).map(to_i32)
+        }
+
+        "frd" => {
+            let handle: i32 = to_numeric(env, ast, args[0])?;
+            let start: usize = to_numeric(env, ast, args[1])?;
+            let len: usize = to_numeric(env, ast, args[2])?;
+            check_range(env, start, len)?;
+
+            let mut buf = vec![0; len];
+            match env.files.read(handle, &mut buf) {
+                Ok(len) => {
+                    for (i, byte) in buf[..len].iter().enumerate() {
+                        env.mem.m_write(start + i, byte)?;
+                    }
+                    Ok(to_i32(len))
+                }
+                Err(e) => Err(e),
+            }
+        }
+
+        "fwr" => {
+            let handle: i32 = to_numeric(env, ast, args[0])?;
+            let start: usize = to_numeric(env, ast, args[1])?;
+            let len: usize = to_numeric(env, ast, args[2])?;
+            let buf = (start..start + len)
+                .map(|i| env.mem.m_read(i))
+                .collect::<Result<Vec<u8>>>()?;
+            env.files.write(handle, &buf).map(|()| to_i32(len))
+        }
+
+        "fsk" => {
+            let handle: i32 = to_numeric(env, ast, args[0])?;
+            let pos: usize = to_numeric(env, ast, args[1])?;
+            env.files
+                .seek(handle, pos as u64)
+                .map(|pos| i32::try_from(pos).unwrap_or(i32::MAX))
+        }
+
+        "fcl" => {
+            let handle: i32 = to_numeric(env, ast, args[0])?;
+            env.files.close(handle).map(|()| 0)
+        }
+
+        _ => unreachable!(),
+    })
+}
+
+#[inline]
 fn set_ind(ind: &mut usize, env: &mut Environment, val: usize) {
     *ind = val;
     env.jump_point.push(env.pos);
@@ -239,12 +530,17 @@ fn set_ind(ind: &mut usize, env: &mut Environment, val: usize) {
 }
 
 #[inline]
-pub fn check_cmp<T>(env: &mut Environment, ast: &[Op], args: &[&Op], f: T) -> Box<dyn Status>
+pub fn check_cmp<T>(
+    env: &mut Environment,
+    ast: &[Op],
+    args: &[&Op],
+    f: T,
+) -> Result<Box<dyn Status>>
 where
     T: FnOnce(i32, i32) -> bool,
 {
-    let left: i32 = to_numeric(env, ast, args[0]);
-    let right = to_numeric(env, ast, args[1]);
+    let left: i32 = to_numeric(env, ast, args[0])?;
+    let right = to_numeric(env, ast, args[1])?;
 
     if f(left, right) {
         env.mem.flag_write_cmp();
@@ -252,7 +548,28 @@ where
         env.mem.flag_reset_cmp();
     }
 
-    bx!(false)
+    Ok(bx!(false))
+}
+
+// Fails with `msg` (and the values that were compared, if any) unless `passed`
+fn check_assert(passed: bool, msg: &Op, values: Option<(i32, i32)>) -> Result<Box<dyn Status>> {
+    if passed {
+        return Ok(bx!(false));
+    }
+
+    let Op::String(msg) = msg else {
+        return err!("Assertion message must be a string. Instead got: {:?}", msg);
+    };
+
+    match values {
+        Some((left, right)) => err!(
+            "Assertion failed: {} (left: {}, right: {})",
+            msg,
+            left,
+            right
+        ),
+        None => err!("Assertion failed: {}", msg),
+    }
 }
 
 #[inline]
@@ -262,18 +579,35 @@ pub fn perform_op<T>(
     args: &[&Op],
     cmd: &str,
     f: T,
-) -> Box<dyn Status>
+) -> Result<Box<dyn Status>>
 where
-    T: FnOnce(i32, i32) -> i32,
+    T: FnOnce(i32, i32) -> Result<i32>,
 {
     if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-        return bx!(false);
+        return Ok(bx!(false));
     }
 
-    let left: i32 = to_numeric(env, ast, args[0]);
-    let right: i32 = to_numeric(env, ast, args[1]);
-    modify_memory(env, ast, args[0], &Op::Numeric(f(left, right)));
-    bx!(false)
+    let left: i32 = to_numeric(env, ast, args[0])?;
+    let right: i32 = to_numeric(env, ast, args[1])?;
+    modify_memory(env, ast, args[0], &Op::Numeric(f(left, right)?))?;
+    Ok(bx!(false))
+}
+
+// The result of an arithmetic operation, failing with the same message Rust
+// panics with if it overflowed
+fn checked(val: Option<i32>, op: &str) -> Result<i32> {
+    match val {
+        Some(val) => Ok(val),
+        None => err!("attempt to {} with overflow", op),
+    }
+}
+
+// How far `r` shifts, failing unless it is less than the 32 bits there are
+fn shift(r: i32, op: &str) -> Result<u32> {
+    match u32::try_from(r) {
+        Ok(r) if r < 32 => Ok(r),
+        _ => err!("attempt to {} with overflow", op),
+    }
 }
 
 // Returns `true` if `ind` was modified, `false` otherwise
@@ -283,90 +617,113 @@ fn run_cmd(
     ind: &mut usize,
     cmd: &str,
     args: &[&Op],
-) -> Box<dyn Status> {
-    match cmd {
+) -> Result<Box<dyn Status>> {
+    Ok(match cmd {
         "mov" | "cmo" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
             // Move second value into the first
-            modify_memory(env, ast, args[0], args[1]);
+            modify_memory(env, ast, args[0], args[1])?;
             bx!(false)
         }
 
         "inc" | "cin" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
             // new_val is 1 more than the previous value
-            let new_val = Op::Numeric(1 + to_numeric::<i32>(env, ast, args[0]));
-            modify_memory(env, ast, args[0], &new_val);
+            let val = to_numeric::<i32>(env, ast, args[0])?;
+            let new_val = Op::Numeric(checked(val.checked_add(1), "add")?);
+            modify_memory(env, ast, args[0], &new_val)?;
             bx!(false)
         }
 
         "dec" | "cde" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
             // new_val is 1 less than the previous value
-            let new_val = Op::Numeric(to_numeric::<i32>(env, ast, args[0]) - 1);
-            modify_memory(env, ast, args[0], &new_val);
+            let val = to_numeric::<i32>(env, ast, args[0])?;
+            let new_val = Op::Numeric(checked(val.checked_sub(1), "subtract")?);
+            modify_memory(env, ast, args[0], &new_val)?;
             bx!(false)
         }
 
         "out" | "cou" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
-            print!("{}", to_numeric::<i32>(env, ast, args[0]));
+            let val = to_numeric::<i32>(env, ast, args[0])?;
+            write!(env.out, "{val}")?;
             bx!(false)
         }
 
         "chr" | "cch" => {
             if cmd == "cch" && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
-            print!("{}", to_numeric::<u8>(env, ast, args[0]) as char);
+            let val = to_numeric::<u8>(env, ast, args[0])? as char;
+            write!(env.out, "{val}")?;
+            bx!(false)
+        }
+
+        "err" => {
+            let val = to_numeric::<i32>(env, ast, args[0])?;
+            write!(env.err, "{val}")?;
+            bx!(false)
+        }
+
+        "errc" => {
+            let val = to_numeric::<u8>(env, ast, args[0])? as char;
+            write!(env.err, "{val}")?;
             bx!(false)
         }
 
         "jmp" | "cjm" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
-            let n = to_numeric(env, ast, args[0]);
+            let n = to_numeric(env, ast, args[0])?;
             set_ind(ind, env, n);
             bx!(true)
         }
 
-        "mul" | "cmu" => perform_op(env, ast, args, cmd, |l, r| l * r),
+        "mul" | "cmu" => perform_op(env, ast, args, cmd, |l, r| {
+            checked(l.checked_mul(r), "multiply")
+        })?,
 
-        "div" | "cdi" => perform_op(env, ast, args, cmd, |l, r| l / r),
+        "div" | "cdi" => perform_op(env, ast, args, cmd, |l, r| match r {
+            0 => err!("attempt to divide by zero"),
+            _ => checked(l.checked_div(r), "divide"),
+        })?,
 
-        "sub" | "csu" => perform_op(env, ast, args, cmd, |l, r| l - r),
+        "sub" | "csu" => perform_op(env, ast, args, cmd, |l, r| {
+            checked(l.checked_sub(r), "subtract")
+        })?,
 
-        "add" | "cad" => perform_op(env, ast, args, cmd, |l, r| l + r),
+        "add" | "cad" => perform_op(env, ast, args, cmd, |l, r| checked(l.checked_add(r), "add"))?,
 
-        "ceq" => check_cmp(env, ast, args, |l, r| l == r),
+        "ceq" => check_cmp(env, ast, args, |l, r| l == r)?,
 
-        "cne" => check_cmp(env, ast, args, |l, r| l != r),
+        "cne" => check_cmp(env, ast, args, |l, r| l != r)?,
 
-        "cl" => check_cmp(env, ast, args, |l, r| l < r),
+        "cl" => check_cmp(env, ast, args, |l, r| l < r)?,
 
-        "cle" => check_cmp(env, ast, args, |l, r| l <= r),
+        "cle" => check_cmp(env, ast, args, |l, r| l <= r)?,
 
-        "cg" => check_cmp(env, ast, args, |l, r| l > r),
+        "cg" => check_cmp(env, ast, args, |l, r| l > r)?,
 
-        "cge" => check_cmp(env, ast, args, |l, r| l >= r),
+        "cge" => check_cmp(env, ast, args, |l, r| l >= r)?,
 
         "cz" => {
-            if to_numeric::<i32>(env, ast, args[0]) == 0 {
+            if to_numeric::<i32>(env, ast, args[0])? == 0 {
                 env.mem.flag_write_cmp();
             } else {
                 env.mem.flag_reset_cmp();
@@ -378,49 +735,59 @@ fn run_cmd(
         "str" => match args[0] {
             Op::String(val) => {
                 for (i, chr) in val.chars().enumerate() {
-                    env.mem.m_write(i, &(chr as u8));
+                    env.mem.m_write(i, &(chr as u8))?;
                 }
 
-                let terminator: u8 = to_numeric(env, ast, args[1]);
-                env.mem.m_write(val.len(), &terminator);
+                let terminator: u8 = to_numeric(env, ast, args[1])?;
+                env.mem.m_write(val.len(), &terminator)?;
                 bx!(false)
             }
 
-            _ => panic!(
-                "Argument #0 for command 'str' must be of type Op::String. Instead got: {:?}",
-                ast[*ind]
-            ),
+            _ => {
+                return err!(
+                    "Argument #0 for command 'str' must be of type Op::String. Instead got: {:?}",
+                    ast[*ind]
+                )
+            }
         },
 
         "stk" => {
-            let count = to_numeric(env, ast, args[0]);
+            let count = to_numeric(env, ast, args[0])?;
+            // Heap blocks are found by where they are in the cell memory,
+            // which moving the stack's end would shift
+            if count != env.mem.s_size && env.mem.heap.in_use() {
+                return err!("The stack can't be resized while memory is allocated");
+            }
+            if count > MEM_SIZE - OFFSET {
+                return err!("The stack does not fit into memory");
+            }
             env.mem.resize_stack(count);
             bx!(false)
         }
 
         "psh" | "cps" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
-            let allocation = to_numeric(env, ast, args[0]);
+            let allocation = to_numeric(env, ast, args[0])?;
             match allocation {
                 1 => {
-                    let n = to_numeric(env, ast, args[1]);
-                    env.mem.s_push::<u8>(&n)
+                    let n = to_numeric(env, ast, args[1])?;
+                    env.mem.s_push::<u8>(&n)?;
                 }
 
                 2 => {
-                    let n = to_numeric(env, ast, args[1]);
-                    env.mem.s_push::<i16>(&n)
+                    let n = to_numeric(env, ast, args[1])?;
+                    env.mem.s_push::<i16>(&n)?;
                 }
 
                 4 => {
-                    let n = to_numeric(env, ast, args[1]);
-                    env.mem.s_push::<i32>(&n)
+                    let n = to_numeric(env, ast, args[1])?;
+                    env.mem.s_push::<i32>(&n)?;
                 }
-                // Shouldn't happen
-                _ => panic!(),
+
+                _ => return err!("Cannot push a {} byte number to the stack", allocation),
             }
 
             bx!(false)
@@ -428,69 +795,353 @@ fn run_cmd(
 
         "pop" | "cpo" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
-            modify_memory(env, ast, args[0], &Op::StackMarker);
+            modify_memory(env, ast, args[0], &Op::StackMarker)?;
+            bx!(false)
+        }
+
+        "enter" => {
+            let len = to_numeric(env, ast, args[0])?;
+            env.mem.enter(len)?;
+            bx!(false)
+        }
+
+        "leave" => {
+            env.mem.leave()?;
+            bx!(false)
+        }
+
+        "lds" | "ldf" => {
+            let len = match width_of(args[0]) {
+                Some(bits) => bits as usize / 8,
+                None => return err!("Invalid parameter: {:?}", args[0]),
+            };
+            let pos = stack_pos(env, ast, cmd, args[1], len)?;
+            let val = match len {
+                1 => i32::from(env.mem.s_read::<u8>(pos)?),
+                2 => i32::from(env.mem.s_read::<i16>(pos)?),
+                _ => env.mem.s_read::<i32>(pos)?,
+            };
+            modify_memory(env, ast, args[0], &Op::Numeric(val))?;
+            bx!(false)
+        }
+
+        "sts" | "stf" => {
+            let len = to_numeric(env, ast, args[0])?;
+            if ![1, 2, 4].contains(&len) {
+                return err!("Cannot store a {} byte number on the stack", len);
+            }
+            let pos = stack_pos(env, ast, cmd, args[1], len)?;
+            match len {
+                1 => {
+                    let n: u8 = to_numeric(env, ast, args[2])?;
+                    env.mem.s_write(pos, &n)?;
+                }
+
+                2 => {
+                    let n: i16 = to_numeric(env, ast, args[2])?;
+                    env.mem.s_write(pos, &n)?;
+                }
+
+                _ => {
+                    let n: i32 = to_numeric(env, ast, args[2])?;
+                    env.mem.s_write(pos, &n)?;
+                }
+            }
             bx!(false)
         }
 
-        "lsh" | "cls" => perform_op(env, ast, args, cmd, |l, r| l << r),
+        "lsh" | "cls" => perform_op(env, ast, args, cmd, |l, r| Ok(l << shift(r, "shift left")?))?,
 
-        "rsh" | "crs" => perform_op(env, ast, args, cmd, |l, r| l >> r),
+        "rsh" | "crs" => perform_op(
+            env,
+            ast,
+            args,
+            cmd,
+            |l, r| Ok(l >> shift(r, "shift right")?),
+        )?,
 
-        "or" | "cor" => perform_op(env, ast, args, cmd, |l, r| l | r),
+        "or" | "cor" => perform_op(env, ast, args, cmd, |l, r| Ok(l | r))?,
 
-        "xor" | "cxo" => perform_op(env, ast, args, cmd, |l, r| l ^ r),
+        "xor" | "cxo" => perform_op(env, ast, args, cmd, |l, r| Ok(l ^ r))?,
 
-        "and" | "can" => perform_op(env, ast, args, cmd, |l, r| l & r),
+        "and" | "can" => perform_op(env, ast, args, cmd, |l, r| Ok(l & r))?,
 
         "not" | "cno" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
-            let val: i32 = to_numeric(env, ast, args[0]);
-            modify_memory(env, ast, args[0], &Op::Numeric(!val));
+            let val: i32 = to_numeric(env, ast, args[0])?;
+            modify_memory(env, ast, args[0], &Op::Numeric(!val))?;
             bx!(false)
         }
 
         "swp" | "csw" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
             let val = args[1];
-            modify_memory(env, ast, args[1], args[0]);
-            modify_memory(env, ast, args[0], val);
+            modify_memory(env, ast, args[1], args[0])?;
+            modify_memory(env, ast, args[0], val)?;
             bx!(false)
         }
 
         "db" => {
-            let mut i = to_numeric(env, ast, args[0]);
-            let terminator: u8 = to_numeric(env, ast, args[1]);
+            let mut i = to_numeric(env, ast, args[0])?;
+            let terminator: u8 = to_numeric(env, ast, args[1])?;
             let mut len = 0;
-            while env.mem.m_read::<u8>(i) != terminator {
+            while env.mem.m_read::<u8>(i)? != terminator {
                 len += 1;
                 i += 1;
             }
             bx!(len)
         }
 
-        "in" => bx!(match env.stdin.next() {
-            Some(val) => i32::from(val),
-            None => 0,
-        }),
+        "in" => bx!(read_in(env)?.map_or(0, i32::from)),
+
+        "eof" => bx!(i32::from(peek_in(env)?.is_none())),
+
+        "rdn" => {
+            while matches!(peek_in(env)?, Some(byte) if byte.is_ascii_whitespace()) {
+                read_in(env)?;
+            }
+
+            if peek_in(env)?.is_none() {
+                modify_memory(env, ast, args[0], &Op::Numeric(0))?;
+                env.mem.flag_reset_cmp();
+                return Ok(bx!(false));
+            }
+
+            let mut digits = String::new();
+            if matches!(peek_in(env)?, Some(b'-' | b'+')) {
+                digits.extend(read_in(env)?.map(char::from));
+            }
+            while matches!(peek_in(env)?, Some(byte) if byte.is_ascii_digit()) {
+                digits.extend(read_in(env)?.map(char::from));
+            }
+
+            let val: i32 = match digits.parse() {
+                Ok(val) => val,
+                Err(_) if digits.ends_with(|chr: char| chr.is_ascii_digit()) => {
+                    return err!("Integer in the input does not fit into <i32>")
+                }
+                Err(_) => return err!("Expected an integer in the input"),
+            };
+            modify_memory(env, ast, args[0], &Op::Numeric(val))?;
+            env.mem.flag_write_cmp();
+            bx!(false)
+        }
+
+        "rdl" => {
+            let start: usize = to_numeric(env, ast, args[0])?;
+            let max: usize = to_numeric(env, ast, args[1])?;
+            let found = peek_in(env)?.is_some();
+
+            let mut len = 0;
+            while len < max {
+                match peek_in(env)? {
+                    Some(byte) if byte != b'\n' => {
+                        read_in(env)?;
+                        env.mem.m_write(start + len, &byte)?;
+                        len += 1;
+                    }
+                    _ => break,
+                }
+            }
+            if peek_in(env)? == Some(b'\n') {
+                read_in(env)?;
+            }
+            env.mem.m_write(start + len, &0_u8)?;
+
+            if found {
+                env.mem.flag_write_cmp();
+            } else {
+                env.mem.flag_reset_cmp();
+            }
+            bx!(i32::try_from(len).unwrap_or(i32::MAX))
+        }
+
+        "hex" | "bin" => {
+            let val: i32 = to_numeric(env, ast, args[0])?;
+            let width: usize = to_numeric(env, ast, args[1])?;
+
+            // Negative numbers are printed as the two's complement of whatever
+            // they were read from
+            let bits = width_of(args[0]).unwrap_or(32);
+            let val = i64::from(val) & ((1 << bits) - 1);
+            if cmd == "hex" {
+                write!(env.out, "{val:0width$x}")?;
+            } else {
+                write!(env.out, "{val:0width$b}")?;
+            }
+            bx!(false)
+        }
+
+        "pad" => {
+            let val: i32 = to_numeric(env, ast, args[0])?;
+            let width: usize = to_numeric(env, ast, args[1])?;
+            write!(env.out, "{val:>width$}")?;
+            bx!(false)
+        }
+
+        "prs" => {
+            let mut i: usize = to_numeric(env, ast, args[0])?;
+            let terminator: u8 = to_numeric(env, ast, args[1])?;
+            loop {
+                let byte: u8 = env.mem.m_read(i)?;
+                if byte == terminator {
+                    break;
+                }
+                write!(env.out, "{}", byte as char)?;
+                i += 1;
+            }
+            bx!(false)
+        }
+
+        "argc" => bx!(i32::try_from(env.args.len()).unwrap_or(i32::MAX)),
+
+        "argv" => {
+            let i: usize = to_numeric(env, ast, args[0])?;
+            let start: usize = to_numeric(env, ast, args[1])?;
+            let max: usize = to_numeric(env, ast, args[2])?;
+            if let Some(arg) = env.args.get(i).cloned() {
+                env.mem.flag_write_cmp();
+                bx!(write_str(env, start, max, arg.as_bytes())?)
+            } else {
+                env.mem.flag_reset_cmp();
+                bx!(-1)
+            }
+        }
+
+        "env" => {
+            let name: usize = to_numeric(env, ast, args[0])?;
+            let start: usize = to_numeric(env, ast, args[1])?;
+            let max: usize = to_numeric(env, ast, args[2])?;
+            let name = read_str(env, name)?;
+            if let Some(val) = env.vars.get(&name).cloned() {
+                env.mem.flag_write_cmp();
+                bx!(write_str(env, start, max, val.as_bytes())?)
+            } else {
+                env.mem.flag_reset_cmp();
+                bx!(-1)
+            }
+        }
+
+        "alloc" => {
+            let len: usize = to_numeric(env, ast, args[0])?;
+            if let Some(pos) = env.mem.alloc(len) {
+                env.mem.flag_write_cmp();
+                bx!(i32::try_from(pos).unwrap_or(i32::MAX))
+            } else {
+                env.mem.flag_reset_cmp();
+                bx!(-1)
+            }
+        }
+
+        "free" => {
+            let pos = to_numeric(env, ast, args[0])?;
+            env.mem.free(pos)?;
+            bx!(false)
+        }
+
+        "fop" | "frd" | "fwr" | "fsk" | "fcl" => {
+            if let Ok(val) = run_file_cmd(env, ast, cmd, args)? {
+                env.mem.flag_write_cmp();
+                bx!(val)
+            } else {
+                env.mem.flag_reset_cmp();
+                bx!(-1)
+            }
+        }
+
+        "fmov" | "fadd" | "fsub" | "fmul" | "fdiv" => {
+            let reg = float_register(args[0])?;
+            let left = if cmd == "fmov" {
+                0.0
+            } else {
+                env.mem.f_read(reg)
+            };
+            let right = to_float(env, ast, args[1])?;
+            let val = match cmd {
+                "fmov" => right,
+                "fadd" => left + right,
+                "fsub" => left - right,
+                "fmul" => left * right,
+                _ => left / right,
+            };
+            env.mem.f_write(reg, val);
+            bx!(false)
+        }
+
+        "ftoi" => {
+            let val = to_float(env, ast, args[1])?;
+            // Rounds towards zero, and fails on NaN as well as on floats that
+            // are too large
+            let Some(val) = num_traits::cast::<f64, i32>(val) else {
+                return err!("Float does not fit into <i32>");
+            };
+            modify_memory(env, ast, args[0], &Op::Numeric(val))?;
+            bx!(false)
+        }
+
+        "feq" | "fne" | "flt" | "fle" | "fgt" | "fge" => {
+            let left = to_float(env, ast, args[0])?;
+            let right = to_float(env, ast, args[1])?;
+            // NaN isn't ordered, so it only ever passes `fne`
+            let ord = left.partial_cmp(&right);
+            let passed = match cmd {
+                "feq" => ord == Some(Ordering::Equal),
+                "fne" => ord != Some(Ordering::Equal),
+                "flt" => ord == Some(Ordering::Less),
+                "fle" => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+                "fgt" => ord == Some(Ordering::Greater),
+                _ => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+            };
+
+            if passed {
+                env.mem.flag_write_cmp();
+            } else {
+                env.mem.flag_reset_cmp();
+            }
+            bx!(false)
+        }
+
+        "fld" => {
+            let reg = float_register(args[0])?;
+            let pos: usize = to_numeric(env, ast, args[1])?;
+            check_float_cell(env, pos)?;
+            let val = env.mem.m_read::<f64>(pos)?;
+            env.mem.f_write(reg, val);
+            bx!(false)
+        }
+
+        "fst" => {
+            let pos: usize = to_numeric(env, ast, args[0])?;
+            let val = to_float(env, ast, args[1])?;
+            check_float_cell(env, pos)?;
+            env.mem.m_write(pos, &val)?;
+            bx!(false)
+        }
+
+        "fout" => {
+            let val = to_float(env, ast, args[0])?;
+            let digits: usize = to_numeric(env, ast, args[1])?;
+            write!(env.out, "{val:.digits$}")?;
+            bx!(false)
+        }
 
         "ret" | "cre" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
-            if !env.jump_point.is_empty() {
-                let (mut left, right) = env.jump_point.pop().unwrap();
+            if let Some((mut left, right)) = env.jump_point.pop() {
                 // If it a top-level call, return to the next bit of the top-level. Otherwise, return to the next bit of the branch
-                if let Op::Branch(..) = env.get_parent().as_ref().unwrap()[left] {
+                if matches!(env.get_parent().unwrap()[left], Op::Branch(..)) {
                     env.pos = (left, right + 1);
                 } else {
                     left += 1;
@@ -498,7 +1149,7 @@ fn run_cmd(
                 }
                 *ind = left;
             } else {
-                panic!("Cannot return");
+                return err!("Cannot return");
             }
 
             bx!(true)
@@ -506,12 +1157,25 @@ fn run_cmd(
 
         "hlt" | "chl" => {
             if cmd.starts_with('c') && !env.mem.flag_read_cmp() {
-                return bx!(false);
+                return Ok(bx!(false));
             }
 
-            std::process::exit(to_numeric(env, ast, args[0]))
+            env.exit = Some(to_numeric(env, ast, args[0])?);
+            bx!(true)
         }
 
-        _ => panic!("Command: {} unrecognized", cmd),
-    }
+        "assert" => {
+            let passed = env.mem.flag_read_cmp();
+            check_assert(passed, args[0], None)?
+        }
+
+        "assert_eq" | "assert_ne" => {
+            let left: i32 = to_numeric(env, ast, args[0])?;
+            let right: i32 = to_numeric(env, ast, args[1])?;
+            let passed = (left == right) == (cmd == "assert_eq");
+            check_assert(passed, args[2], Some((left, right)))?
+        }
+
+        _ => return err!("Command: {} unrecognized", cmd),
+    })
 }
@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use serde_json::{json, Value};
+
+use crate::utils::{
+    consts::OFFSET,
+    error::Result,
+    heap::{Block, Heap, State},
+    mem::Memory,
+};
+use crate::{err, Environment};
+
+// Snapshots of a running program, written as JSON so they can be read by hand.
+// One holds everything the program can see of its state: its memory (with
+// the registers, flags and stack), heap, where execution is and every return
+// point, and how much of its input it used up. Open files and what was
+// already printed aren't part of it
+const VERSION: u64 = 3;
+
+// Everything needed to pick running `program` back up where `env` is
+pub fn save(env: &Environment, program: &[u8]) -> Value {
+    let blocks: Vec<Value> = env
+        .mem
+        .heap
+        .blocks
+        .iter()
+        .map(|(start, block)| json!([start, block.len, block.state.name()]))
+        .collect();
+    let jump_points: Vec<Value> = env.jump_point.iter().map(|&(a, b)| json!([a, b])).collect();
+
+    json!({
+        "version": VERSION,
+        "program": fingerprint(program),
+        "memory": {
+            "size": env.mem.size,
+            "stack_size": env.mem.s_size,
+            "stack_len": env.mem.s_len,
+            "bytes": hex(env.mem.as_slice()),
+        },
+        "heap": { "len": env.mem.heap.len, "blocks": blocks },
+        "pos": [env.pos.0, env.pos.1],
+        "jump_points": jump_points,
+        "exit": env.exit,
+        "steps": env.steps,
+        "stdin": env.stdin.consumed(),
+    })
+}
+
+// Puts `env` back into the state `snapshot` was saved in, to resume `program`
+// from. `env` has to read the same input as before, and whatever of it the
+// program had used up already is skipped. Whether the heap checks for misuse,
+// and what is watched, is left as it was
+pub fn restore(env: &mut Environment, snapshot: &str, program: &[u8]) -> Result<()> {
+    let Ok(snapshot) = serde_json::from_str::<Value>(snapshot) else {
+        return invalid();
+    };
+    if snapshot["version"].as_u64() != Some(VERSION) {
+        return err!("Unsupported snapshot version");
+    }
+    if snapshot["program"].as_u64() != Some(fingerprint(program)) {
+        return err!("The snapshot was taken of a different program");
+    }
+
+    let memory = &snapshot["memory"];
+    let size = usize_of(&memory["size"])?;
+    let s_size = usize_of(&memory["stack_size"])?;
+    let s_len = usize_of(&memory["stack_len"])?;
+    let bytes = unhex(memory["bytes"].as_str().unwrap_or_default())?;
+    if size < OFFSET || s_size > size - OFFSET || s_len > s_size || bytes.len() != size {
+        return invalid();
+    }
+
+    let mut mem = Memory::init(size, s_size);
+    mem.as_mut_slice().copy_from_slice(&bytes);
+    mem.s_len = s_len;
+    mem.heap = heap(&snapshot["heap"], mem.memory_len())?;
+    mem.heap.debug = env.mem.heap.debug;
+
+    let pos = pair(&snapshot["pos"])?;
+    let jump_point = snapshot["jump_points"]
+        .as_array()
+        .map_or_else(invalid, Ok)?
+        .iter()
+        .map(pair)
+        .collect::<Result<Vec<_>>>()?;
+    let exit = match &snapshot["exit"] {
+        Value::Null => None,
+        code => Some(
+            code.as_i64()
+                .and_then(|code| i32::try_from(code).ok())
+                .map_or_else(invalid, Ok)?,
+        ),
+    };
+    let steps = snapshot["steps"].as_u64().map_or_else(invalid, Ok)?;
+    let consumed = snapshot["stdin"].as_u64().map_or_else(invalid, Ok)?;
+
+    if env.stdin.skip(consumed)? != consumed {
+        return err!("The input ran out before where the snapshot was taken");
+    }
+    mem.watches = std::mem::take(&mut env.mem.watches);
+    env.mem = mem;
+    env.pos = pos;
+    env.jump_point = jump_point;
+    env.exit = exit;
+    env.steps = steps;
+    Ok(())
+}
+
+fn invalid<T>() -> Result<T> {
+    err!("Invalid snapshot")
+}
+
+fn usize_of(value: &Value) -> Result<usize> {
+    value
+        .as_u64()
+        .and_then(|value| usize::try_from(value).ok())
+        .map_or_else(invalid, Ok)
+}
+
+fn pair(value: &Value) -> Result<(usize, usize)> {
+    Ok((usize_of(&value[0])?, usize_of(&value[1])?))
+}
+
+fn heap(heap: &Value, top: usize) -> Result<Heap> {
+    let mut blocks = BTreeMap::new();
+    for block in heap["blocks"].as_array().map_or_else(invalid, Ok)? {
+        let state = match block[2].as_str() {
+            Some("used") => State::Used,
+            Some("free") => State::Free,
+            Some("freed") => State::Freed,
+            _ => return invalid(),
+        };
+        let len = usize_of(&block[1])?;
+        blocks.insert(usize_of(&block[0])?, Block { len, state });
+    }
+
+    let len = usize_of(&heap["len"])?;
+    if len > top {
+        return invalid();
+    }
+    Ok(Heap {
+        blocks,
+        len,
+        debug: false,
+    })
+}
+
+// FNV-1a, so a snapshot isn't resumed with a program it wasn't taken of
+fn fingerprint(program: &[u8]) -> u64 {
+    program.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut res, byte| {
+        let _ = write!(res, "{byte:02x}");
+        res
+    })
+}
+
+fn unhex(text: &str) -> Result<Vec<u8>> {
+    if !text.is_ascii() || !text.len().is_multiple_of(2) {
+        return invalid();
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_or_else(|_| invalid(), Ok))
+        .collect()
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use super::*;
+    use crate::utils::{input::Input, output::Capture};
+
+    const PROGRAM: &str = "mov ax alloc 2
+mov bx 0
+jmp :loop
+out ax
+hlt 3
+
+.loop
+    inc bx
+    mov cx in
+    chr cx
+    cne bx 3
+    cjm :loop
+    ret";
+
+    // Runs `PROGRAM` with `input` for at most `steps` commands, returning
+    // what it printed and its environment
+    fn run(input: &str, steps: Option<u64>) -> (Capture, Environment) {
+        let out = Capture::default();
+        let mut env = Environment::new();
+        env.stdin = Input::from(input.as_bytes().to_vec());
+        env.out = Box::new(out.clone());
+        env.step_limit = steps;
+        crate::run(PROGRAM, &mut env).unwrap();
+        (out, env)
+    }
+
+    #[test]
+    fn test_resume() {
+        let (whole, _) = run("abc", None);
+
+        // Paused inside of the branch, with return points saved
+        let (first, env) = run("abc", Some(9));
+        assert_eq!(env.pos, (5, 1));
+        assert_eq!(env.jump_point.len(), 2);
+        let snapshot = save(&env, PROGRAM.as_bytes()).to_string();
+
+        let rest = Capture::default();
+        let mut env = Environment::new();
+        env.stdin = Input::from(b"abc".to_vec());
+        env.out = Box::new(rest.clone());
+        restore(&mut env, &snapshot, PROGRAM.as_bytes()).unwrap();
+        assert_eq!(env.steps, 9);
+        assert_eq!(
+            crate::parser::resume(&crate::compile(PROGRAM).unwrap(), &mut env),
+            Ok(3)
+        );
+        assert_eq!(first.text() + &rest.text(), whole.text());
+        assert!(env.mem.heap.in_use());
+    }
+
+    #[test]
+    fn test_wrong_program() {
+        let (_, env) = run("abc", Some(2));
+        let snapshot = save(&env, PROGRAM.as_bytes()).to_string();
+        let mut env = Environment::new();
+        assert!(restore(&mut env, &snapshot, b"out 1").is_err());
+        assert!(restore(&mut env, "{}", PROGRAM.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex(&[0, 15, 255]), "000fff");
+        assert_eq!(unhex("000fff").unwrap(), [0, 15, 255]);
+        assert!(unhex("0g").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use std::io::{self, Write};
+
+use crate::utils::{
+    error::{Error, Result},
+    input::Input,
+    token::Op,
+};
+use crate::{ast, lexer, parser, Environment};
+
+// The outcome of running a single test branch
+#[derive(Debug)]
+pub struct TestResult {
+    pub name: String,
+
+    // Why the test failed, or `None` if it passed
+    pub failure: Option<Error>,
+}
+
+// Tests are top-level branches whose name starts with `test`, e.g. `.test_add`
+fn is_test(op: &Op) -> bool {
+    matches!(op, Op::Branch(name, _) if name[1..].starts_with("test"))
+}
+
+// Runs every test branch in `program`, each in a fresh `Environment`. A test
+// starts at its branch and ends once execution reaches another test branch or
+// the end of the program. It fails if an assertion (or anything else) errors,
+// or if it halts with a non-zero exit code
+pub fn run_tests(program: &str) -> Result<Vec<TestResult>> {
+    let tree = ast::construct_tree(&lexer::lex_spanned(program)?)?;
+
+    Ok(tree
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| is_test(op))
+        .map(|(ind, op)| {
+            let name = match op {
+                Op::Branch(name, _) => name[1..].to_owned(),
+                _ => unreachable!(),
+            };
+
+            let mut env = Environment::new();
+            env.stdin = Input::from(Vec::new());
+            env.out = Box::new(io::sink());
+            env.err = Box::new(io::sink());
+            let failure = match parser::parse_from(&tree, &mut env, ind, |i| is_test(&tree[i])) {
+                Ok(0) => None,
+                Ok(code) => Some(Error::new(format!("Halted with exit code {code}"))),
+                Err(e) => Some(e),
+            };
+
+            TestResult { name, failure }
+        })
+        .collect())
+}
+
+// Writes a summary of `results` for the file at `path`, returning whether
+// every test passed
+pub fn report(path: &str, results: &[TestResult], out: &mut impl Write) -> io::Result<bool> {
+    writeln!(out, "running {} tests", results.len())?;
+    for res in results {
+        let status = if res.failure.is_some() {
+            "FAILED"
+        } else {
+            "ok"
+        };
+        writeln!(out, "test {} ... {}", res.name, status)?;
+    }
+
+    let failed: Vec<&TestResult> = results.iter().filter(|r| r.failure.is_some()).collect();
+    if !failed.is_empty() {
+        writeln!(out, "\nfailures:")?;
+        for res in &failed {
+            let e = res.failure.as_ref().unwrap();
+            match e.span {
+                Some(span) => writeln!(out, "    {}: {}:{}: {}", res.name, path, span, e.msg)?,
+                None => writeln!(out, "    {}: {}: {}", res.name, path, e.msg)?,
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "\ntest result: {}. {} passed; {} failed",
+        if failed.is_empty() { "ok" } else { "FAILED" },
+        results.len() - failed.len(),
+        failed.len()
+    )?;
+
+    Ok(failed.is_empty())
+}
+
+#[cfg(test)]
+mod tester_tests {
+    use super::*;
+
+    const PROGRAM: &str = "
+.double
+    add ax ax
+    ret
+..test_double
+    mov ax 21
+    jmp :double
+    assert_eq ax 42 \"21 doubled\"
+..test_broken
+    mov ax 2
+    cg ax 3
+    assert \"2 > 3\"
+..test_halt
+    hlt 2
+";
+
+    #[test]
+    fn test_discovery() {
+        let results = run_tests(PROGRAM).unwrap();
+        let names: Vec<&str> = results.iter().map(|r| &*r.name).collect();
+
+        assert_eq!(names, vec!["test_double", "test_broken", "test_halt"]);
+    }
+
+    #[test]
+    fn test_failures() {
+        let results = run_tests(PROGRAM).unwrap();
+
+        assert!(results[0].failure.is_none());
+        let broken = results[1].failure.as_ref().unwrap();
+        assert_eq!(broken.msg, "Assertion failed: 2 > 3");
+        assert_eq!(broken.span.map(|s| s.line), Some(12));
+        assert_eq!(
+            results[2].failure.as_ref().unwrap().msg,
+            "Halted with exit code 2"
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let mut out = Vec::new();
+        let passed = report("prog.asm", &run_tests(PROGRAM).unwrap(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(!passed);
+        assert!(out.contains("test test_double ... ok"));
+        assert!(out.contains("test_broken: prog.asm:12:5: Assertion failed: 2 > 3"));
+        assert!(out.contains("1 passed; 2 failed"));
+    }
+}
@@ -12,8 +12,17 @@ macro_rules! insert_into {
 }
 
 // For `parser.rs`
-pub const REGISTERS: &[char] = &['a', 'b', 'c', 'd', 'e', 'A', 'B', 'C', 'D', 'E'];
+pub const REGISTERS: &[char] = &[
+    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H',
+];
 pub const REGISTER_ENDINGS: &[char] = &['x', 'X', 'h', 'H', 'l', 'L'];
+// The general purpose registers, in the order they are kept in memory, two
+// bytes each
+pub const REGISTER_NAMES: &str = "abcdefgh";
+// The stack and frame pointers, which are 16 bits wide and have no halves
+pub const POINTERS: &[&str] = &["sp", "fp"];
+// The float registers, `f0` to `f7`, which each hold a 64-bit float
+pub const FLOAT_REGISTER_COUNT: usize = 8;
 pub const PUNCTUATION: &[&str] = &["+", "-", "*", "/", "[", "]", "#", "$", "@"];
 lazy_static! {
     pub static ref COMMANDS: HashMap<String, usize> = {
@@ -29,18 +38,74 @@ lazy_static! {
             "cge" 2; "cl"  2; "cle" 2; "cne" 2; "cz"  1;
             "rsh" 2; "crs" 2; "lsh" 2; "cls" 2; "or"  2;
             "cor" 2; "xor" 2; "cxo" 2; "and" 2; "can" 2;
-            "not" 1; "cno" 1; "swp" 2; "csw" 2
+            "not" 1; "cno" 1; "swp" 2; "csw" 2; "assert" 1;
+            "assert_eq" 3; "assert_ne" 3; "rdn" 1; "rdl" 2;
+            "hex" 2; "bin" 2; "pad" 2; "prs" 2; "eof" 0;
+            "err" 1; "errc" 1; "fop" 2; "frd" 3; "fwr" 3;
+            "fsk" 2; "fcl" 1; "argc" 0; "argv" 3; "env" 3;
+            "alloc" 1; "free" 1; "fmov" 2; "fadd" 2; "fsub" 2;
+            "fmul" 2; "fdiv" 2; "ftoi" 2; "feq" 2; "fne" 2;
+            "flt" 2; "fle" 2; "fgt" 2; "fge" 2; "fld" 2;
+            "fst" 2; "fout" 2; "enter" 1; "leave" 0; "lds" 2;
+            "sts" 3; "ldf" 2; "stf" 3
         );
         m
     };
 }
 
+// Opcodes of the commands in compiled programs, by index. New commands must
+// only ever be appended, or older binaries would decode to the wrong commands
+#[rustfmt::skip]
+pub const OPCODES: &[&str] = &[
+    "mov", "cmo", "inc", "cin", "dec", "cde", "out", "cou", "jmp", "cjm",
+    "mul", "div", "add", "sub", "cmu", "cdi", "cad", "csu", "str", "db",
+    "in", "chr", "cch", "hlt", "chl", "ret", "cre", "cmp", "stk", "psh",
+    "cps", "pop", "cpo", "ceq", "cg", "cge", "cl", "cle", "cne", "cz",
+    "rsh", "crs", "lsh", "cls", "or", "cor", "xor", "cxo", "and", "can",
+    "not", "cno", "swp", "csw", "assert", "assert_eq", "assert_ne", "rdn", "rdl", "hex",
+    "bin", "pad", "prs", "eof", "err", "errc", "fop", "frd", "fwr", "fsk",
+    "fcl", "argc", "argv", "env", "alloc", "free", "fmov", "fadd", "fsub", "fmul",
+    "fdiv", "ftoi", "feq", "fne", "flt", "fle", "fgt", "fge", "fld", "fst",
+    "fout", "enter", "leave", "lds", "sts", "ldf", "stf",
+];
+
+// The commands that work with floats, and which of their operands are floats.
+// Float operands can be float registers, float literals or anything an integer
+// can be read from, which is then converted. Those that are written to, like
+// the first operand of `fadd`, have to be float registers
+#[rustfmt::skip]
+pub const FLOAT_COMMANDS: &[(&str, &[bool])] = &[
+    ("fmov", &[true, true]), ("fadd", &[true, true]), ("fsub", &[true, true]),
+    ("fmul", &[true, true]), ("fdiv", &[true, true]), ("ftoi", &[false, true]),
+    ("feq", &[true, true]), ("fne", &[true, true]), ("flt", &[true, true]),
+    ("fle", &[true, true]), ("fgt", &[true, true]), ("fge", &[true, true]),
+    ("fld", &[true, false]), ("fst", &[false, true]), ("fout", &[true, false]),
+];
+
+// Conditional commands and the command they run when the conditional flag is set
+#[rustfmt::skip]
+pub const CONDITIONALS: &[(&str, &str)] = &[
+    ("cmo", "mov"), ("cin", "inc"), ("cde", "dec"), ("cou", "out"), ("cjm", "jmp"),
+    ("cmu", "mul"), ("cdi", "div"), ("cad", "add"), ("csu", "sub"), ("cch", "chr"),
+    ("chl", "hlt"), ("cre", "ret"), ("cps", "psh"), ("cpo", "pop"), ("crs", "rsh"),
+    ("cls", "lsh"), ("cor", "or"), ("cxo", "xor"), ("can", "and"), ("cno", "not"),
+    ("csw", "swp"),
+];
+
 // For `mem.rs`
 pub const U8_ALIGN: usize = std::mem::align_of::<u8>();
-pub const REGISTRY_OFFSET: usize = 10;
-// First 10 bytes are the registry, 11th byte for the cmp flags, next 9 are
-// reserved for future use
+// First 16 bytes are the general purpose registers, the next 2 the frame
+// pointer and the 64 after that the float registers. The stack pointer is the
+// length of the stack, so it isn't kept
+pub const FRAME_POINTER: usize = 16;
+pub const FLOAT_REGISTERS: usize = 18;
+pub const REGISTRY_OFFSET: usize = FLOAT_REGISTERS + FLOAT_REGISTER_COUNT * 8;
+// The byte at REGISTRY_OFFSET is for the cmp flags, next 9 are reserved for
+// future use
 pub const OFFSET: usize = REGISTRY_OFFSET + 10;
+// How big memory is. The stack and cells share 1004 bytes, as many as they had
+// before there were more registers, so programs can still use the same cells
+pub const MEM_SIZE: usize = OFFSET + 1004;
 
 // Python backend. Used by `utils/mod.rs`
 #[cfg(feature = "inline-python")]
@@ -52,5 +117,5 @@ ret = eval"#;
 
 #[cfg(feature = "inline-python")]
 lazy_static! {
-    pub static ref REGISTER_REGEX: Regex = Regex::new(r#"@([a-e][hl]|[a-e]{1,2}x)"#).unwrap();
+    pub static ref REGISTER_REGEX: Regex = Regex::new(r#"@([a-h][hl]|[a-h]{1,2}x|sp|fp)"#).unwrap();
 }
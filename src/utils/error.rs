@@ -0,0 +1,53 @@
+use std::fmt;
+
+use super::token::Span;
+
+// Any error raised while building or running a program. Everything that used
+// to abort the process bubbles up as one of these instead, so the library can
+// be embedded (and tested) without tearing down the host
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub msg: String,
+
+    // Where in the source the error was raised, if known
+    pub span: Option<Span>,
+}
+
+impl Error {
+    pub fn new(msg: impl Into<String>) -> Self {
+        Self {
+            msg: msg.into(),
+            span: None,
+        }
+    }
+
+    // Attaches `span` to the error, unless a more precise one was already set.
+    // The default span means the location is unknown, like in programs
+    // compiled without line info, so it is ignored
+    #[must_use]
+    pub fn at(mut self, span: Span) -> Self {
+        if span != Span::default() {
+            self.span.get_or_insert(span);
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{}: {}", span, self.msg),
+            None => write!(f, "{}", self.msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::new(format!("I/O error: {e}"))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,217 @@
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// The files a program opened, by handle, and the directories it may open files
+// in. Nothing is allowed until a directory is granted, so by default every
+// file the program tries to open is denied
+#[derive(Debug, Default)]
+pub struct Files {
+    allowed: Vec<PathBuf>,
+    handles: Vec<Option<File>>,
+}
+
+impl Files {
+    // Lets the program open files inside of `dir`, or any directory below it
+    pub fn allow(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = fs::canonicalize(dir)?;
+        if !dir.is_dir() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Not a directory"));
+        }
+        self.allowed.push(dir);
+        Ok(())
+    }
+
+    // Opens `path` for reading (mode 0), writing over it (1) or appending to
+    // it (2), creating it if it is written to. Returns the file's handle
+    pub fn open(&mut self, path: &Path, mode: i32) -> io::Result<usize> {
+        let mut options = OpenOptions::new();
+        match mode {
+            0 => options.read(true),
+            1 => options.write(true).create(true).truncate(true),
+            2 => options.append(true).create(true),
+            _ => return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid mode")),
+        };
+        let file = options.open(self.resolve(path)?)?;
+
+        if let Some(handle) = self.handles.iter().position(Option::is_none) {
+            self.handles[handle] = Some(file);
+            Ok(handle)
+        } else {
+            self.handles.push(Some(file));
+            Ok(self.handles.len() - 1)
+        }
+    }
+
+    // Reads until `buf` is full or the file ends, returning how much was read
+    pub fn read(&mut self, handle: i32, buf: &mut [u8]) -> io::Result<usize> {
+        let file = self.get(handle)?;
+        let mut len = 0;
+        while len < buf.len() {
+            match file.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(len)
+    }
+
+    pub fn write(&mut self, handle: i32, buf: &[u8]) -> io::Result<()> {
+        self.get(handle)?.write_all(buf)
+    }
+
+    // Moves to `pos` bytes from the start of the file
+    pub fn seek(&mut self, handle: i32, pos: u64) -> io::Result<u64> {
+        self.get(handle)?.seek(SeekFrom::Start(pos))
+    }
+
+    pub fn close(&mut self, handle: i32) -> io::Result<()> {
+        let file = self.get(handle)?;
+        file.flush()?;
+        self.handles[usize::try_from(handle).unwrap_or_default()] = None;
+        Ok(())
+    }
+
+    fn get(&mut self, handle: i32) -> io::Result<&mut File> {
+        let handles = &mut self.handles;
+        usize::try_from(handle)
+            .ok()
+            .and_then(move |handle| handles.get_mut(handle))
+            .and_then(Option::as_mut)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Invalid file handle"))
+    }
+
+    // Where `path` really points to, as long as that is inside of an allowed
+    // directory. Symlinks are followed, so they can't lead out of one
+    fn resolve(&self, path: &Path) -> io::Result<PathBuf> {
+        let resolved = match fs::canonicalize(path) {
+            Ok(resolved) => resolved,
+            // A file that doesn't exist yet is resolved through the directory
+            // it would be created in. If something exists there after all, it
+            // is a dangling symlink, which could point anywhere
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let name = path.file_name().ok_or(e)?;
+                let parent = match path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+                let resolved = fs::canonicalize(parent)?.join(name);
+                if fs::symlink_metadata(&resolved).is_ok() {
+                    return Err(denied());
+                }
+                resolved
+            }
+            Err(e) => return Err(e),
+        };
+
+        if self.allowed.iter().any(|dir| resolved.starts_with(dir)) {
+            Ok(resolved)
+        } else {
+            Err(denied())
+        }
+    }
+}
+
+fn denied() -> io::Error {
+    io::Error::new(
+        ErrorKind::PermissionDenied,
+        "Not inside of an allowed directory",
+    )
+}
+
+#[cfg(test)]
+mod files_tests {
+    use super::*;
+
+    // A fresh directory for a test to work in
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("arsm-files-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_denied_by_default() {
+        let dir = temp_dir("default");
+        fs::write(dir.join("a.txt"), "hi").unwrap();
+
+        let mut files = Files::default();
+        let e = files.open(&dir.join("a.txt"), 0).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = temp_dir("round-trip");
+        let mut files = Files::default();
+        files.allow(&dir).unwrap();
+
+        let path = dir.join("a.txt");
+        let handle = i32::try_from(files.open(&path, 1).unwrap()).unwrap();
+        files.write(handle, b"hello").unwrap();
+        files.close(handle).unwrap();
+        assert!(files.close(handle).is_err());
+
+        let handle = i32::try_from(files.open(&path, 0).unwrap()).unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(files.read(handle, &mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(files.seek(handle, 1).unwrap(), 1);
+        assert_eq!(files.read(handle, &mut buf[..2]).unwrap(), 2);
+        assert_eq!(&buf[..2], b"el");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_escapes() {
+        let dir = temp_dir("escapes");
+        let inner = dir.join("inner");
+        fs::create_dir_all(&inner).unwrap();
+        fs::write(dir.join("secret.txt"), "no").unwrap();
+
+        let mut files = Files::default();
+        files.allow(&inner).unwrap();
+        for path in [
+            inner.join("../secret.txt"),
+            inner.join("../new.txt"),
+            inner.join(".."),
+        ] {
+            assert!(
+                files.open(&path, 1).is_err(),
+                "{} was opened",
+                path.display()
+            );
+        }
+        assert!(!dir.join("new.txt").exists());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("secret.txt"), inner.join("link")).unwrap();
+            std::os::unix::fs::symlink(dir.join("missing.txt"), inner.join("dangling")).unwrap();
+            assert!(files.open(&inner.join("link"), 0).is_err());
+            assert!(files.open(&inner.join("dangling"), 1).is_err());
+            assert!(!dir.join("missing.txt").exists());
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_handles_are_reused() {
+        let dir = temp_dir("handles");
+        let mut files = Files::default();
+        files.allow(&dir).unwrap();
+
+        let a = files.open(&dir.join("a"), 1).unwrap();
+        let b = files.open(&dir.join("b"), 1).unwrap();
+        assert_ne!(a, b);
+        files.close(i32::try_from(a).unwrap()).unwrap();
+        assert_eq!(files.open(&dir.join("c"), 1).unwrap(), a);
+        assert!(files.write(-1, b"x").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+
+use super::error::Result;
+
+// Keeps track of the blocks handed out by `alloc`. The heap grows down from
+// the end of the cell memory, so it stays out of the way of cells that
+// programs manage themselves, which usually sit at its start. Its blocks are
+// kept by where they start, and together cover the last `len` cells
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Heap {
+    pub(crate) blocks: BTreeMap<usize, Block>,
+    pub(crate) len: usize,
+
+    // Freed blocks are never handed out again, so that freeing or using them
+    // again can be caught
+    pub debug: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Block {
+    pub(crate) len: usize,
+    pub(crate) state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum State {
+    Used,
+    Free,
+    // Freed in debug mode
+    Freed,
+}
+
+impl State {
+    // How dumps and snapshots call it
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Used => "used",
+            Self::Free => "free",
+            Self::Freed => "freed",
+        }
+    }
+}
+
+impl Heap {
+    // Finds room for `len` cells (at least one), in a cell memory that is
+    // `top` cells long. Returns where they start, or `None` if there is no room
+    pub fn alloc(&mut self, top: usize, len: usize) -> Option<usize> {
+        let len = len.max(1);
+        let fit = self
+            .blocks
+            .iter()
+            .find(|(_, block)| block.state == State::Free && block.len >= len)
+            .map(|(&start, &block)| (start, block));
+
+        if let Some((start, block)) = fit {
+            if block.len > len {
+                self.blocks.insert(
+                    start + len,
+                    Block {
+                        len: block.len - len,
+                        state: State::Free,
+                    },
+                );
+            }
+            self.blocks.insert(
+                start,
+                Block {
+                    len,
+                    state: State::Used,
+                },
+            );
+            return Some(start);
+        }
+
+        if self.len + len > top {
+            return None;
+        }
+        self.len += len;
+        let start = top - self.len;
+        self.blocks.insert(
+            start,
+            Block {
+                len,
+                state: State::Used,
+            },
+        );
+        Some(start)
+    }
+
+    // Gives the block starting at `start` back, merging it with the free
+    // blocks around it
+    pub fn free(&mut self, start: usize) -> Result<()> {
+        let mut block = match self.blocks.get(&start) {
+            Some(block) if block.state == State::Used => *block,
+            Some(block) if block.state == State::Freed => {
+                return crate::err!("Double free of address {start}")
+            }
+            _ => return crate::err!("Address {start} is not the start of an allocated block"),
+        };
+
+        if self.debug {
+            self.blocks.insert(
+                start,
+                Block {
+                    state: State::Freed,
+                    ..block
+                },
+            );
+            return Ok(());
+        }
+
+        let mut start = start;
+        if let Some(next) = self.blocks.get(&(start + block.len)).copied() {
+            if next.state == State::Free {
+                self.blocks.remove(&(start + block.len));
+                block.len += next.len;
+            }
+        }
+        if let Some((&prev_start, &prev)) = self.blocks.range(..start).next_back() {
+            if prev.state == State::Free {
+                self.blocks.remove(&start);
+                start = prev_start;
+                block.len += prev.len;
+            }
+        }
+
+        // Free space at the bottom of the heap goes back to the cell memory
+        if self.blocks.keys().next() == Some(&start) {
+            self.blocks.remove(&start);
+            self.len -= block.len;
+        } else {
+            self.blocks.insert(
+                start,
+                Block {
+                    len: block.len,
+                    state: State::Free,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    // In debug mode, fails if any of the `len` cells starting at `pos` were freed
+    pub fn check(&self, pos: usize, len: usize) -> Result<()> {
+        if !self.debug {
+            return Ok(());
+        }
+
+        for (&start, block) in self.blocks.range(..pos.saturating_add(len)).rev() {
+            if start + block.len <= pos {
+                break;
+            }
+            if block.state == State::Freed {
+                return crate::err!("Use after free of address {pos}");
+            }
+        }
+        Ok(())
+    }
+
+    // Whether any cells are taken up by the heap
+    pub fn in_use(&self) -> bool {
+        self.len > 0
+    }
+}
+
+#[cfg(test)]
+mod heap_tests {
+    use super::*;
+
+    #[test]
+    fn test_alloc() {
+        let mut heap = Heap::default();
+        assert_eq!(heap.alloc(100, 10), Some(90));
+        assert_eq!(heap.alloc(100, 0), Some(89));
+        assert_eq!(heap.alloc(100, 89), Some(0));
+        assert_eq!(heap.alloc(100, 1), None);
+    }
+
+    #[test]
+    fn test_reuse() {
+        let mut heap = Heap::default();
+        let a = heap.alloc(100, 10).unwrap();
+        let b = heap.alloc(100, 10).unwrap();
+        let c = heap.alloc(100, 10).unwrap();
+
+        // Both neighbours of `b` merge into one block, which is split again
+        heap.free(a).unwrap();
+        heap.free(b).unwrap();
+        assert_eq!(heap.alloc(100, 15), Some(b));
+        assert_eq!(heap.alloc(100, 5), Some(b + 15));
+
+        // Freeing the bottom block gives its space back
+        heap.free(c).unwrap();
+        assert_eq!(heap.len, 20);
+        heap.free(b).unwrap();
+        heap.free(b + 15).unwrap();
+        assert!(!heap.in_use());
+        assert!(heap.blocks.is_empty());
+    }
+
+    #[test]
+    fn test_bad_free() {
+        let mut heap = Heap::default();
+        let a = heap.alloc(100, 10).unwrap();
+        assert!(heap.free(a + 1).is_err());
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+    }
+
+    #[test]
+    fn test_debug() {
+        let mut heap = Heap {
+            debug: true,
+            ..Heap::default()
+        };
+        let a = heap.alloc(100, 10).unwrap();
+        let b = heap.alloc(100, 10).unwrap();
+        heap.free(a).unwrap();
+
+        assert_eq!(heap.free(a).unwrap_err().msg, "Double free of address 90");
+        assert!(heap.check(b, 10).is_ok());
+        assert!(heap.check(b + 8, 4).is_err());
+        assert!(heap.check(a + 9, 1).is_err());
+        assert!(heap.check(0, 4).is_ok());
+        // Freed blocks aren't handed out again
+        assert_eq!(heap.alloc(100, 5), Some(75));
+    }
+}
@@ -0,0 +1,29 @@
+use super::heap::Heap;
+
+// A byte of memory that was written to, and what it held before and after
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Change {
+    pub loc: usize,
+    pub old: u8,
+    pub new: u8,
+}
+
+// Everything about the program right before one of its commands ran, apart
+// from the bytes of its memory. Those are kept as the `Change`s the command
+// made, which start at `changes` in the memory's log
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    // How many commands ran before this one
+    pub step: u64,
+    pub pos: (usize, usize),
+    pub changes: usize,
+    pub s_size: usize,
+    pub s_len: usize,
+    pub heap: Heap,
+    // How many return points there were, and the last of them. A command can
+    // add one and take one away, but never more, so this is enough to undo it
+    pub jumps: usize,
+    pub last_jump: Option<(usize, usize)>,
+    // How much input was used up
+    pub stdin: u64,
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Cursor, ErrorKind, Read, Write};
+
+// The program's input. Nothing is read from the source until the program asks
+// for it, so programs can read from pipes or be interactive, and whatever was
+// read but not used yet is buffered so it can be peeked at. Running out of
+// input is `None`, which keeps it apart from a literal 0 byte
+pub struct Input {
+    reader: BufReader<Box<dyn Read>>,
+    // How many bytes the program used up, which is where a snapshot resumes
+    consumed: u64,
+    // What was used up, while a history is being kept, and what was given
+    // back, last byte first, to be used up again
+    used: Option<Vec<u8>>,
+    rewound: Vec<u8>,
+}
+
+impl Input {
+    pub fn new(source: impl Read + 'static) -> Self {
+        Self {
+            reader: BufReader::new(Box::new(source)),
+            consumed: 0,
+            used: None,
+            rewound: Vec::new(),
+        }
+    }
+
+    // Reads from the process' stdin
+    pub fn stdin() -> Self {
+        Self::new(io::stdin())
+    }
+
+    // The next byte, without moving past it. Blocks until there is one, or the
+    // source is exhausted
+    pub fn peek(&mut self) -> io::Result<Option<u8>> {
+        if let Some(&byte) = self.rewound.last() {
+            return Ok(Some(byte));
+        }
+        loop {
+            match self.reader.fill_buf() {
+                Ok(buf) => return Ok(buf.first().copied()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn read(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.peek()?;
+        if let Some(byte) = byte {
+            if self.rewound.pop().is_none() {
+                self.reader.consume(1);
+            }
+            self.consumed += 1;
+            if let Some(used) = &mut self.used {
+                used.push(byte);
+            }
+        }
+        Ok(byte)
+    }
+
+    // Keeps what is used up from here on, so it can be given back
+    pub fn remember(&mut self) {
+        self.used.get_or_insert_with(Vec::new);
+    }
+
+    // Gives back the last `n` bytes that were used up (as far as they were
+    // remembered), so they are read again next
+    pub fn rewind(&mut self, n: u64) {
+        for _ in 0..n {
+            let Some(byte) = self.used.as_mut().and_then(Vec::pop) else {
+                break;
+            };
+            self.rewound.push(byte);
+            self.consumed -= 1;
+        }
+    }
+
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    // Reads past the next `n` bytes, as if the program had used them up.
+    // Returns how many there were before the source ran out
+    pub fn skip(&mut self, n: u64) -> io::Result<u64> {
+        let mut skipped = 0;
+        while skipped < n && self.read()?.is_some() {
+            skipped += 1;
+        }
+        Ok(skipped)
+    }
+
+    // Whether the next read has to wait on the source, because everything that
+    // was read from it already was used up
+    pub fn is_drained(&self) -> bool {
+        self.rewound.is_empty() && self.reader.buffer().is_empty()
+    }
+}
+
+impl From<Vec<u8>> for Input {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::new(Cursor::new(bytes))
+    }
+}
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Input")
+            .field("buffered", &self.reader.buffer())
+            .field("consumed", &self.consumed)
+            .finish_non_exhaustive()
+    }
+}
+
+// Reads from `source`, writing everything that was read to `log` as it goes.
+// Feeding the log back as input later replays exactly what the program saw,
+// up to and including where the input ran out, even if it was typed in
+pub struct Recorder<R, W> {
+    source: R,
+    log: W,
+}
+
+impl<R: Read, W: Write> Recorder<R, W> {
+    pub fn new(source: R, log: W) -> Self {
+        Self { source, log }
+    }
+}
+
+impl<R: Read, W: Write> Read for Recorder<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.source.read(buf)?;
+        // Written right away, so the log survives the program crashing
+        self.log.write_all(&buf[..len])?;
+        self.log.flush()?;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod input_tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let mut input = Input::from(vec![1, 2, 3, 5]);
+
+        assert_eq!(input.read().unwrap(), Some(1));
+        assert_eq!(input.read().unwrap(), Some(2));
+        input.read().unwrap();
+        assert_eq!(input.read().unwrap(), Some(5));
+        assert_eq!(input.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut input = Input::from(vec![1, 2]);
+
+        assert_eq!(input.peek().unwrap(), Some(1));
+        assert_eq!(input.read().unwrap(), Some(1));
+        input.read().unwrap();
+        assert_eq!(input.peek().unwrap(), None);
+    }
+
+    #[test]
+    fn test_zero_byte() {
+        let mut input = Input::from(vec![0]);
+
+        assert_eq!(input.read().unwrap(), Some(0));
+        assert_eq!(input.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_skip() {
+        let mut input = Input::from(vec![1, 2, 3]);
+        input.read().unwrap();
+        assert_eq!(input.skip(1).unwrap(), 1);
+        assert_eq!(input.consumed(), 2);
+        assert_eq!(input.skip(5).unwrap(), 1);
+        assert_eq!(input.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut input = Input::from(vec![1, 2, 3]);
+        input.read().unwrap();
+        input.remember();
+        input.read().unwrap();
+        input.read().unwrap();
+
+        input.rewind(5);
+        assert_eq!(input.consumed(), 1);
+        assert_eq!(input.peek().unwrap(), Some(2));
+        assert_eq!(input.read().unwrap(), Some(2));
+        input.rewind(1);
+        assert_eq!(input.read().unwrap(), Some(2));
+        assert_eq!(input.read().unwrap(), Some(3));
+        assert_eq!(input.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_recorder() {
+        let log = crate::utils::output::Capture::default();
+        let mut input = Input::new(Recorder::new(Cursor::new(vec![4, 5]), log.clone()));
+        assert_eq!(input.read().unwrap(), Some(4));
+        assert_eq!(log.contents(), [4, 5]);
+    }
+
+    #[test]
+    fn test_lazy() {
+        // Hands out one byte per read, like an interactive terminal would a line
+        struct Trickle(Vec<u8>);
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() || buf.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = self.0.remove(0);
+                Ok(1)
+            }
+        }
+
+        let mut input = Input::new(Trickle(vec![7, 8]));
+        assert!(input.is_drained());
+        assert_eq!(input.peek().unwrap(), Some(7));
+        assert!(!input.is_drained());
+        assert_eq!(input.read().unwrap(), Some(7));
+        assert!(input.is_drained());
+        assert_eq!(input.read().unwrap(), Some(8));
+        assert_eq!(input.read().unwrap(), None);
+    }
+}
@@ -2,18 +2,32 @@
 #![allow(dead_code)]
 
 use std::alloc::{alloc_zeroed, Layout};
+use std::cell::RefCell;
+use std::convert::TryFrom;
 use std::ops::Range;
 use std::{fmt, ptr};
 
 use super::consts::{OFFSET, REGISTRY_OFFSET, U8_ALIGN};
+use super::error::Result;
+use super::heap::Heap;
+use super::history::Change;
 use super::traits::*;
+use super::watch::{Access, Hit, Watch};
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone)]
 pub struct Memory {
     mem: *mut u8,
     pub size: usize,
     pub s_size: usize,
     pub s_len: usize,
+    pub heap: Heap,
+
+    // Every byte written, oldest first, while a history is being kept
+    pub log: Option<Vec<Change>>,
+
+    // Accesses to any of `watches` are kept in `hits` until they are taken
+    pub watches: Vec<Watch>,
+    hits: RefCell<Vec<Hit>>,
 }
 
 // GENERAL PURPOSE
@@ -35,11 +49,29 @@ impl Memory {
             size: mem_size,
             s_size,
             s_len: 0,
+            heap: Heap::default(),
+            log: None,
+            watches: Vec::new(),
+            hits: RefCell::default(),
         }
     }
 
     #[inline]
     fn write(&mut self, loc: usize, element: u8) {
+        if let Some(log) = &mut self.log {
+            log.push(Change {
+                loc,
+                // Safety: Same as for the write
+                old: unsafe { ptr::read(self.mem.add(loc)) },
+                new: element,
+            });
+        }
+        self.write_raw(loc, element);
+    }
+
+    // Writes without keeping the write in the log
+    #[inline]
+    fn write_raw(&mut self, loc: usize, element: u8) {
         // Safety: All calls to this function are checked (private)
         unsafe {
             ptr::write(self.mem.add(loc), element);
@@ -51,11 +83,27 @@ impl Memory {
         unsafe { ptr::read(self.mem.add(loc)) }
     }
 
+    // Every byte, the registers and stack included
+    #[inline]
+    pub fn as_slice(&self) -> &[u8] {
+        // Safety: `mem` always points to `size` bytes
+        unsafe { std::slice::from_raw_parts(self.mem, self.size) }
+    }
+
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.mem, self.size) }
+    }
+
+    // The raw memory, for code that accesses it directly (like the JIT)
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.mem
+    }
+
     pub fn resize_stack(&mut self, s_size: usize) {
-        if s_size > self.s_size {
-            unsafe {
-                ptr::write_bytes(self.mem.add(self.s_size), 0, s_size - self.s_size);
-            }
+        for i in self.s_size..s_size {
+            self.write(OFFSET + i, 0);
         }
 
         self.s_size = s_size;
@@ -64,10 +112,8 @@ impl Memory {
 
     #[inline]
     pub fn clear(&mut self) {
-        // Safety: since it is guaranteed to be a non-null pointer of self.size length,
-        // this is safe
-        unsafe {
-            ptr::write_bytes(self.mem, 0, self.size);
+        for i in 0..self.size {
+            self.write(i, 0);
         }
     }
 
@@ -76,8 +122,9 @@ impl Memory {
         self.size - self.s_size - OFFSET
     }
 
+    /// # Safety
+    /// Range must be ascending and fit within 0..=N, where N is the size of Memory
     #[inline]
-    // Safety: Range must be ascending and fit within 0..=N, where N is the size of Memory
     pub unsafe fn read_range(&self, range: Range<usize>) -> &[u8] {
         assert!(range.start > 0 && range.end < self.size);
         std::slice::from_raw_parts(self.mem.add(range.start), range.end - range.start)
@@ -97,16 +144,19 @@ impl Memory {
     // cmp flags (generic, eq, g, l, unused, unused, unused)
     #[inline]
     pub fn flag_write_cmp(&mut self) {
+        self.watch(Access::Write, REGISTRY_OFFSET..REGISTRY_OFFSET + 1);
         self.write(REGISTRY_OFFSET, 1);
     }
 
     #[inline]
     pub fn flag_reset_cmp(&mut self) {
+        self.watch(Access::Write, REGISTRY_OFFSET..REGISTRY_OFFSET + 1);
         self.write(REGISTRY_OFFSET, 0);
     }
 
     #[inline]
     pub fn flag_read_cmp(&self) -> bool {
+        self.watch(Access::Read, REGISTRY_OFFSET..REGISTRY_OFFSET + 1);
         self.read(REGISTRY_OFFSET) != 0
     }
 }
@@ -115,13 +165,14 @@ impl Memory {
 impl Memory {
     pub fn r_write<N: Num, P: Position>(&mut self, keys: &P, element: &N) {
         let bytes = element.get_bytes();
+        self.watch_register(Access::Write, keys);
         match keys.indexes() {
-            (Some(a), None, None) => {
+            Location::Wide(a) => {
                 self.write(a, bytes[0]);
                 self.write(a + 1, bytes[1]);
             }
 
-            (Some(a), None, Some(side)) => {
+            Location::Half(a, side) => {
                 if Pos::Upper == side {
                     self.write(a + 1, bytes[0]);
                 } else {
@@ -129,51 +180,102 @@ impl Memory {
                 }
             }
 
-            (Some(a), Some(b), None) => {
+            Location::Pair(a, b) => {
                 self.write(a, bytes[0]);
                 self.write(a + 1, bytes[1]);
                 self.write(b, bytes[2]);
                 self.write(b + 1, bytes[3]);
             }
-
-            _ => {}
         }
     }
 
     pub fn r_read<N: Num>(&self, keys: &dyn Position) -> N {
+        self.watch_register(Access::Read, keys);
         match keys.indexes() {
-            (Some(a), None, None) => N::from_bytes(&[self.read(a), self.read(a + 1)]),
+            Location::Wide(a) => N::from_bytes(&[self.read(a), self.read(a + 1)]),
 
-            (Some(a), None, Some(side)) => N::from_bytes(&[if Pos::Upper == side {
+            Location::Half(a, side) => N::from_bytes(&[if Pos::Upper == side {
                 self.read(a + 1)
             } else {
                 self.read(a)
             }]),
 
-            (Some(a), Some(b), None) => N::from_bytes(&[
+            Location::Pair(a, b) => N::from_bytes(&[
                 self.read(a),
                 self.read(a + 1),
                 self.read(b),
                 self.read(b + 1),
             ]),
+        }
+    }
 
-            _ => panic!("Something went wrong"),
+    fn watch_register<P: Position + ?Sized>(&self, access: Access, keys: &P) {
+        match keys.indexes() {
+            Location::Wide(a) => self.watch(access, a..a + 2),
+            Location::Half(a, Pos::Upper) => self.watch(access, a + 1..a + 2),
+            Location::Half(a, Pos::Lower) => self.watch(access, a..a + 1),
+            Location::Pair(a, b) => {
+                self.watch(access, a..a + 2);
+                self.watch(access, b..b + 2);
+            }
         }
     }
 }
 
+// FLOAT REGISTRY
+impl Memory {
+    pub fn f_write(&mut self, reg: FloatRegister, val: f64) {
+        let start = reg.offset();
+        self.watch(Access::Write, start..start + 8);
+        for (i, byte) in val.to_ne_bytes().iter().enumerate() {
+            self.write(start + i, *byte);
+        }
+    }
+
+    pub fn f_read(&self, reg: FloatRegister) -> f64 {
+        let start = reg.offset();
+        self.watch(Access::Read, start..start + 8);
+        f64::from_bytes(&self.as_slice()[start..start + 8])
+    }
+}
+
 // STACK
 impl Memory {
-    pub fn s_push<N: Num>(&mut self, element: &N) {
+    // The stack pointer, which is how many bytes are on the stack
+    #[inline]
+    pub fn sp(&self) -> usize {
+        self.s_len
+    }
+
+    // Moves the stack pointer, zeroing whatever falls off of the stack
+    pub fn set_sp(&mut self, sp: usize) -> Result<()> {
+        if sp > self.s_size {
+            return crate::err!("The stack pointer has to stay inside of the stack");
+        }
+        if sp < self.s_len {
+            self.watch(Access::Write, OFFSET + sp..OFFSET + self.s_len);
+        }
+        for i in sp..self.s_len {
+            self.write(OFFSET + i, 0);
+        }
+        self.s_len = sp;
+        Ok(())
+    }
+
+    // Pushes `element` onto the stack, failing if it doesn't fit
+    pub fn s_push<N: Num>(&mut self, element: &N) -> Result<()> {
         let bytes = element.get_bytes();
+        if self.s_len + bytes.len() > self.s_size {
+            return crate::err!("The stack is full");
+        }
+
+        let top = OFFSET + self.s_len;
+        self.watch(Access::Write, top..top + bytes.len());
         for byte in &bytes {
-            if self.s_len == self.s_size {
-                panic!("s is full. Cannot fit size {} element.", bytes.len());
-            } else {
-                self.write(OFFSET + self.s_len, *byte);
-                self.s_len += 1;
-            }
+            self.write(OFFSET + self.s_len, *byte);
+            self.s_len += 1;
         }
+        Ok(())
     }
 
     pub fn s_pop_8(&mut self) -> Option<u8> {
@@ -181,11 +283,10 @@ impl Memory {
             None
         } else {
             self.s_len -= 1;
+            self.watch(Access::Read, OFFSET + self.s_len..OFFSET + self.s_len + 1);
             let num = self.read(OFFSET + self.s_len);
             // Let's be sanitary and zero the value
-            unsafe {
-                ptr::write(self.mem.add(OFFSET + self.s_len), 0);
-            }
+            self.write(OFFSET + self.s_len, 0);
 
             Some(num)
         }
@@ -196,12 +297,13 @@ impl Memory {
             None
         } else {
             self.s_len -= 2;
+            self.watch(Access::Read, OFFSET + self.s_len..OFFSET + self.s_len + 2);
             let num = i16::from_ne_bytes([
                 self.read(OFFSET + self.s_len),
                 self.read(OFFSET + 1 + self.s_len),
             ]);
-            unsafe {
-                ptr::write_bytes(self.mem.add(OFFSET + self.s_len), 0, 2);
+            for i in 0..2 {
+                self.write(OFFSET + self.s_len + i, 0);
             }
 
             Some(num)
@@ -213,70 +315,187 @@ impl Memory {
             None
         } else {
             self.s_len -= 4;
+            self.watch(Access::Read, OFFSET + self.s_len..OFFSET + self.s_len + 4);
             let num = i32::from_ne_bytes([
                 self.read(OFFSET + self.s_len),
                 self.read(OFFSET + 1 + self.s_len),
                 self.read(OFFSET + 2 + self.s_len),
                 self.read(OFFSET + 3 + self.s_len),
             ]);
-            unsafe {
-                ptr::write_bytes(self.mem.add(OFFSET + self.s_len), 0, 4);
+            for i in 0..4 {
+                self.write(OFFSET + self.s_len + i, 0);
             }
 
             Some(num)
         }
     }
+
+    // Reads the value `pos` bytes above the bottom of the stack, failing
+    // unless all of it is on the stack
+    pub fn s_read<N: Num>(&self, pos: usize) -> Result<N> {
+        let range = self.stack_range(pos, N::len())?;
+        self.watch(Access::Read, range.clone());
+        Ok(N::from_bytes(&self.as_slice()[range]))
+    }
+
+    // Overwrites the value `pos` bytes above the bottom of the stack, failing
+    // unless all of it is on the stack
+    pub fn s_write<N: Num>(&mut self, pos: usize, element: &N) -> Result<()> {
+        let range = self.stack_range(pos, N::len())?;
+        self.watch(Access::Write, range.clone());
+        for (loc, byte) in range.zip(element.get_bytes()) {
+            self.write(loc, byte);
+        }
+        Ok(())
+    }
+
+    // Where the `len` bytes `pos` bytes above the bottom of the stack are
+    fn stack_range(&self, pos: usize, len: usize) -> Result<Range<usize>> {
+        match pos.checked_add(len) {
+            Some(end) if end <= self.s_len => Ok(OFFSET + pos..OFFSET + end),
+            _ => crate::err!("Stack access out of bounds"),
+        }
+    }
+
+    // Starts a frame: saves the frame pointer on the stack, points it at the
+    // new top, and makes room for `len` zeroed bytes of locals above it
+    pub fn enter(&mut self, len: usize) -> Result<()> {
+        match self
+            .s_len
+            .checked_add(len)
+            .and_then(|end| end.checked_add(2))
+        {
+            Some(end) if end <= self.s_size => {}
+            _ => return crate::err!("The stack is full"),
+        }
+
+        let fp: i16 = self.r_read(&FramePointer);
+        self.s_push(&fp)?;
+        let Ok(fp) = i16::try_from(self.s_len) else {
+            return crate::err!("Could not convert value to type <i16>");
+        };
+        self.r_write(&FramePointer, &fp);
+        self.set_sp(self.s_len + len)
+    }
+
+    // Ends the frame `enter` started, dropping everything above the frame
+    // pointer and restoring the one it saved
+    pub fn leave(&mut self) -> Result<()> {
+        let fp: i16 = self.r_read(&FramePointer);
+        match usize::try_from(fp) {
+            Ok(fp) if (2..=self.s_len).contains(&fp) => self.set_sp(fp)?,
+            _ => return crate::err!("There is no frame to leave"),
+        }
+
+        let fp = self.s_pop_16().unwrap();
+        self.r_write(&FramePointer, &fp);
+        Ok(())
+    }
 }
 
 // MEMORY
 impl Memory {
-    pub fn m_write<N: Num>(&mut self, pos: usize, val: &N) {
-        let start = OFFSET + self.s_size;
-        let bytes = val.get_bytes();
-        for (i, byte) in bytes.iter().enumerate() {
-            if start + pos + i >= self.size {
-                panic!(
-                    "Cannot fit {} byte number into memory of size {} at point {}",
-                    bytes.len(),
-                    self.size,
-                    start + pos
-                );
-            } else {
-                self.write(start + pos + i, *byte);
+    // Where the `len` cells starting at cell `pos` are, failing unless all of
+    // them are inside of the memory
+    pub fn cells(&self, pos: usize, len: usize) -> Result<Range<usize>> {
+        match pos.checked_add(len) {
+            Some(end) if end <= self.memory_len() => {
+                let start = OFFSET + self.s_size;
+                Ok(start + pos..start + end)
             }
+            _ => crate::err!("Memory access out of bounds"),
         }
     }
 
-    pub fn m_read<N: Num>(&self, pos: usize) -> N {
-        let start = OFFSET + self.s_size;
+    pub fn m_write<N: Num>(&mut self, pos: usize, val: &N) -> Result<()> {
+        let bytes = val.get_bytes();
+        let range = self.cells(pos, bytes.len())?;
+        self.watch(Access::Write, range.clone());
+        for (loc, byte) in range.zip(bytes) {
+            self.write(loc, byte);
+        }
+        Ok(())
+    }
 
-        match N::len() {
-            1 => N::from_bytes(&[self.read(start + pos)]),
+    pub fn m_read<N: Num>(&self, pos: usize) -> Result<N> {
+        let range = self.cells(pos, N::len())?;
+        self.watch(Access::Read, range.clone());
+        Ok(N::from_bytes(&self.as_slice()[range]))
+    }
+}
 
-            2 => N::from_bytes(&[self.read(start + pos), self.read(start + pos + 1)]),
+// HEAP
+impl Memory {
+    // Takes `len` zeroed cells (at least one) from the end of the cell memory.
+    // Returns the address of the first one, or `None` if they don't fit
+    pub fn alloc(&mut self, len: usize) -> Option<usize> {
+        let pos = self.heap.alloc(self.memory_len(), len)?;
+        let start = OFFSET + self.s_size + pos;
+        for i in start..start + len.max(1) {
+            self.write(i, 0);
+        }
+        Some(pos)
+    }
 
-            4 => N::from_bytes(&[
-                self.read(start + pos),
-                self.read(start + pos + 1),
-                self.read(start + pos + 2),
-                self.read(start + pos + 3),
-            ]),
+    pub fn free(&mut self, pos: usize) -> Result<()> {
+        self.heap.free(pos)
+    }
+}
 
-            _ => panic!("Something went wrong"),
+// HISTORY
+impl Memory {
+    // Puts back what every byte written since the log held `len` changes held
+    // before, and forgets those writes
+    pub fn undo(&mut self, len: usize) {
+        let Some(mut log) = self.log.take() else {
+            return;
+        };
+        while log.len() > len {
+            let change = log.pop().unwrap();
+            self.write_raw(change.loc, change.old);
         }
+        self.log = Some(log);
+    }
+}
+
+// WATCHES
+impl Memory {
+    // Notes down every watch that catches `access` to the bytes in `range`
+    #[inline]
+    fn watch(&self, access: Access, range: Range<usize>) {
+        if self.watches.is_empty() {
+            return;
+        }
+        for watch in &self.watches {
+            if watch.catches(self, access, &range) {
+                self.hits.borrow_mut().push(Hit {
+                    name: watch.name.clone(),
+                    access,
+                });
+            }
+        }
+    }
+
+    pub fn has_hits(&self) -> bool {
+        !self.hits.borrow().is_empty()
+    }
+
+    // The accesses caught since this was last called, oldest first
+    pub fn take_hits(&self) -> Vec<Hit> {
+        self.hits.take()
     }
 }
 
 impl fmt::Debug for Memory {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Memory")
-            .field("mem", &unsafe {
-                std::slice::from_raw_parts(self.mem, self.size)
-            })
+            .field("mem", &self.as_slice())
             .field("size", &self.size)
             .field("stack_size", &self.s_size)
             .field("stack_len", &self.s_len)
-            .finish()
+            .field("heap", &self.heap)
+            // The history's log would bury everything else
+            .finish_non_exhaustive()
     }
 }
 
@@ -284,54 +503,161 @@ impl fmt::Debug for Memory {
 mod mem_tests {
     use super::*;
 
+    #[test]
+    fn test_frames() {
+        let mut env = Memory::init(1024, 12);
+        env.s_push(&7_i16).unwrap();
+        env.enter(4).unwrap();
+        assert_eq!((env.sp(), env.r_read::<i16>(&FramePointer)), (8, 4));
+
+        env.s_write(4, &-3_i32).unwrap();
+        assert_eq!(env.s_read::<i32>(4).unwrap(), -3);
+        assert_eq!(env.s_read::<i16>(0).unwrap(), 7);
+        assert!(env.s_read::<i32>(6).is_err());
+        assert!(env.enter(3).is_err());
+
+        env.leave().unwrap();
+        assert_eq!((env.sp(), env.r_read::<i16>(&FramePointer)), (2, 0));
+        assert!(env.leave().is_err());
+    }
+
     #[test]
     fn test_stack() {
         let mut env = Memory::init(1024, 5);
-        env.s_push(&13_u8);
-        env.s_push(&128935_i32);
+        env.s_push(&13_u8).unwrap();
+        env.s_push(&128_935_i32).unwrap();
 
-        assert_eq!(env.s_pop_32(), Some(128935));
+        assert_eq!(env.s_pop_32(), Some(128_935));
 
-        env.s_push(&345_i16);
-        env.s_push(&68_u8);
-        env.s_push(&31_u8);
+        env.s_push(&345_i16).unwrap();
+        env.s_push(&68_u8).unwrap();
+        env.s_push(&31_u8).unwrap();
 
         assert_eq!(env.s_pop_8(), Some(31));
         assert_eq!(env.s_pop_8(), Some(68));
         assert_eq!(env.s_pop_16(), Some(345));
         assert_eq!(env.s_pop_8(), Some(13));
+
+        env.s_push(&1_i32).unwrap();
+        assert!(env.s_push(&1_i16).is_err());
     }
 
     #[test]
     fn test_registry() {
         let mut env = Memory::init(1024, 10);
         env.r_write(&'e', &276_i16);
-        env.r_write(&('a', 'b'), &128935_i32);
+        env.r_write(&('a', 'b'), &128_935_i32);
         env.r_write(&('c', Pos::Upper), &7_u8);
 
-        assert_eq!(env.r_read::<i32>(&('a', 'b')), 128935);
+        assert_eq!(env.r_read::<i32>(&('a', 'b')), 128_935);
         assert_eq!(env.r_read::<u8>(&('c', Pos::Upper)), 7);
         assert_eq!(env.r_read::<i16>(&'e'), 276);
     }
 
+    #[test]
+    // These values are exact in binary, so nothing gets rounded
+    #[allow(clippy::float_cmp)]
+    fn test_floats() {
+        let mut env = Memory::init(1024, 10);
+        env.f_write(FloatRegister(7), -2.5);
+        env.m_write(3, &0.125_f64).unwrap();
+
+        assert_eq!(env.f_read(FloatRegister(7)), -2.5);
+        assert_eq!(env.f_read(FloatRegister(0)), 0.0);
+        assert_eq!(env.m_read::<f64>(3).unwrap(), 0.125);
+        assert_eq!(
+            env.as_slice()[REGISTRY_OFFSET - 1],
+            (-2.5_f64).to_ne_bytes()[7]
+        );
+    }
+
     #[test]
     fn test_memory() {
         let mut env = Memory::init(1024, 0);
-        env.m_write(0, &15_u8);
-        env.m_write(5, &1056_i16);
-        env.m_write(3, &18_u8);
-        env.m_write(100, &-65412_i32);
+        env.m_write(0, &15_u8).unwrap();
+        env.m_write(5, &1056_i16).unwrap();
+        env.m_write(3, &18_u8).unwrap();
+        env.m_write(100, &-65_412_i32).unwrap();
+
+        assert_eq!(env.m_read::<i16>(5).unwrap(), 1056);
+        assert_eq!(env.m_read::<i32>(100).unwrap(), -65_412);
+        assert_eq!(env.m_read::<u8>(0).unwrap(), 15);
+        assert_eq!(env.m_read::<u8>(3).unwrap(), 18);
+
+        let len = env.memory_len();
+        assert!(env.m_write(len - 1, &1_i16).is_err());
+        assert!(env.m_read::<i32>(len).is_err());
+    }
 
-        assert_eq!(env.m_read::<i16>(5), 1056);
-        assert_eq!(env.m_read::<i32>(100), -65412);
-        assert_eq!(env.m_read::<u8>(0), 15);
-        assert_eq!(env.m_read::<u8>(3), 18);
+    #[test]
+    fn test_alloc() {
+        let mut env = Memory::init(1024, 4);
+        let len = env.memory_len();
+        env.m_write(len - 4, &-1_i32).unwrap();
+
+        let pos = env.alloc(4).unwrap();
+        assert_eq!(pos, len - 4);
+        assert_eq!(env.m_read::<i32>(pos).unwrap(), 0);
+        assert_eq!(env.alloc(len), None);
+        env.free(pos).unwrap();
+        assert!(!env.heap.in_use());
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut env = Memory::init(1024, 4);
+        env.log = Some(Vec::new());
+        env.m_write(0, &5_u8).unwrap();
+        env.s_push(&300_i16).unwrap();
+        let len = env.log.as_ref().unwrap().len();
+        env.m_write(0, &6_u8).unwrap();
+        env.s_pop_16();
+        env.r_write(&'a', &-1_i16);
+
+        env.undo(len);
+        assert_eq!(env.m_read::<u8>(0).unwrap(), 5);
+        assert_eq!(env.read(OFFSET), 44);
+        assert_eq!(env.r_read::<i16>(&'a'), 0);
+        env.undo(0);
+        assert_eq!(env.m_read::<u8>(0).unwrap(), 0);
+        assert!(env.log.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut env = Memory::init(1024, 4);
+        env.watches = vec![
+            Watch::parse("#[1..3]", false, true).unwrap(),
+            Watch::parse("al", true, true).unwrap(),
+            Watch::parse("stack", true, false).unwrap(),
+        ];
+        let names = |env: &Memory| -> Vec<String> {
+            env.take_hits()
+                .into_iter()
+                .map(|hit| format!("{} {}", hit.name, hit.access))
+                .collect()
+        };
+
+        env.m_write(0, &1_u8).unwrap();
+        env.m_read::<i16>(1).unwrap();
+        env.r_read::<u8>(&('a', Pos::Upper));
+        assert!(!env.has_hits());
+
+        env.m_write(2, &1_i16).unwrap();
+        env.r_write(&'a', &1_i16);
+        env.s_push(&1_u8).unwrap();
+        env.s_pop_8();
+        assert_eq!(
+            names(&env),
+            ["#[1..3] written to", "al written to", "stack read"]
+        );
+        assert!(!env.has_hits());
     }
 
     #[test]
     fn test_together() {
         let mut env = Memory::init(1024, 5);
-        env.s_push(&13_u8);
+        env.s_push(&13_u8).unwrap();
         env.r_write(&'e', &1342_i16);
         assert_eq!(env.s_pop_8(), Some(13));
         assert_eq!(env.r_read::<i16>(&'e'), 1342);
@@ -339,7 +665,7 @@ mod mem_tests {
 
     #[test]
     fn test_flags() {
-        let mut env = Memory::init(20, 0);
+        let mut env = Memory::init(OFFSET, 0);
         env.flag_write_cmp();
 
         assert!(env.flag_read_cmp());
@@ -1,8 +1,14 @@
 pub mod consts;
-pub mod iter;
+pub mod error;
+pub mod files;
+pub mod heap;
+pub mod history;
+pub mod input;
 pub mod mem;
+pub mod output;
 pub mod token;
 pub mod traits;
+pub mod watch;
 
 #[cfg(feature = "inline-python")]
 use {
@@ -18,6 +24,18 @@ macro_rules! bx {
     };
 }
 
+// Shorthand for returning an `Error` built from a format string, optionally
+// located with `err!(at span; ...)`
+#[macro_export]
+macro_rules! err {
+    (at $span:expr; $($arg:tt)*) => {
+        Err($crate::utils::error::Error::new(format!($($arg)*)).at($span))
+    };
+    ($($arg:tt)*) => {
+        Err($crate::utils::error::Error::new(format!($($arg)*)))
+    };
+}
+
 #[cfg(feature = "inline-python")]
 pub struct PyGuard {
     guard: GILGuard,
@@ -32,7 +50,11 @@ impl PyGuard {
     }
 
     #[inline]
-    pub fn run_python(&self, env: &crate::env::Environment, code: &str) -> (Option<Vec<u8>>, i32) {
+    pub fn run_python(
+        &self,
+        env: &crate::env::Environment,
+        code: &str,
+    ) -> error::Result<(Option<Vec<u8>>, i32)> {
         let mut code = code.to_string();
         let py = self.guard.python();
 
@@ -43,17 +65,30 @@ impl PyGuard {
         };
 
         if REGISTER_REGEX.is_match(&code) {
+            // The replacement can't fail, so the first register that can't be
+            // read is kept and returned afterwards
+            let mut failed = None;
             // We can pass an empty slice for the AST as we know this will always be simply a register
             code = REGISTER_REGEX
-                .replace_all(&code, |caps: &regex::Captures<'_>| {
-                    crate::parser::to_numeric::<i32>(
+                .replace_all(
+                    &code,
+                    |caps: &regex::Captures<'_>| match crate::parser::to_numeric::<i32>(
                         &mut env.shallow_copy(),
                         &[],
-                        &Op::Register(format!("{}", &caps[1])),
-                    )
-                    .to_string()
-                })
+                        &Op::Register(caps[1].to_string()),
+                    ) {
+                        Ok(val) => val.to_string(),
+                        Err(e) => {
+                            failed.get_or_insert(e);
+                            String::new()
+                        }
+                    },
+                )
                 .to_string();
+
+            if let Some(e) = failed {
+                return Err(e);
+            }
         }
 
         let dict = [("stk", old_stk)].into_py_dict(py);
@@ -71,7 +106,7 @@ impl PyGuard {
 
         // Return new stack and return value of inline code
         let new_stk = dict.get_item("stk").unwrap().extract::<Vec<u8>>().unwrap();
-        (
+        Ok((
             if old_stk.iter().copied().collect::<Vec<u8>>() == new_stk {
                 None
             } else {
@@ -81,6 +116,6 @@ impl PyGuard {
                 .expect("Could not run python code")
                 .extract()
                 .unwrap_or_default(),
-        )
+        ))
     }
 }
@@ -0,0 +1,90 @@
+use std::cell::RefCell;
+use std::io::{self, BufWriter, Write};
+use std::rc::Rc;
+
+// Where `out`, `chr` and the other printing commands, or `err` and `errc`, write to. Anything that
+// implements `Write` can be used. Writes are buffered, and only guaranteed to
+// show up once the output is flushed, which happens when the program halts or
+// fails, and before it waits on input
+pub trait Output: Write {}
+
+impl<W: Write> Output for W {}
+
+// The process' stdout, buffered so that printing a character at a time doesn't
+// lock and write to stdout every time
+pub fn stdout() -> Box<dyn Output> {
+    Box::new(BufWriter::new(io::stdout()))
+}
+
+// The process' stderr, which is left unbuffered so diagnostics show up right away
+pub fn stderr() -> Box<dyn Output> {
+    Box::new(io::stderr())
+}
+
+// Keeps everything written to it in memory. Clones share the same buffer, so
+// the output can still be read once an `Environment` owns one of them
+#[derive(Clone, Debug, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    // The contents as text, with invalid UTF-8 replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod output_tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let capture = Capture::default();
+        let mut out: Box<dyn Output> = Box::new(capture.clone());
+
+        let val = 1;
+        write!(out, "{val} a").unwrap();
+        out.write_all(&[0xff]).unwrap();
+        assert_eq!(capture.contents(), b"1 a\xff");
+        assert_eq!(capture.text(), "1 a\u{fffd}");
+    }
+
+    // Runs `program` with its output going through a buffer that is only
+    // written to `Capture` when flushed
+    fn run_buffered(program: &str) -> (crate::Result<i32>, String) {
+        let capture = Capture::default();
+        let mut env = crate::Environment::new();
+        env.out = Box::new(BufWriter::new(capture.clone()));
+        let res = crate::run(program, &mut env);
+        (res, capture.text())
+    }
+
+    #[test]
+    fn test_flushed_on_hlt() {
+        let (res, out) = run_buffered("out 1\nhlt 3\nout 2");
+        assert_eq!(res, Ok(3));
+        assert_eq!(out, "1");
+    }
+
+    #[test]
+    fn test_flushed_on_error() {
+        let (res, out) = run_buffered("chr 'a\njmp :missing");
+        assert!(res.is_err());
+        assert_eq!(out, "a");
+    }
+}
@@ -1,9 +1,24 @@
+// Where a node starts in the source. Both fields are 1-indexed
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Node {
     Keyword(String),
 
     Numeric(i32),
 
+    Float(f64),
+
     String(String),
 
     Branch(String),
@@ -14,6 +29,9 @@ pub enum Node {
 
     Char(char),
 
+    // Everything after a `;` up to the end of the line. Ignored by the AST
+    Comment(String),
+
     #[cfg(feature = "inline-python")]
     InlinePy(String),
 }
@@ -24,7 +42,7 @@ pub enum Op {
     // TODO: Implement binary operators
     BinOp(char, Box<Op>, Box<Op>),
 
-    Cmd(String, Vec<Op>),
+    Cmd(String, Vec<Op>, Span),
 
     Branch(String, Vec<Op>),
 
@@ -36,6 +54,8 @@ pub enum Op {
 
     Numeric(i32),
 
+    Float(f64),
+
     String(String),
 
     Char(char),
@@ -48,3 +68,14 @@ pub enum Op {
 
     Empty,
 }
+
+// How a float is written as a literal, which always has a point so it lexes
+// back to the same float, not an integer
+pub fn float_text(val: f64) -> String {
+    let res = val.to_string();
+    if res.contains('.') || !val.is_finite() {
+        res
+    } else {
+        res + ".0"
+    }
+}
@@ -2,6 +2,9 @@
 // the parser and memory ops. Almost completely inlined because the functions
 // are so small, this will greatly improve performance
 
+use super::consts::{FLOAT_REGISTERS, FLOAT_REGISTER_COUNT, FRAME_POINTER, REGISTER_NAMES};
+use super::token::{float_text, Op};
+
 pub trait Num: num_traits::Num + num_traits::NumCast {
     fn get_bytes(&self) -> Vec<u8>;
 
@@ -110,45 +113,162 @@ impl Num for usize {
     }
 }
 
-type Location = (Option<usize>, Option<usize>, Option<Pos>);
+impl Num for f64 {
+    #[inline]
+    fn get_bytes(&self) -> Vec<u8> {
+        self.to_ne_bytes().into()
+    }
 
-pub trait Position: std::fmt::Debug {
-    fn len(&self) -> usize;
+    #[inline]
+    fn from_bytes(bytes: &[u8]) -> Self
+    where
+        Self: Sized,
+    {
+        f64::from_ne_bytes([
+            bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+        ])
+    }
 
+    #[inline]
+    fn len() -> usize
+    where
+        Self: Sized,
+    {
+        8
+    }
+}
+
+// Where the bytes of a register are in memory
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Location {
+    // A 16-bit register
+    Wide(usize),
+    // One half of a 16-bit register
+    Half(usize, Pos),
+    // Two 16-bit registers joined together, the lower one first
+    Pair(usize, usize),
+}
+
+pub trait Position: std::fmt::Debug {
     fn indexes(&self) -> Location;
 }
 
 impl Position for char {
-    fn len(&self) -> usize {
-        1
+    fn indexes(&self) -> Location {
+        Location::Wide(REGISTER_NAMES.find(*self).unwrap() * 2)
     }
+}
 
+impl Position for (char, Pos) {
     fn indexes(&self) -> Location {
-        (Some("abcde".find(*self).unwrap() * 2), None, None)
+        Location::Half(REGISTER_NAMES.find(self.0).unwrap() * 2, self.1)
     }
 }
 
-impl Position for (char, Pos) {
-    fn len(&self) -> usize {
-        1
+impl Position for (char, char) {
+    fn indexes(&self) -> Location {
+        Location::Pair(
+            REGISTER_NAMES.find(self.0).unwrap() * 2,
+            REGISTER_NAMES.find(self.1).unwrap() * 2,
+        )
     }
+}
+
+// The frame pointer, `fp`
+#[derive(Debug)]
+pub struct FramePointer;
 
+impl Position for FramePointer {
     fn indexes(&self) -> Location {
-        (Some("abcde".find(self.0).unwrap() * 2), None, Some(self.1))
+        Location::Wide(FRAME_POINTER)
     }
 }
 
-impl Position for (char, char) {
-    fn len(&self) -> usize {
-        2
+// One of the float registers, `f0` to `f7`, by its number. They are kept
+// outside of the registry `Position` covers, since they are 8 bytes wide
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct FloatRegister(pub usize);
+
+impl FloatRegister {
+    pub fn parse(name: &str) -> Option<Self> {
+        let num = name.strip_prefix(['f', 'F'])?;
+        match num.parse() {
+            Ok(ind) if ind < FLOAT_REGISTER_COUNT && num.len() == 1 => Some(Self(ind)),
+            _ => None,
+        }
     }
 
+    // Where its first byte is in memory
+    pub fn offset(self) -> usize {
+        FLOAT_REGISTERS + self.0 * 8
+    }
+}
+
+// The register a name refers to, as offsets into the register file
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Reg {
+    Low(usize),
+    High(usize),
+    Wide(usize),
+    Pair(usize, usize),
+    // The stack pointer, which is the length of the stack
+    Stack,
+}
+
+impl Position for Reg {
     fn indexes(&self) -> Location {
-        (
-            Some("abcde".find(self.0).unwrap() * 2),
-            Some("abcde".find(self.1).unwrap() * 2),
-            None,
-        )
+        match *self {
+            Reg::Low(a) => Location::Half(a, Pos::Lower),
+            Reg::High(a) => Location::Half(a - 1, Pos::Upper),
+            Reg::Wide(a) => Location::Wide(a),
+            Reg::Pair(a, b) => Location::Pair(a, b),
+            Reg::Stack => unreachable!("the stack pointer isn't kept in the registry"),
+        }
+    }
+}
+
+fn reg_offset(chr: char) -> Option<usize> {
+    REGISTER_NAMES.find(chr).map(|ind| ind * 2)
+}
+
+// Decodes a register name, as the lexer accepts it. Float registers aren't
+// part of the registry, see `float_register`
+pub(crate) fn register(name: &str) -> Option<Reg> {
+    match name {
+        "sp" => return Some(Reg::Stack),
+        "fp" => return Some(Reg::Wide(FRAME_POINTER)),
+        _ => {}
+    }
+
+    let chrs: Vec<char> = name.chars().collect();
+    Some(match chrs[..] {
+        [r, 'x'] => Reg::Wide(reg_offset(r)?),
+        [r, 'h'] => Reg::High(reg_offset(r)? + 1),
+        [r, 'l'] => Reg::Low(reg_offset(r)?),
+        [r1, r2, 'x'] => Reg::Pair(reg_offset(r1)?, reg_offset(r2)?),
+        _ => return None,
+    })
+}
+
+// The float register `op` is, if it is one
+pub(crate) fn float_register(op: &Op) -> Option<FloatRegister> {
+    match op {
+        Op::Register(name) => FloatRegister::parse(name),
+        _ => None,
+    }
+}
+
+// Why `op` can't be read as an integer, if it is a float
+pub(crate) fn not_integer(op: &Op) -> Option<String> {
+    match op {
+        Op::Register(name) if FloatRegister::parse(name).is_some() => Some(format!(
+            "Expected an integer, but {name} is a float register"
+        )),
+        Op::Float(val) => Some(format!(
+            "Expected an integer, but {} is a float",
+            float_text(*val)
+        )),
+        _ => None,
     }
 }
 
@@ -0,0 +1,183 @@
+use std::fmt;
+use std::ops::Range;
+
+use super::consts::{OFFSET, REGISTRY_OFFSET};
+use super::mem::Memory;
+use super::traits::{register, FloatRegister, Reg};
+
+// Something in memory that can be looked at and watched: a register, the
+// conditional flag, the stack, or cells of memory, either by their
+// identifier and address or as a range of addresses
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Register(String),
+    Flag,
+    Stack,
+    Memory(char, usize),
+    Cells(Range<usize>),
+}
+
+impl Target {
+    // Parses a target as it is written in a program, like `ax`, `abx`, `fp`,
+    // `f0`, `#[3]` or `#[3..10]`, or as `flag` or `stack`
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.to_lowercase();
+        match text.as_str() {
+            "flag" => return Some(Self::Flag),
+            "stack" => return Some(Self::Stack),
+            _ => {}
+        }
+
+        let mut chrs = text.chars();
+        let ident = chrs.next()?;
+        if matches!(ident, '#' | '$' | '@') {
+            let pos = chrs.as_str().strip_prefix('[')?.strip_suffix(']')?;
+            return match pos.split_once("..") {
+                Some((start, end)) if ident == '#' => {
+                    let range = start.parse().ok()?..end.parse().ok()?;
+                    (!range.is_empty()).then_some(Self::Cells(range))
+                }
+                Some(_) => None,
+                None => Some(Self::Memory(ident, pos.parse().ok()?)),
+            };
+        }
+
+        let valid = register(&text).is_some() || FloatRegister::parse(&text).is_some();
+        valid.then_some(Self::Register(text))
+    }
+
+    // Where the bytes of the target are in `mem`. Cells move along with the
+    // end of the stack, so this can change as the program runs. The stack
+    // pointer isn't kept in memory, so it has none
+    pub fn locations(&self, mem: &Memory) -> Vec<Range<usize>> {
+        let cells = OFFSET + mem.s_size;
+        let (start, len) = match self {
+            Self::Register(name) => match (register(name), FloatRegister::parse(name)) {
+                (Some(Reg::Low(a) | Reg::High(a)), _) => (a, 1),
+                (Some(Reg::Wide(a)), _) => (a, 2),
+                (Some(Reg::Pair(a, b)), _) => return vec![a..a + 2, b..b + 2],
+                (_, Some(reg)) => (reg.offset(), 8),
+                (Some(Reg::Stack), _) | (None, None) => return Vec::new(),
+            },
+            Self::Flag => (REGISTRY_OFFSET, 1),
+            Self::Stack => (OFFSET, mem.s_size),
+            Self::Memory(ident, pos) => match ident {
+                '#' => (cells + pos, 1),
+                '$' => (cells + pos, 2),
+                _ => (cells + pos, 4),
+            },
+            Self::Cells(range) => (cells + range.start, range.len()),
+        };
+        let range = start..start + len;
+        vec![range]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Read => "read",
+            Self::Write => "written to",
+        })
+    }
+}
+
+// A target to report reads from or writes to, by the name it was given as
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watch {
+    pub name: String,
+    pub target: Target,
+    pub reads: bool,
+    pub writes: bool,
+}
+
+impl Watch {
+    // Watches what `text` refers to, unless it is the stack pointer, which
+    // changes with every push and pop. Watch `stack` for those instead
+    pub fn parse(text: &str, reads: bool, writes: bool) -> Option<Self> {
+        let target = Target::parse(text)?;
+        if target == Target::Register("sp".to_owned()) {
+            return None;
+        }
+        Some(Self {
+            name: text.to_lowercase(),
+            target,
+            reads,
+            writes,
+        })
+    }
+
+    // Whether `access` to the bytes in `range` of `mem` is reported
+    pub fn catches(&self, mem: &Memory, access: Access, range: &Range<usize>) -> bool {
+        let wanted = match access {
+            Access::Read => self.reads,
+            Access::Write => self.writes,
+        };
+        wanted
+            && self
+                .target
+                .locations(mem)
+                .iter()
+                .any(|loc| loc.start < range.end && range.start < loc.end)
+    }
+}
+
+// A watched target that was accessed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub name: String,
+    pub access: Access,
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use super::*;
+    use crate::utils::consts::FRAME_POINTER;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Target::parse("AH"), Some(Target::Register("ah".into())));
+        assert_eq!(Target::parse("flag"), Some(Target::Flag));
+        assert_eq!(Target::parse("$[4]"), Some(Target::Memory('$', 4)));
+        assert_eq!(Target::parse("#[2..5]"), Some(Target::Cells(2..5)));
+        assert!(Target::parse("#[5..2]").is_none());
+        assert!(Target::parse("@[2..5]").is_none());
+        assert_eq!(Target::parse("hx"), Some(Target::Register("hx".into())));
+        assert!(Target::parse("ix").is_none());
+        assert!(Target::parse("abcx").is_none());
+        assert!(Target::parse("a").is_none());
+        assert!(Target::parse("ix").is_none());
+        assert_eq!(Target::parse("f7"), Some(Target::Register("f7".into())));
+        assert!(Target::parse("f8").is_none());
+        assert!(Target::parse("#10").is_none());
+    }
+
+    #[test]
+    fn test_catches() {
+        let mem = Memory::init(OFFSET + 100, 10);
+        let watch = Watch::parse("#[2..5]", false, true).unwrap();
+        let cells = OFFSET + 10;
+        assert!(watch.catches(&mem, Access::Write, &(cells + 4..cells + 6)));
+        assert!(!watch.catches(&mem, Access::Write, &(cells + 5..cells + 6)));
+        assert!(!watch.catches(&mem, Access::Read, &(cells + 2..cells + 3)));
+
+        let watch = Watch::parse("abx", true, false).unwrap();
+        assert!(watch.catches(&mem, Access::Read, &(3..4)));
+        assert!(!watch.catches(&mem, Access::Read, &(4..6)));
+
+        let watch = Watch::parse("fp", true, true).unwrap();
+        assert!(watch.catches(&mem, Access::Write, &(FRAME_POINTER..FRAME_POINTER + 1)));
+        assert!(Watch::parse("sp", true, true).is_none());
+
+        let watch = Watch::parse("f1", true, true).unwrap();
+        let f1 = FloatRegister(1).offset();
+        assert!(watch.catches(&mem, Access::Read, &(f1 + 7..f1 + 8)));
+        assert!(!watch.catches(&mem, Access::Read, &(f1 + 8..f1 + 9)));
+    }
+}
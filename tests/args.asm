@@ -0,0 +1,22 @@
+; Prints its arguments, one per line, and then looks up two environment
+; variables, of which only the first one was allowed
+out argc
+chr 10
+mov ax 0
+.loop
+    cge ax argc
+    cjm :done
+    argv ax 100 50
+    prs 100 0
+    chr 10
+    inc ax
+    jmp :loop
+..done
+    str "ARSM_ALLOWED" 0
+    out env 0 100 50
+    chr 32
+    prs 100 0
+    chr 10
+    str "ARSM_SECRET" 0
+    out env 0 100 50
+    chr 10
@@ -0,0 +1,807 @@
+// Runs every `.asm` program in `tests/std_cases` (and `tests/python_cases`
+// when the `inline-python` feature is enabled) through the library and checks
+// it against the files sharing its name:
+//   N.in   - input for the program (optional)
+//   N.out  - expected output (optional, defaults to nothing)
+//   N.stderr - expected output on stderr (optional, defaults to nothing)
+//   N.code - expected exit code (optional, defaults to 0)
+//   N.err  - expected error message (optional, the program must fail with it)
+// Every case is run twice, once from source and once compiled to a binary
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use arsm::binary::Program;
+use arsm::optimizer::{self, Rule};
+use arsm::utils::{input::Input, output::Capture};
+use arsm::Environment;
+
+fn read_optional(path: &Path, ext: &str) -> Option<String> {
+    fs::read_to_string(path.with_extension(ext)).ok()
+}
+
+// Whether the stderr of a process running `case` holds what the program was
+// expected to print there, followed by nothing but the error it failed with
+fn stderr_matches(case: &Path, got: &str) -> bool {
+    let expected = read_optional(case, "stderr").unwrap_or_default();
+    got.strip_prefix(&expected).is_some_and(|rest| {
+        rest.is_empty() || (case.with_extension("err").exists() && rest.starts_with("Error: "))
+    })
+}
+
+// A minimal line diff, good enough to spot where the output went wrong
+fn diff(expected: &str, got: &str) -> String {
+    let expected: Vec<&str> = expected.split('\n').collect();
+    let got: Vec<&str> = got.split('\n').collect();
+    let mut res = String::new();
+
+    for i in 0..expected.len().max(got.len()) {
+        match (expected.get(i), got.get(i)) {
+            (Some(e), Some(g)) if e == g => res.push_str(&format!("    {e:?}\n")),
+            (e, g) => {
+                if let Some(e) = e {
+                    res.push_str(&format!("  - {e:?}\n"));
+                }
+                if let Some(g) = g {
+                    res.push_str(&format!("  + {g:?}\n"));
+                }
+            }
+        }
+    }
+
+    res
+}
+
+// Returns a description of every way the case failed, or `None` if it passed
+fn run_case(path: &Path, compiled: bool) -> Option<String> {
+    let program = fs::read_to_string(path).expect("Could not read test program");
+    let input = read_optional(path, "in").unwrap_or_default().into_bytes();
+    let expected_out = read_optional(path, "out").unwrap_or_default();
+    let expected_stderr = read_optional(path, "stderr").unwrap_or_default();
+    let expected_err = read_optional(path, "err").map(|e| e.trim().to_owned());
+    let expected_code: i32 = read_optional(path, "code").map_or(0, |c| {
+        c.trim()
+            .parse()
+            .expect("`.code` files must hold a single integer")
+    });
+
+    let (out, err) = (Capture::default(), Capture::default());
+    let mut env = Environment::new();
+    env.stdin = Input::from(input);
+    env.out = Box::new(out.clone());
+    env.err = Box::new(err.clone());
+    let res = if compiled {
+        arsm::compile(&program)
+            .and_then(|tree| Program::from_tree(&tree, true))
+            .and_then(|program| arsm::load(&program.to_bytes()))
+            .and_then(|tree| arsm::parser::parse(&tree, &mut env))
+    } else {
+        arsm::run(&program, &mut env)
+    };
+    let got_out = out.text();
+    let got_stderr = err.text();
+
+    let mut failures = Vec::new();
+    if got_out != expected_out {
+        failures.push(format!(
+            "output differs:\n{}",
+            diff(&expected_out, &got_out)
+        ));
+    }
+    if got_stderr != expected_stderr {
+        failures.push(format!(
+            "stderr differs:\n{}",
+            diff(&expected_stderr, &got_stderr)
+        ));
+    }
+
+    match (res, expected_err) {
+        (Ok(code), None) if code != expected_code => {
+            failures.push(format!("expected exit code {expected_code}, got {code}"));
+        }
+        (Ok(_), Some(e)) => failures.push(format!("expected error {e:?}, but the program ran")),
+        (Err(e), None) => failures.push(format!("unexpected error: {e}")),
+        (Err(e), Some(expected)) if e.msg != expected => {
+            failures.push(format!("expected error {expected:?}, got {:?}", e.msg));
+        }
+        _ => {}
+    }
+
+    if failures.is_empty() {
+        None
+    } else {
+        Some(failures.join("\n"))
+    }
+}
+
+// Every `.asm` program in `tests/<dir>`, sorted by name
+fn cases(dir: &str) -> Vec<PathBuf> {
+    let dir: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", dir].iter().collect();
+    let mut cases: Vec<PathBuf> = fs::read_dir(&dir)
+        .expect("Could not read test directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "asm"))
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "No cases found in {}", dir.display());
+    cases
+}
+
+// Runs `check` on every case, which returns a description of how the case
+// failed, or `None` if it passed, and reports on each of them
+fn check_cases(cases: &[PathBuf], mut check: impl FnMut(&Path) -> Option<String>) {
+    let mut failed = 0;
+    for case in cases {
+        let name = case.file_stem().unwrap().to_string_lossy();
+        if let Some(failure) = check(case) {
+            failed += 1;
+            println!("Case {name}: \x1b[31mFailed\x1b[0m\n{failure}");
+        } else {
+            println!("Case {name}: \x1b[32mPassed\x1b[0m");
+        }
+    }
+
+    assert!(failed == 0, "{failed} of {} cases failed", cases.len());
+}
+
+fn run_dir(dir: &str) {
+    check_cases(&cases(dir), |case| {
+        let failures: Vec<String> = vec![
+            run_case(case, false),
+            run_case(case, true).map(|failure| format!("compiled, {failure}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        (!failures.is_empty()).then(|| failures.join("\n"))
+    });
+}
+
+// Checks how a process running `case` went, returning a description of how it
+// failed, if it did
+fn check_process(case: &Path, res: &Output) -> Option<String> {
+    let expected_out = read_optional(case, "out").unwrap_or_default();
+    let expected_code = if case.with_extension("err").exists() {
+        1
+    } else {
+        read_optional(case, "code").map_or(0, |c| c.trim().parse().unwrap())
+    };
+    let got_out = String::from_utf8_lossy(&res.stdout);
+    let got_stderr = String::from_utf8_lossy(&res.stderr);
+    if got_out != expected_out
+        || res.status.code() != Some(expected_code)
+        || !stderr_matches(case, &got_stderr)
+    {
+        Some(format!(
+            "expected exit code {expected_code}, got {:?}\n{}stderr: {got_stderr:?}",
+            res.status.code(),
+            diff(&expected_out, &got_out)
+        ))
+    } else {
+        None
+    }
+}
+
+#[test]
+fn std_cases() {
+    run_dir("std_cases");
+}
+
+// Runs `program`, optimized with `rules` if there are any, returning its
+// output, how it ended and the final memory
+fn run_optimized(program: &str, input: &[u8], rules: Option<&[Rule]>) -> (String, String, String) {
+    let out = Capture::default();
+    let mut env = Environment::new();
+    env.stdin = Input::from(input.to_vec());
+    env.out = Box::new(out.clone());
+    // What goes to stderr is checked along with the rest of the output
+    env.err = Box::new(out.clone());
+
+    let res = arsm::compile(program).and_then(|tree| match rules {
+        Some(rules) => arsm::parser::parse(&optimizer::optimize(&tree, rules), &mut env),
+        None => arsm::parser::parse(&tree, &mut env),
+    });
+    let got_out = out.text();
+    (got_out, format!("{res:?}"), format!("{:?}", env.mem))
+}
+
+// Runs every case optimized, with all of the rules and with each one on its
+// own, and checks that it behaves exactly like it does unoptimized
+#[test]
+fn optimized_cases() {
+    let cases = cases("std_cases");
+
+    let mut rule_sets = vec![Rule::ALL.to_vec()];
+    rule_sets.extend(Rule::ALL.iter().map(|&rule| vec![rule]));
+
+    let mut changed = 0;
+    check_cases(&cases, |case| {
+        let program = fs::read_to_string(case).unwrap();
+        let input = fs::read(case.with_extension("in")).unwrap_or_default();
+        let expected = run_optimized(&program, &input, None);
+
+        if let Ok(tree) = arsm::compile(&program) {
+            if optimizer::optimize(&tree, Rule::ALL) != tree {
+                changed += 1;
+            }
+        }
+
+        let mut failures = Vec::new();
+        for rules in &rule_sets {
+            let names: Vec<&str> = rules.iter().map(|rule| rule.name()).collect();
+            let (got_out, got_res, got_mem) = run_optimized(&program, &input, Some(rules));
+            if got_out != expected.0 {
+                failures.push(format!(
+                    "output differs with {names:?}:\n{}",
+                    diff(&expected.0, &got_out)
+                ));
+            }
+            if got_res != expected.1 {
+                failures.push(format!(
+                    "expected {} with {names:?}, got {got_res}",
+                    expected.1
+                ));
+            }
+            if got_mem != expected.2 {
+                failures.push(format!(
+                    "memory differs with {names:?}:\n  - {}\n  + {got_mem}",
+                    expected.2
+                ));
+            }
+        }
+        (!failures.is_empty()).then(|| failures.join("\n"))
+    });
+
+    assert!(changed > 0, "No case was changed by the optimizer");
+}
+
+// Runs every case with the `arsm` binary, piping its input into the process'
+// stdin, and checks that it behaves the same
+#[cfg(not(feature = "literal-code"))]
+#[test]
+fn cli_cases() {
+    check_cases(&cases("std_cases"), |case| {
+        let input = fs::read(case.with_extension("in")).unwrap_or_default();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_arsm"))
+            .arg(case)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Programs may stop before reading all of their input
+        let _ = child.stdin.take().unwrap().write_all(&input);
+        check_process(case, &child.wait_with_output().unwrap())
+    });
+}
+
+// Copies a file with the `arsm` binary, which may only touch the directory
+// it is allowed into
+#[cfg(not(feature = "literal-code"))]
+#[test]
+fn allowed_files() {
+    let dir = std::env::temp_dir().join(format!("arsm-allowed-files-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("in.txt"), "hello").unwrap();
+
+    let program = dir.join("copy.asm");
+    fs::write(
+        &program,
+        format!(
+            "str \"{0}/in.txt\" 0\nmov ax fop 0 0\nout frd ax 200 10\nstr \"{0}/out.txt\" 0\nmov bx fop 0 1\nout fwr bx 200 5\nout fcl bx\n",
+            dir.display()
+        ),
+    )
+    .unwrap();
+    let run = |args: &[&std::ffi::OsStr]| {
+        let res = Command::new(env!("CARGO_BIN_EXE_arsm"))
+            .arg(&program)
+            .args(args)
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&res.stdout).into_owned()
+    };
+
+    assert_eq!(run(&[]), "-1-1-1");
+    assert!(!dir.join("out.txt").exists());
+
+    assert_eq!(run(&["--allow-dir".as_ref(), dir.as_os_str()]), "550");
+    assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), "hello");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// Runs programs named like subcommands, or like the start of one, which clap
+// would otherwise take for a (misspelt) subcommand
+#[cfg(not(feature = "literal-code"))]
+#[test]
+fn subcommand_names() {
+    let dir = std::env::temp_dir().join(format!("arsm-subcommand-names-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    for name in ["t", "b", "ch", "fm", "tes", "tests.asm"] {
+        fs::write(dir.join(name), "out 7").unwrap();
+        let res = Command::new(env!("CARGO_BIN_EXE_arsm"))
+            .current_dir(&dir)
+            .arg(name)
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&res.stdout), "7", "{name}");
+    }
+
+    // Subcommands are still found by their full name
+    let res = Command::new(env!("CARGO_BIN_EXE_arsm"))
+        .current_dir(&dir)
+        .args(["check", "ch"])
+        .output()
+        .unwrap();
+    assert!(res.status.success());
+    assert!(res.stdout.is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// Passes arguments and environment variables to a program run by the `arsm`
+// binary, which may only look up the variables it was allowed to
+#[cfg(not(feature = "literal-code"))]
+#[test]
+fn program_args() {
+    let program: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "args.asm"]
+        .iter()
+        .collect();
+    let res = Command::new(env!("CARGO_BIN_EXE_arsm"))
+        .arg(&program)
+        .args([
+            "--allow-env",
+            "ARSM_ALLOWED",
+            "--",
+            "one",
+            "two words",
+            "-u",
+        ])
+        .env("ARSM_ALLOWED", "yes")
+        .env("ARSM_SECRET", "no")
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8_lossy(&res.stdout),
+        format!("4\n{}\none\ntwo words\n-u\n3 yes\n-1\n", program.display())
+    );
+}
+
+// Pauses a program run by the `arsm` binary partway through, resumes it from
+// its snapshot, and replays the input of a run that was recorded
+#[cfg(not(feature = "literal-code"))]
+#[test]
+fn snapshot_and_replay() {
+    let dir = std::env::temp_dir().join(format!("arsm-snapshot-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let program: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "std_cases", "cat.asm"]
+        .iter()
+        .collect();
+    let run = |args: &[&std::ffi::OsStr], input: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_arsm"))
+            .arg(&program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Replayed runs don't read it, and may be done before it is written
+        let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+        let res = child.wait_with_output().unwrap();
+        assert!(res.status.success());
+        String::from_utf8_lossy(&res.stdout).into_owned()
+    };
+
+    let snapshot = dir.join("snapshot.json");
+    let first = run(
+        &[
+            "--steps".as_ref(),
+            "20".as_ref(),
+            "--snapshot".as_ref(),
+            snapshot.as_os_str(),
+        ],
+        "hello world",
+    );
+    assert_eq!(first, "hell");
+    let rest = run(&["--resume".as_ref(), snapshot.as_os_str()], "hello world");
+    assert_eq!(rest, "o world");
+
+    let log = dir.join("input.log");
+    assert_eq!(run(&["--record".as_ref(), log.as_os_str()], "xyz"), "xyz");
+    assert_eq!(fs::read_to_string(&log).unwrap(), "xyz");
+    assert_eq!(run(&["--replay".as_ref(), log.as_os_str()], "other"), "xyz");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// Steps through a program run by the `arsm` binary in the debugger, and back
+#[cfg(not(feature = "literal-code"))]
+#[test]
+fn interactive() {
+    let program: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "std_cases", "cat.asm"]
+        .iter()
+        .collect();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_arsm"))
+        .arg(&program)
+        .args(["-i", "-u", "hi"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"c\nback 100\nq\n")
+        .unwrap();
+    let res = child.wait_with_output().unwrap();
+    assert!(res.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&res.stdout),
+        "#1 2:3: mov eh in\n> hiThe program stopped with exit code 0\n> #1 2:3: mov eh in\n> \n"
+    );
+}
+
+// Reports the reads and writes being watched in a program run by the `arsm`
+// binary
+#[cfg(not(feature = "literal-code"))]
+#[test]
+fn watches() {
+    let program: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "std_cases", "cat.asm"]
+        .iter()
+        .collect();
+    let res = Command::new(env!("CARGO_BIN_EXE_arsm"))
+        .arg(&program)
+        .args(["-u", "hi", "--watch", "eh", "--rwatch", "flag"])
+        .output()
+        .unwrap();
+    assert!(res.status.success());
+    assert_eq!(String::from_utf8_lossy(&res.stdout), "hi");
+    let log = String::from_utf8_lossy(&res.stderr);
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], "Watch: eh was written to by #1 at 2:3");
+    assert_eq!(lines[5], "Watch: flag was read by #13 at 4:3");
+}
+
+// Translates every case to C, compiles it with the system's C compiler and
+// checks that it behaves the same. Skipped if there is no C compiler
+#[test]
+fn c_cases() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        // Written to stderr directly, since the test harness hides what passing
+        // tests print
+        let _ = writeln!(
+            std::io::stderr(),
+            "c_cases: skipped, no C compiler (`cc`) was found"
+        );
+        return;
+    }
+
+    let build = std::env::temp_dir().join(format!("arsm-c-cases-{}", std::process::id()));
+    fs::create_dir_all(&build).unwrap();
+
+    check_cases(&cases("std_cases"), |case| {
+        let name = case.file_stem().unwrap().to_string_lossy();
+        let program = fs::read_to_string(case).unwrap();
+        let Ok(tree) = arsm::compile(&program) else {
+            // Programs that don't build can't be translated either
+            assert!(
+                case.with_extension("err").exists(),
+                "{} doesn't build",
+                name
+            );
+            return None;
+        };
+
+        let source = build.join(format!("{name}.c"));
+        let exe = build.join(&*name);
+        fs::write(&source, arsm::emit_c::emit(&tree).unwrap()).unwrap();
+        let status = Command::new("cc")
+            .arg("-o")
+            .arg(&exe)
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success(), "Could not compile the C for {}", name);
+
+        let input = fs::read(case.with_extension("in")).unwrap_or_default();
+        let mut child = Command::new(&exe)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(&input).unwrap();
+        check_process(case, &child.wait_with_output().unwrap())
+    });
+
+    let _ = fs::remove_dir_all(&build);
+}
+
+// What the imports of a translated WebAssembly module work with
+#[derive(Default)]
+struct WasmHost {
+    out: Vec<u8>,
+    err: Vec<u8>,
+    input: Vec<u8>,
+    input_pos: usize,
+}
+
+// Reads a string the module passed as (address, length) out of its memory
+fn wasm_str(caller: &wasmi::Caller<'_, WasmHost>, ptr: i32, len: i32) -> String {
+    let memory = caller
+        .get_export("memory")
+        .and_then(wasmi::Extern::into_memory)
+        .unwrap();
+    let (ptr, len) = (ptr as usize, len as usize);
+    String::from_utf8_lossy(&memory.data(caller)[ptr..ptr + len]).into_owned()
+}
+
+// Runs a module `arsm emit-wat` produced, returning its output and either its
+// exit code or the error it failed with
+fn run_wasm(wat: &str, input: Vec<u8>) -> (String, String, Result<i32, String>) {
+    use wasmi::{Caller, Engine, Error, Linker, Module, Store};
+
+    let engine = Engine::default();
+    let module = Module::new(&engine, &wat::parse_str(wat).unwrap()).unwrap();
+    let mut store = Store::new(
+        &engine,
+        WasmHost {
+            input,
+            ..WasmHost::default()
+        },
+    );
+
+    let mut linker = <Linker<WasmHost>>::new(&engine);
+    linker
+        .func_wrap(
+            "arsm",
+            "out",
+            |mut caller: Caller<'_, WasmHost>, val: i32| {
+                caller.data_mut().out.extend(val.to_string().bytes());
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "arsm",
+            "chr",
+            |mut caller: Caller<'_, WasmHost>, val: i32| {
+                let chr = char::from(val as u8);
+                caller
+                    .data_mut()
+                    .out
+                    .extend(chr.encode_utf8(&mut [0; 4]).bytes());
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "arsm",
+            "fout",
+            |mut caller: Caller<'_, WasmHost>, val: f64, digits: i32| {
+                let digits = digits as usize;
+                caller
+                    .data_mut()
+                    .out
+                    .extend(format!("{val:.digits$}").bytes());
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "arsm",
+            "err",
+            |mut caller: Caller<'_, WasmHost>, val: i32| {
+                caller.data_mut().err.extend(val.to_string().bytes());
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "arsm",
+            "errc",
+            |mut caller: Caller<'_, WasmHost>, val: i32| {
+                let chr = char::from(val as u8);
+                caller
+                    .data_mut()
+                    .err
+                    .extend(chr.encode_utf8(&mut [0; 4]).bytes());
+            },
+        )
+        .unwrap()
+        .func_wrap("arsm", "in", |mut caller: Caller<'_, WasmHost>| {
+            let host = caller.data_mut();
+            match host.input.get(host.input_pos) {
+                Some(&byte) => {
+                    host.input_pos += 1;
+                    i32::from(byte)
+                }
+                None => -1,
+            }
+        })
+        .unwrap()
+        .func_wrap(
+            "arsm",
+            "fail",
+            |caller: Caller<'_, WasmHost>, at: i32, at_len: i32, msg: i32, msg_len: i32| {
+                Err::<(), _>(Error::new(format!(
+                    "{}: {}",
+                    wasm_str(&caller, at, at_len),
+                    wasm_str(&caller, msg, msg_len)
+                )))
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "arsm",
+            "fail_value",
+            |caller: Caller<'_, WasmHost>,
+             at: i32,
+             at_len: i32,
+             msg: i32,
+             msg_len: i32,
+             value: i32| {
+                Err::<(), _>(Error::new(format!(
+                    "{}: {}",
+                    wasm_str(&caller, at, at_len),
+                    wasm_str(&caller, msg, msg_len).replacen("{}", &value.to_string(), 1)
+                )))
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "arsm",
+            "fail_values",
+            |caller: Caller<'_, WasmHost>,
+             at: i32,
+             at_len: i32,
+             msg: i32,
+             msg_len: i32,
+             left: i32,
+             right: i32| {
+                Err::<(), _>(Error::new(format!(
+                    "{}: Assertion failed: {} (left: {left}, right: {right})",
+                    wasm_str(&caller, at, at_len),
+                    wasm_str(&caller, msg, msg_len)
+                )))
+            },
+        )
+        .unwrap();
+
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+    let main = instance.get_typed_func::<(), i32>(&store, "main").unwrap();
+    let res = main.call(&mut store, ()).map_err(|e| e.to_string());
+
+    let host = store.data();
+    (
+        String::from_utf8_lossy(&host.out).into_owned(),
+        String::from_utf8_lossy(&host.err).into_owned(),
+        res,
+    )
+}
+
+// Translates every case to WebAssembly, validates the module and runs it, and
+// checks that it behaves the same
+#[test]
+fn wasm_cases() {
+    check_cases(&cases("std_cases"), |case| {
+        let program = fs::read_to_string(case).unwrap();
+        let Ok(tree) = arsm::compile(&program) else {
+            // Programs that don't build can't be translated either
+            assert!(
+                case.with_extension("err").exists(),
+                "{} doesn't build",
+                case.display()
+            );
+            return None;
+        };
+
+        let input = fs::read(case.with_extension("in")).unwrap_or_default();
+        let (got_out, got_stderr, res) = run_wasm(&arsm::emit_wat::emit(&tree).unwrap(), input);
+
+        let expected_out = read_optional(case, "out").unwrap_or_default();
+        let expected_stderr = read_optional(case, "stderr").unwrap_or_default();
+        let expected_err = read_optional(case, "err").map(|e| e.trim().to_owned());
+        let expected_code = read_optional(case, "code").map_or(0, |c| c.trim().parse().unwrap());
+        let mut failures = Vec::new();
+        if got_out != expected_out {
+            failures.push(format!(
+                "output differs:\n{}",
+                diff(&expected_out, &got_out)
+            ));
+        }
+        if got_stderr != expected_stderr {
+            failures.push(format!(
+                "stderr differs:\n{}",
+                diff(&expected_stderr, &got_stderr)
+            ));
+        }
+        match (res, expected_err) {
+            (Ok(code), None) if code != expected_code => {
+                failures.push(format!("expected exit code {expected_code}, got {code}"));
+            }
+            (Ok(_), Some(e)) => failures.push(format!("expected error {e:?}, but the module ran")),
+            (Err(e), None) => failures.push(format!("unexpected error: {e}")),
+            // Trapped messages start with where the module failed
+            (Err(e), Some(expected)) if !e.ends_with(&format!(": {expected}")) => {
+                failures.push(format!("expected error {expected:?}, got {e:?}"));
+            }
+            _ => {}
+        }
+        (!failures.is_empty()).then(|| failures.join("\n"))
+    });
+}
+
+// Runs `program` with branches compiled once they were entered `threshold`
+// times, returning its output, how it ended, the final memory and how many
+// branches were compiled
+#[cfg(feature = "jit")]
+fn run_jit(program: &str, input: &[u8], threshold: usize) -> (String, String, String, usize) {
+    let out = Capture::default();
+    let mut env = Environment::new();
+    env.stdin = Input::from(input.to_vec());
+    env.out = Box::new(out.clone());
+    // What goes to stderr is checked along with the rest of the output
+    env.err = Box::new(out.clone());
+    env.jit.threshold = threshold;
+
+    let res = arsm::run(program, &mut env);
+    let got_out = out.text();
+    (
+        got_out,
+        format!("{res:?}"),
+        format!("{:?}", env.mem),
+        env.jit.compiled(),
+    )
+}
+
+// Runs every case with each branch compiled as soon as it is entered, and
+// checks that it behaves exactly like the interpreter on its own: the same
+// output, the same result and the same memory once it stops
+#[cfg(feature = "jit")]
+#[test]
+fn jit_cases() {
+    let cases = cases("std_cases");
+
+    let mut compiled = 0;
+    check_cases(&cases, |case| {
+        let program = fs::read_to_string(case).unwrap();
+        let input = fs::read(case.with_extension("in")).unwrap_or_default();
+
+        let (expected_out, expected_res, expected_mem, _) = run_jit(&program, &input, usize::MAX);
+        let (got_out, got_res, got_mem, count) = run_jit(&program, &input, 0);
+        compiled += count;
+
+        let mut failures = Vec::new();
+        if got_out != expected_out {
+            failures.push(format!(
+                "output differs:\n{}",
+                diff(&expected_out, &got_out)
+            ));
+        }
+        if got_res != expected_res {
+            failures.push(format!("expected {expected_res}, got {got_res}"));
+        }
+        if got_mem != expected_mem {
+            failures.push(format!(
+                "memory differs:\n  - {expected_mem}\n  + {got_mem}"
+            ));
+        }
+
+        (!failures.is_empty()).then(|| failures.join("\n"))
+    });
+
+    assert!(compiled > 0, "No branch was compiled");
+}
+
+#[cfg(feature = "inline-python")]
+#[test]
+fn python_cases() {
+    run_dir("python_cases");
+}
@@ -0,0 +1,6 @@
+mov ax 4
+assert_eq ax 4 "ax was just set"
+out ax
+cg 3 ax
+assert "ax is less than 3"
+out 0
@@ -0,0 +1,8 @@
+; Dividing by zero stops the program instead of crashing it
+mov ax 0
+mov bx 7
+div bx 2
+out bx
+chr 10
+div bx ax
+out bx
@@ -0,0 +1,6 @@
+.loop
+    ceq eof 1
+    chl 0
+    out in
+    chr 32
+    jmp :loop
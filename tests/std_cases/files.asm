@@ -0,0 +1,21 @@
+; No directory was allowed, so every file command fails
+str "files.asm" 0
+mov ax fop 0 0
+cjm :read
+str "Could not open files.asm" 10
+prs 0 10
+chr 10
+
+out frd ax 20 4
+cou 1
+out fwr ax 0 4
+cou 1
+out fsk ax 0
+cou 1
+out fcl ax
+cou 1
+hlt 1
+
+.read
+    frd ax 20 100
+    prs 20 0
@@ -0,0 +1,41 @@
+; Averages some measurements, scales them and rounds the result
+fst 0 1.5
+fst 8 2.25
+fst 16 4.75
+mov ax 0
+fmov f0 0
+.sum
+    fld f1 ax
+    fadd f0 f1
+    add ax 8
+    cg 24 ax
+    cjm :sum
+
+fdiv f0 3
+fout f0 4
+chr 10
+
+; Scaled by 2.5 and rounded down
+fmul f0 2.5
+fout f0 2
+chr 32
+ftoi bx f0
+out bx
+chr 10
+
+fmov f7 bx
+fsub f7 f0
+fout f7 3
+chr 32
+flt f7 0
+cou 1
+fge f0 7
+cou 2
+fne f0 f0
+cou 3
+chr 10
+
+; Too big for an integer
+fmov f3 1000000
+fmul f3 f3
+ftoi bx f3
@@ -0,0 +1,54 @@
+; Arguments and locals kept in frames on the stack
+stk 32
+psh 2 7
+psh 2 5
+jmp :area
+; The caller drops the arguments once the call returns
+sub sp 4
+out ax
+chr 32
+out sp
+chr 32
+out fp
+chr 10
+
+enter 4
+sts 2 0 300
+lds bx 0
+out bx
+chr 32
+ldf cx 2
+out cx
+chr 32
+stf 1 0 65
+lds dl 3
+chr dl
+chr 32
+enter 0
+out fp
+chr 32
+leave
+out fp
+chr 32
+leave
+out sp
+chr 10
+
+; Nothing is left on the stack to read
+lds ax 0
+
+.area
+    ; Arguments sit below the saved frame pointer, so their offsets are
+    ; negative
+    enter 2
+    mov bx 0
+    sub bx 6
+    ldf ax bx
+    add bx 2
+    ldf cx bx
+    mul ax cx
+    stf 2 0 ax
+    lds ax 0
+    leave
+    ret
+.
@@ -0,0 +1,3 @@
+out 1
+hlt 3
+out 2
@@ -0,0 +1,31 @@
+; Blocks are taken from the end of the cell memory and reused once freed
+mov ax alloc 4
+out ax
+chr 10
+mov bx alloc 8
+out bx
+chr 10
+mov @[ax] 7
+out @[ax]
+chr 10
+
+; Freed blocks merge with their free neighbours, and are split again
+mov cx alloc 1
+free bx
+free ax
+mov ax alloc 10
+out ax
+chr 10
+out alloc 2
+chr 10
+out #[ax]
+chr 10
+
+; There is no room for a block this big
+out alloc 2000
+cou 0
+chr 10
+
+free cx
+free ax
+free 5
@@ -0,0 +1,26 @@
+; Reads a name, then sums the numbers after it until the input runs out
+rdl 16 16
+str "Hello, " 0
+prs 0 0
+prs 16 0
+
+.sum
+  rdn bx
+  cad eax bx
+  cjm :sum
+.
+
+chr 10
+str "Sum: " '|
+prs 0 '|
+pad eax 8
+chr 10
+hex eax 8
+chr 32
+bin bx 4
+chr 32
+mov cx 0
+dec cx
+dec cx
+hex cx 0
+chr 10
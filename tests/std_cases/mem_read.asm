@@ -0,0 +1,7 @@
+; So are reads, even when only the last bytes fall outside
+mov ax 996
+mov @[ax] 5
+out @[ax]
+chr 10
+add ax 6
+out @[ax]
@@ -0,0 +1,5 @@
+; Writes past the end of the cell memory are caught
+mov #[3] 1
+out #[3]
+chr 10
+mov #[3000] 1
@@ -0,0 +1,7 @@
+; Math that doesn't fit into 32 bits stops the program instead of wrapping
+mov abx 2147483646
+inc abx
+out abx
+chr 10
+inc abx
+out abx
@@ -0,0 +1,48 @@
+; The kind of waste the optimizer removes, in places where it has to be careful
+stk 8
+mov ax 5
+inc bx
+dec bx
+mov cx cx
+psh 2 ax
+pop bx
+out bx
+ceq 1 1
+cmo dx 7
+cou dx
+chr 10
+
+.loop
+    inc cl
+    dec cl
+    add cl 1
+    psh 1 cl
+    pop ch
+    out ch
+    cne 2 2
+    cou 99
+    cg 5 cl
+    cjm :loop
+.
+
+; Falling into this starts at index 10, where `loop` ended
+.after
+    out 1
+    out 2
+    out 3
+    out 4
+    out 5
+    out 6
+    out 7
+    out 8
+    out 9
+    dec ax
+    inc ax
+    out ax
+    cg 2 1
+    cou 8
+    psh 4 123456
+    pop eax
+    out eax
+.
+chr 10
@@ -0,0 +1,28 @@
+; Prints every prime below 60 by trial division, then their sum
+mov ax 2
+.outer
+    mov bx 2
+    jmp :inner
+..inner
+    mov cx bx
+    mul cx bx
+    cg cx ax
+    cjm :prime
+    mov dx ax
+    div dx bx
+    mul dx bx
+    ceq dx ax
+    cjm :next
+    inc bx
+    jmp :inner
+..prime
+    out ax
+    chr 32
+    add @[0] ax
+    jmp :next
+..next
+    inc ax
+    cg 60 ax
+    cjm :outer
+    out @[0]
+    hlt 0
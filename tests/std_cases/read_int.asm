@@ -0,0 +1,7 @@
+; Fails once what comes next in the input is not a number
+.loop
+  rdn ax
+  out ax
+  chr 32
+  jmp :loop
+.
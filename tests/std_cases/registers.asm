@@ -0,0 +1,31 @@
+; The registers past e, the pairs they make, and the stack and frame pointers
+stk 16
+mov fx 300
+mov gh 1
+mov hl 2
+mov ahx 65536
+out fx
+chr 32
+out gx
+chr 32
+out hx
+chr 32
+out ahx
+chr 10
+
+psh 2 fx
+psh 1 gh
+out sp
+chr 32
+mov fp sp
+psh 4 ahx
+out sp
+chr 32
+mov sp fp
+out sp
+chr 32
+pop bl
+out bl
+chr 32
+out fp
+chr 10
@@ -0,0 +1,7 @@
+; Shifting by 32 bits or more (or by a negative amount) is an error
+mov abx 1
+lsh abx 31
+out abx
+chr 10
+mov bx 1
+rsh bx 32
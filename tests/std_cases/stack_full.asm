@@ -0,0 +1,9 @@
+; Pushing onto a full stack fails
+stk 4
+psh 2 5
+psh 1 7
+pop al
+out al
+chr 10
+psh 2 5
+psh 1 7
@@ -0,0 +1,13 @@
+str "Oops" 0
+out 1
+err 2
+errc 10
+chr 10
+.loop
+    ceq db 0 0 eh
+    cjm :done
+    errc #[eh]
+    inc eh
+    jmp :loop
+..done
+    hlt 4
@@ -0,0 +1,7 @@
+; Resizing the stack leaves the registers alone
+mov ax 7
+stk 4
+out ax
+chr 10
+; and the stack can't grow past the end of memory
+stk 5000
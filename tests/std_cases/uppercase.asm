@@ -0,0 +1,13 @@
+; Registers can be written in uppercase, or in any mix of cases
+mov AX 5
+out ax
+chr 32
+mov Abx 70000
+out ABX
+chr 32
+mov hH 3
+out Hh
+chr 32
+fmov F2 1.5
+fout f2 1
+chr 10